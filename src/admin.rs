@@ -0,0 +1,158 @@
+use crate::metrics::{render_value, render_values};
+use crate::utils::http::{full, BoxBody};
+use crate::{GatewayState, GenericError};
+use hyper::body::{Bytes, Incoming};
+use hyper::header::CONTENT_TYPE;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+/// Serves operational endpoints on a listener kept apart from public traffic.
+pub async fn serve_admin(listener: TcpListener, state: Arc<GatewayState>) {
+    loop {
+        let (stream, _) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(err) => {
+                state.logger.err(
+                    "Failed to accept admin connection",
+                    &[("reason", &err.to_string())],
+                );
+                continue;
+            }
+        };
+        let io = TokioIo::new(stream);
+        let state = state.clone();
+
+        tokio::task::spawn(async move {
+            let conn_state = state.clone();
+            let service = service_fn(move |req| handle_admin_request(req, state.clone()));
+            if let Err(err) = http1::Builder::new().serve_connection(io, service).await {
+                conn_state.logger.warn(
+                    "Failed to serve admin connection",
+                    &[("reason", &err.to_string())],
+                );
+            }
+        });
+    }
+}
+
+async fn handle_admin_request(
+    req: Request<Incoming>,
+    state: Arc<GatewayState>,
+) -> Result<Response<BoxBody>, GenericError> {
+    match (req.method(), req.uri().path()) {
+        (&Method::GET, "/metrics") => metrics_response(&state),
+        (&Method::GET, "/limits") => json_response(state.limiter.usage().to_string()),
+        (&Method::GET, "/logger") => json_response(state.logger.usage().to_string()),
+        (&Method::GET, "/logger/entries") => match state.logger.entries() {
+            Some(entries) => json_response(entries.to_string()),
+            None => error_response(StatusCode::NOT_FOUND, "No memory sink".to_string()),
+        },
+        (&Method::PUT, "/logger") | (&Method::DELETE, "/logger") => {
+            let params = query_params(&req);
+            let module = params.get("module").map(String::as_str);
+            let level = match (req.method(), params.get("level")) {
+                (&Method::PUT, Some(level)) => match level.parse() {
+                    Ok(level) => Some(level),
+                    Err(err) => return error_response(StatusCode::BAD_REQUEST, err),
+                },
+                (&Method::PUT, None) => {
+                    return error_response(StatusCode::BAD_REQUEST, "Missing level".to_string())
+                }
+                _ => None,
+            };
+            state.logger.set_level(module, level);
+            json_response(state.logger.levels().to_string())
+        }
+        (&Method::GET, "/cache") => json_response(state.cache.usage().to_string()),
+        (&Method::DELETE, "/cache") => {
+            let params = query_params(&req);
+            let purged = state.cache.purge(
+                params.get("route").map(String::as_str),
+                params.get("key").map(String::as_str),
+            );
+            json_response(json!({ "purged": purged }).to_string())
+        }
+        _ => {
+            let response = Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(full(Bytes::from("Not Found")))
+                .unwrap();
+            Ok(response)
+        }
+    }
+}
+
+fn metrics_response(state: &GatewayState) -> Result<Response<BoxBody>, GenericError> {
+    let mut body = String::new();
+    state.metrics.render(&mut body);
+    render_value(
+        &mut body,
+        "hypergate_in_flight_requests",
+        "gauge",
+        "Requests being proxied, until their response body has been sent.",
+        state.limiter.in_flight() as f64,
+    );
+    render_value(
+        &mut body,
+        "hypergate_open_connections",
+        "gauge",
+        "Client connections open on the public listener.",
+        state.limiter.open_connections() as f64,
+    );
+    let (writer, kafka) = state.logger.dropped();
+    let mut dropped = vec![(&[("stage", "writer")][..], writer as f64)];
+    if let Some(kafka) = kafka {
+        dropped.push((&[("stage", "kafka")][..], kafka as f64));
+    }
+    render_values(
+        &mut body,
+        "hypergate_log_entries_dropped_total",
+        "counter",
+        "Log entries lost to a full queue or buffer, or a failed write.",
+        &dropped,
+    );
+
+    render_value(
+        &mut body,
+        "hypergate_spans_dropped_total",
+        "counter",
+        "Trace spans lost to a full export queue or a failed export.",
+        state.tracer.dropped() as f64,
+    );
+
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+        .body(full(body))
+        .unwrap();
+    Ok(response)
+}
+
+fn query_params<B>(req: &Request<B>) -> HashMap<String, String> {
+    form_urlencoded::parse(req.uri().query().unwrap_or_default().as_bytes())
+        .into_owned()
+        .collect()
+}
+
+fn error_response(status: StatusCode, message: String) -> Result<Response<BoxBody>, GenericError> {
+    let response = Response::builder()
+        .status(status)
+        .body(full(message))
+        .unwrap();
+    Ok(response)
+}
+
+fn json_response(body: String) -> Result<Response<BoxBody>, GenericError> {
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "application/json")
+        .body(full(body))
+        .unwrap();
+    Ok(response)
+}
@@ -0,0 +1,299 @@
+use super::parser::{KafkaCompression, KafkaConfig};
+use kafka::client::Compression;
+use kafka::producer::{Producer, Record, RequiredAcks};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Ships log entries to a Kafka topic from a thread of its own, in batches over one
+/// long-lived producer. Entries sent while the broker is down are spilled to a local
+/// file and replayed, in order, once it is reachable again.
+pub struct KafkaProducer {
+    buffer: Arc<Buffer>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl KafkaProducer {
+    pub fn start(host: &str, topic: &str, config: &KafkaConfig) -> KafkaProducer {
+        let buffer = Arc::new(Buffer {
+            entries: Mutex::new(VecDeque::new()),
+            available: Condvar::new(),
+            capacity: config.buffer_capacity.max(1),
+            closed: AtomicBool::new(false),
+            connected: AtomicBool::new(false),
+            dropped: AtomicU64::new(0),
+            // Entries left over from a previous run are replayed too
+            spilled: AtomicU64::new(config.spill_file.as_deref().map_or(0, |path| {
+                fs::read_to_string(path).map_or(0, |spilled| spilled.lines().count() as u64)
+            })),
+        });
+        let compression = match config.compression {
+            KafkaCompression::None => Compression::NONE,
+            KafkaCompression::Gzip => Compression::GZIP,
+            KafkaCompression::Snappy => Compression::SNAPPY,
+        };
+        let sender = Sender {
+            buffer: buffer.clone(),
+            host: host.to_string(),
+            topic: topic.to_string(),
+            compression,
+            ack_timeout: Duration::from_millis(config.ack_timeout_ms),
+            batch_size: config.batch_size.max(1),
+            linger: Duration::from_millis(config.linger_ms),
+            producer: None,
+            backoff_initial: Duration::from_millis(config.backoff_initial_ms),
+            backoff_max: Duration::from_millis(config.backoff_max_ms),
+            backoff: Duration::from_millis(config.backoff_initial_ms),
+            next_attempt: Instant::now(),
+            spill_file: config.spill_file.clone(),
+            spill_max_bytes: config.spill_max_bytes,
+        };
+        let thread = thread::Builder::new()
+            .name("kafka-producer".to_string())
+            .spawn(move || sender.run())
+            .expect("failed to start the Kafka producer");
+
+        KafkaProducer {
+            buffer,
+            thread: Some(thread),
+        }
+    }
+
+    /// Buffers an entry without waiting, evicting the oldest one when the buffer is full.
+    pub fn send(&self, line: String) {
+        let mut entries = self.buffer.lock();
+        if entries.len() >= self.buffer.capacity {
+            entries.pop_front();
+            self.buffer.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        entries.push_back(line);
+        drop(entries);
+        self.buffer.available.notify_one();
+    }
+
+    /// Entries lost to a full buffer or a full spill file.
+    pub fn dropped(&self) -> u64 {
+        self.buffer.dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn usage(&self) -> Value {
+        json!({
+            "connected": self.buffer.connected.load(Ordering::Relaxed),
+            "buffered": self.buffer.lock().len(),
+            "spilled": self.buffer.spilled.load(Ordering::Relaxed),
+            "dropped": self.dropped(),
+        })
+    }
+}
+
+impl Drop for KafkaProducer {
+    fn drop(&mut self) {
+        self.buffer.closed.store(true, Ordering::Relaxed);
+        self.buffer.available.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+struct Buffer {
+    entries: Mutex<VecDeque<String>>,
+    available: Condvar,
+    capacity: usize,
+    closed: AtomicBool,
+    connected: AtomicBool,
+    dropped: AtomicU64,
+    spilled: AtomicU64,
+}
+
+impl Buffer {
+    fn lock(&self) -> MutexGuard<'_, VecDeque<String>> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The next batch: up to `max` entries, gathered for at most `linger` after the first
+    /// one arrives. An empty batch when nothing arrives within `idle`, `None` once closed
+    /// and drained.
+    fn take(&self, max: usize, linger: Duration, idle: Duration) -> Option<Vec<String>> {
+        let closed = || self.closed.load(Ordering::Relaxed);
+        let mut entries = self.lock();
+        if entries.is_empty() && !closed() {
+            entries = self
+                .available
+                .wait_timeout(entries, idle)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+        if entries.is_empty() {
+            return (!closed()).then(Vec::new);
+        }
+
+        let deadline = Instant::now() + linger;
+        while entries.len() < max && !closed() {
+            let Some(remaining) = deadline.checked_duration_since(Instant::now()) else {
+                break;
+            };
+            entries = self
+                .available
+                .wait_timeout(entries, remaining)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+        let count = entries.len().min(max);
+        Some(entries.drain(..count).collect())
+    }
+}
+
+struct Sender {
+    buffer: Arc<Buffer>,
+    host: String,
+    topic: String,
+    compression: Compression,
+    ack_timeout: Duration,
+    batch_size: usize,
+    linger: Duration,
+    producer: Option<Producer>,
+    backoff_initial: Duration,
+    backoff_max: Duration,
+    backoff: Duration,
+    next_attempt: Instant,
+    spill_file: Option<String>,
+    spill_max_bytes: u64,
+}
+
+impl Sender {
+    fn run(mut self) {
+        // Wake up now and then to replay spilled entries even when nothing is logged
+        let idle = self.backoff_initial.max(Duration::from_secs(1));
+        while let Some(batch) = self.buffer.take(self.batch_size, self.linger, idle) {
+            self.deliver(batch);
+        }
+    }
+
+    fn deliver(&mut self, batch: Vec<String>) {
+        if self.connect() && self.replay() && (batch.is_empty() || self.produce(&batch)) {
+            return;
+        }
+        self.spill(&batch);
+    }
+
+    /// Whether a producer is available, creating one unless still backing off from the
+    /// last failure.
+    fn connect(&mut self) -> bool {
+        if self.producer.is_some() {
+            return true;
+        }
+        if Instant::now() < self.next_attempt {
+            return false;
+        }
+        match Producer::from_hosts(vec![self.host.clone()])
+            .with_ack_timeout(self.ack_timeout)
+            .with_required_acks(RequiredAcks::One)
+            .with_compression(self.compression)
+            .create()
+        {
+            Ok(producer) => {
+                self.producer = Some(producer);
+                self.backoff = self.backoff_initial;
+                self.buffer.connected.store(true, Ordering::Relaxed);
+                true
+            }
+            Err(_) => {
+                self.back_off();
+                false
+            }
+        }
+    }
+
+    fn back_off(&mut self) {
+        self.producer = None;
+        self.buffer.connected.store(false, Ordering::Relaxed);
+        self.next_attempt = Instant::now() + self.backoff;
+        self.backoff = (self.backoff * 2).min(self.backoff_max);
+    }
+
+    fn produce(&mut self, lines: &[String]) -> bool {
+        let Some(producer) = &mut self.producer else {
+            return false;
+        };
+        let records: Vec<_> = lines
+            .iter()
+            .map(|line| Record::from_value(&self.topic, line.as_bytes()))
+            .collect();
+        let delivered = producer.send_all(&records).is_ok_and(|confirms| {
+            confirms
+                .iter()
+                .flat_map(|confirm| &confirm.partition_confirms)
+                .all(|partition| partition.offset.is_ok())
+        });
+        if !delivered {
+            self.back_off();
+        }
+        delivered
+    }
+
+    /// Sends the spilled entries, keeping those not yet sent. Whether the spill is empty.
+    fn replay(&mut self) -> bool {
+        let Some(path) = self.spill_file.clone() else {
+            return true;
+        };
+        if self.buffer.spilled.load(Ordering::Relaxed) == 0 {
+            return true;
+        }
+        let Ok(spilled) = fs::read_to_string(&path) else {
+            return true;
+        };
+        let lines: Vec<String> = spilled.lines().map(str::to_string).collect();
+        let mut sent = 0;
+        while sent < lines.len() {
+            let end = (sent + self.batch_size).min(lines.len());
+            if !self.produce(&lines[sent..end]) {
+                break;
+            }
+            sent = end;
+        }
+
+        let rest = &lines[sent..];
+        let result = match rest.is_empty() {
+            true => fs::remove_file(&path),
+            false => fs::write(&path, joined(rest)),
+        };
+        if result.is_err() {
+            self.buffer
+                .dropped
+                .fetch_add(rest.len() as u64, Ordering::Relaxed);
+        }
+        self.buffer
+            .spilled
+            .store(rest.len() as u64, Ordering::Relaxed);
+        rest.is_empty()
+    }
+
+    fn spill(&mut self, lines: &[String]) {
+        let size = |path: &str| fs::metadata(path).map_or(0, |metadata| metadata.len());
+        let written = self.spill_file.as_deref().is_some_and(|path| {
+            let bytes: u64 = lines.iter().map(|line| line.len() as u64 + 1).sum();
+            size(path) + bytes <= self.spill_max_bytes
+                && OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .and_then(|mut file| file.write_all(joined(lines).as_bytes()))
+                    .is_ok()
+        });
+        let counter = match written {
+            true => &self.buffer.spilled,
+            false => &self.buffer.dropped,
+        };
+        counter.fetch_add(lines.len() as u64, Ordering::Relaxed);
+    }
+}
+
+fn joined(lines: &[String]) -> String {
+    lines.iter().map(|line| format!("{}\n", line)).collect()
+}
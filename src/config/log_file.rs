@@ -0,0 +1,192 @@
+use super::parser::{RotationConfig, RotationInterval};
+use chrono::{DateTime, Utc};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::thread::{self, JoinHandle};
+
+/// A log file opened on first use and reopened after a failed write. Only the log writer
+/// thread writes to it, so rotation always falls between two whole lines.
+pub struct LogFile {
+    path: String,
+    rotation: RotationConfig,
+    file: Option<BufWriter<File>>,
+    size: u64,
+    period: Option<String>,
+    compressing: Option<JoinHandle<()>>,
+    failing: bool,
+}
+
+impl LogFile {
+    pub fn new(path: &str, rotation: &RotationConfig) -> LogFile {
+        LogFile {
+            path: path.to_string(),
+            rotation: rotation.clone(),
+            file: None,
+            size: 0,
+            period: None,
+            compressing: None,
+            failing: false,
+        }
+    }
+
+    pub fn write(&mut self, line: &str) -> bool {
+        let len = line.len() as u64 + 1;
+        if self.file.is_none() && !self.open() {
+            return false;
+        }
+        if self.rotation_due(len) {
+            self.rotate();
+            if !self.open() {
+                return false;
+            }
+        }
+
+        let file = self.file.as_mut().unwrap();
+        match writeln!(file, "{}", line) {
+            Ok(()) => {
+                self.size += len;
+                self.failing = false;
+                true
+            }
+            Err(err) => {
+                self.file = None;
+                self.report(err);
+                false
+            }
+        }
+    }
+
+    pub fn flush(&mut self) {
+        if let Some(Err(err)) = self.file.as_mut().map(Write::flush) {
+            self.file = None;
+            self.report(err);
+        }
+    }
+
+    /// Closes the file so the next line reopens it, for when it was moved away by an
+    /// external rotation.
+    pub fn reopen(&mut self) {
+        self.flush();
+        self.file = None;
+    }
+
+    fn open(&mut self) -> bool {
+        let opened = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|file| Ok((file.metadata()?, file)));
+        match opened {
+            Ok((metadata, file)) => {
+                self.size = metadata.len();
+                // A file left by an earlier run belongs to the period it was last written in
+                self.period = metadata
+                    .modified()
+                    .ok()
+                    .and_then(|modified| self.period_of(modified.into()));
+                self.file = Some(BufWriter::new(file));
+                true
+            }
+            Err(err) => {
+                self.report(err);
+                false
+            }
+        }
+    }
+
+    fn period_of(&self, time: DateTime<Utc>) -> Option<String> {
+        let format = match self.rotation.interval? {
+            RotationInterval::Hourly => "%Y-%m-%dT%H",
+            RotationInterval::Daily => "%Y-%m-%d",
+        };
+        Some(time.format(format).to_string())
+    }
+
+    fn rotation_due(&self, len: u64) -> bool {
+        let too_large = self
+            .rotation
+            .max_bytes
+            .is_some_and(|max_bytes| self.size > 0 && self.size + len > max_bytes);
+        let period = self.period_of(Utc::now());
+        too_large || (self.size > 0 && period.is_some() && period != self.period)
+    }
+
+    /// Moves the current file to `.1`, shifting older ones up and deleting the oldest,
+    /// and compresses it in the background when configured.
+    fn rotate(&mut self) {
+        self.reopen();
+        if let Some(compressing) = self.compressing.take() {
+            let _ = compressing.join();
+        }
+
+        let max_files = self.rotation.max_files;
+        let result = (|| {
+            if let Some(oldest) = self.rotated(max_files.max(1)) {
+                fs::remove_file(oldest)?;
+            }
+            for index in (1..max_files).rev() {
+                if let Some(path) = self.rotated(index) {
+                    let extension = if path.ends_with(".gz") { ".gz" } else { "" };
+                    fs::rename(&path, format!("{}.{}{}", self.path, index + 1, extension))?;
+                }
+            }
+            match max_files {
+                0 => fs::remove_file(&self.path),
+                _ => fs::rename(&self.path, format!("{}.1", self.path)),
+            }
+        })();
+        if let Err(err) = result {
+            self.report(err);
+            return;
+        }
+
+        if self.rotation.compress && max_files > 0 {
+            let path = format!("{}.1", self.path);
+            self.compressing = Some(thread::spawn(move || {
+                if let Err(err) = compress(&path) {
+                    eprintln!("Failed to compress log file {}: {}", path, err);
+                }
+            }));
+        }
+    }
+
+    /// The rotated file at `index`, compressed or not, if there is one.
+    fn rotated(&self, index: usize) -> Option<String> {
+        let path = format!("{}.{}", self.path, index);
+        let compressed = format!("{}.gz", path);
+        [path, compressed]
+            .into_iter()
+            .find(|path| Path::new(path).exists())
+    }
+
+    /// Reports the first of a run of failures, the log itself being unavailable.
+    fn report(&mut self, err: io::Error) {
+        if !self.failing {
+            self.failing = true;
+            eprintln!("Failed to write log file {}: {}", self.path, err);
+        }
+    }
+}
+
+impl Drop for LogFile {
+    fn drop(&mut self) {
+        self.flush();
+        if let Some(compressing) = self.compressing.take() {
+            let _ = compressing.join();
+        }
+    }
+}
+
+fn compress(path: &str) -> io::Result<()> {
+    let mut source = File::open(path)?;
+    let mut encoder = GzEncoder::new(
+        File::create(format!("{}.gz", path))?,
+        Compression::default(),
+    );
+    io::copy(&mut source, &mut encoder)?;
+    encoder.finish()?;
+    fs::remove_file(path)
+}
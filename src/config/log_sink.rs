@@ -0,0 +1,183 @@
+use super::kafka_producer::KafkaProducer;
+use super::log_file::LogFile;
+use super::parser::{Level, LoggerConfig};
+use std::collections::VecDeque;
+use std::fs::create_dir_all;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// A destination for log entries. Sinks are only ever called from the log writer thread,
+/// with entries already rendered to a JSON line.
+pub trait LogSink: Send {
+    /// The least severe entries this sink takes.
+    fn level(&self) -> Level;
+
+    /// Writes one entry, returning whether it was written.
+    fn write(&mut self, level: Level, line: &str) -> bool;
+
+    /// Writes one access log entry, which may not be JSON.
+    fn write_access(&mut self, line: &str) -> bool {
+        self.write(Level::Info, line)
+    }
+
+    fn flush(&mut self) {}
+
+    /// Reopens whatever the sink writes to, after an external log rotation.
+    fn reopen(&mut self) {}
+}
+
+/// Writes errors to `err_file`, debug and trace entries to `debug_file`, the access log
+/// to `access_file` when set, and everything else to `out_file`.
+pub struct FileSink {
+    level: Level,
+    out: LogFile,
+    err: LogFile,
+    debug: LogFile,
+    access: Option<LogFile>,
+}
+
+impl FileSink {
+    pub fn from_config(config: &LoggerConfig) -> FileSink {
+        // Create dir if it doesn't exist
+        let files = [&config.out_file, &config.err_file, &config.debug_file];
+        for file in files.into_iter().chain(&config.access_file) {
+            if let Some(parent) = Path::new(file).parent() {
+                create_dir_all(parent).unwrap();
+            }
+        }
+
+        FileSink {
+            level: config.sink_levels.file.unwrap_or(Level::Trace),
+            out: LogFile::new(&config.out_file, &config.rotation),
+            err: LogFile::new(&config.err_file, &config.rotation),
+            debug: LogFile::new(&config.debug_file, &config.rotation),
+            access: config
+                .access_file
+                .as_ref()
+                .map(|file| LogFile::new(file, &config.rotation)),
+        }
+    }
+}
+
+impl LogSink for FileSink {
+    fn level(&self) -> Level {
+        self.level
+    }
+
+    fn write(&mut self, level: Level, line: &str) -> bool {
+        let file = match level {
+            Level::Trace | Level::Debug => &mut self.debug,
+            Level::Info | Level::Warn => &mut self.out,
+            Level::Err => &mut self.err,
+        };
+        file.write(line)
+    }
+
+    fn write_access(&mut self, line: &str) -> bool {
+        self.access.as_mut().unwrap_or(&mut self.out).write(line)
+    }
+
+    fn flush(&mut self) {
+        self.out.flush();
+        self.err.flush();
+        self.debug.flush();
+        self.access.iter_mut().for_each(LogFile::flush);
+    }
+
+    fn reopen(&mut self) {
+        self.out.reopen();
+        self.err.reopen();
+        self.debug.reopen();
+        self.access.iter_mut().for_each(LogFile::reopen);
+    }
+}
+
+/// Hands entries to the Kafka producer, which batches them on a thread of its own.
+pub struct KafkaSink {
+    level: Level,
+    producer: Arc<KafkaProducer>,
+}
+
+impl KafkaSink {
+    pub fn new(level: Level, producer: Arc<KafkaProducer>) -> KafkaSink {
+        KafkaSink { level, producer }
+    }
+}
+
+impl LogSink for KafkaSink {
+    fn level(&self) -> Level {
+        self.level
+    }
+
+    fn write(&mut self, _level: Level, line: &str) -> bool {
+        self.producer.send(line.to_string());
+        true
+    }
+}
+
+/// Writes errors to stderr and everything else to stdout, for the log collectors of
+/// container platforms.
+pub struct StdoutSink {
+    level: Level,
+}
+
+impl StdoutSink {
+    pub fn new(level: Level) -> StdoutSink {
+        StdoutSink { level }
+    }
+}
+
+impl LogSink for StdoutSink {
+    fn level(&self) -> Level {
+        self.level
+    }
+
+    fn write(&mut self, level: Level, line: &str) -> bool {
+        let written = match level {
+            Level::Err => writeln!(io::stderr().lock(), "{}", line),
+            _ => writeln!(io::stdout().lock(), "{}", line),
+        };
+        written.is_ok()
+    }
+
+    fn flush(&mut self) {
+        let _ = io::stdout().flush();
+    }
+}
+
+/// The latest entries, kept in memory.
+pub type MemoryEntries = Arc<Mutex<VecDeque<String>>>;
+
+/// Keeps the latest `capacity` entries in memory, for inspection without access to the
+/// files.
+pub struct MemorySink {
+    level: Level,
+    capacity: usize,
+    entries: MemoryEntries,
+}
+
+impl MemorySink {
+    pub fn new(level: Level, capacity: usize, entries: MemoryEntries) -> MemorySink {
+        MemorySink {
+            level,
+            capacity,
+            entries,
+        }
+    }
+}
+
+impl LogSink for MemorySink {
+    fn level(&self) -> Level {
+        self.level
+    }
+
+    fn write(&mut self, _level: Level, line: &str) -> bool {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        if entries.len() >= self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(line.to_string());
+        true
+    }
+}
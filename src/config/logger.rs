@@ -1,99 +1,268 @@
-use super::parser::LoggerConfig;
+use super::kafka_producer::KafkaProducer;
+use super::log_sink::{FileSink, KafkaSink, LogSink, MemoryEntries, MemorySink, StdoutSink};
+use super::parser::{Level, LoggerConfig, OverflowPolicy, SinkKind};
+use super::redaction::Redactor;
 use chrono::{SecondsFormat, Utc};
-use kafka::producer::{Producer, Record, RequiredAcks};
-use serde_json::json;
-use std::collections::HashMap;
-use std::fs::{create_dir_all, OpenOptions};
-use std::io::Write;
-use std::path::Path;
-use std::time::Duration;
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::panic::Location;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tokio::runtime::{Handle, RuntimeFlavor};
 
+/// Queues log entries for a dedicated writer thread, so logging never does I/O on the
+/// request path. Dropping the logger writes out whatever is still queued.
 pub struct Logger {
-    use_kafka: bool,
-    kafka_host: Option<String>,
-    kafka_topic: String,
-    out_file: String,
-    err_file: String,
+    queue: Arc<Queue>,
+    writer: Option<JoinHandle<()>>,
+    kafka: Option<Arc<KafkaProducer>>,
+    memory: Option<MemoryEntries>,
+    levels: RwLock<Levels>,
+    redactor: Redactor,
+}
+
+/// The least severe entries logged, overall and for the modules given their own level.
+struct Levels {
+    level: Level,
+    modules: HashMap<String, Level>,
+}
+
+impl Levels {
+    /// The level for the module that logged from `file`. Overrides apply to a module and
+    /// the modules under it, the most specific one winning.
+    fn for_file(&self, file: &str) -> Level {
+        if self.modules.is_empty() {
+            return self.level;
+        }
+        let module = module_path(file);
+        self.modules
+            .iter()
+            .filter(|(name, _)| {
+                module == **name
+                    || module
+                        .strip_prefix(name.as_str())
+                        .is_some_and(|rest| rest.starts_with("::"))
+            })
+            .max_by_key(|(name, _)| name.len())
+            .map_or(self.level, |(_, level)| *level)
+    }
+}
+
+/// The module path of a source file, `src/proxy/upgrade.rs` being `hypergate::proxy::upgrade`.
+fn module_path(file: &str) -> String {
+    let path = file.strip_prefix("src/").unwrap_or(file);
+    let path = path.strip_suffix(".rs").unwrap_or(path);
+    let path = path.strip_suffix("/mod").unwrap_or(path);
+    match path {
+        "main" => "hypergate".to_string(),
+        _ => format!("hypergate::{}", path.replace('/', "::")),
+    }
 }
 
 impl Logger {
     pub fn from_config(config: &LoggerConfig) -> Logger {
-        // Create dir if it doesn't exist
-        if !Path::new(&config.out_file).exists() {
-            create_dir_all(Path::new(&config.out_file).parent().unwrap()).unwrap();
+        let queue = Arc::new(Queue {
+            entries: Mutex::new(VecDeque::new()),
+            available: Condvar::new(),
+            space: Condvar::new(),
+            capacity: config.queue.capacity.max(1),
+            overflow: config.queue.overflow,
+            dropped: AtomicU64::new(0),
+            closed: AtomicBool::new(false),
+            reopen: AtomicBool::new(false),
+        });
+        let kafka = match (config.use_kafka, &config.kafka_host) {
+            (true, Some(host)) => Some(Arc::new(KafkaProducer::start(
+                host,
+                config.kafka_topic.as_deref().unwrap_or_default(),
+                &config.kafka,
+            ))),
+            _ => None,
+        };
+        let levels = &config.sink_levels;
+        let mut memory = None;
+        let mut sinks: Vec<Box<dyn LogSink>> = Vec::new();
+        for kind in &config.sinks {
+            match kind {
+                SinkKind::File => sinks.push(Box::new(FileSink::from_config(config))),
+                SinkKind::Stdout => sinks.push(Box::new(StdoutSink::new(
+                    levels.stdout.unwrap_or(Level::Trace),
+                ))),
+                SinkKind::Memory => {
+                    let entries = memory.get_or_insert_with(MemoryEntries::default).clone();
+                    sinks.push(Box::new(MemorySink::new(
+                        levels.memory.unwrap_or(Level::Trace),
+                        config.memory_capacity,
+                        entries,
+                    )));
+                }
+            }
         }
-        if !Path::new(&config.err_file).exists() {
-            create_dir_all(Path::new(&config.err_file).parent().unwrap()).unwrap();
+        if let Some(producer) = &kafka {
+            sinks.push(Box::new(KafkaSink::new(
+                levels.kafka.unwrap_or(Level::Trace),
+                producer.clone(),
+            )));
         }
 
-        if !config.use_kafka {
-            Logger {
-                use_kafka: false,
-                kafka_host: None,
-                kafka_topic: "".to_string(),
-                out_file: config.out_file.clone(),
-                err_file: config.err_file.clone(),
-            }
-        } else {
-            Logger {
-                use_kafka: true,
-                kafka_host: config.kafka_host.clone(),
-                kafka_topic: config.kafka_topic.clone().unwrap_or("".to_string()),
-                out_file: config.out_file.clone(),
-                err_file: config.err_file.clone(),
-            }
+        let writer = Writer {
+            queue: queue.clone(),
+            sinks,
+            batch_size: config.queue.batch_size.max(1),
+            flush_interval: Duration::from_millis(config.queue.flush_interval_ms),
+        };
+        let writer = thread::Builder::new()
+            .name("log-writer".to_string())
+            .spawn(move || writer.run())
+            .expect("failed to start the log writer");
+
+        Logger {
+            queue,
+            writer: Some(writer),
+            kafka,
+            memory,
+            levels: RwLock::new(Levels {
+                level: config.level,
+                modules: config.modules.clone(),
+            }),
+            redactor: Redactor::from_config(&config.redaction),
         }
     }
 
+    #[track_caller]
+    pub fn trace(&self, message: &str, params: &[(&str, &str)]) {
+        self.log(Level::Trace, message, params);
+    }
+
+    #[track_caller]
+    pub fn debug(&self, message: &str, params: &[(&str, &str)]) {
+        self.log(Level::Debug, message, params);
+    }
+
+    #[track_caller]
     pub fn info(&self, message: &str, params: &[(&str, &str)]) {
-        let log_entry = self.build_log_entry("info", message, params);
+        self.log(Level::Info, message, params);
+    }
 
-        let mut file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&self.out_file)
-            .unwrap();
+    #[track_caller]
+    pub fn warn(&self, message: &str, params: &[(&str, &str)]) {
+        self.log(Level::Warn, message, params);
+    }
 
-        Write::write_fmt(&mut file, format_args!("{}\n", log_entry)).unwrap();
+    #[track_caller]
+    pub fn err(&self, message: &str, params: &[(&str, &str)]) {
+        self.log(Level::Err, message, params);
+    }
 
-        if self.use_kafka {
-            self.send_to_kafka(&log_entry);
+    /// Changes the level at runtime, for one module and those under it when `module` is
+    /// given. `None` as the level drops a module's override.
+    pub fn set_level(&self, module: Option<&str>, level: Option<Level>) {
+        let mut levels = self.levels.write().unwrap_or_else(PoisonError::into_inner);
+        match (module, level) {
+            (None, Some(level)) => levels.level = level,
+            (None, None) => {}
+            (Some(module), Some(level)) => {
+                levels.modules.insert(module.to_string(), level);
+            }
+            (Some(module), None) => {
+                levels.modules.remove(module);
+            }
         }
     }
 
-    pub fn warn(&self, message: &str, params: &[(&str, &str)]) {
-        let log_entry = self.build_log_entry("warn", message, params);
+    pub fn levels(&self) -> Value {
+        let levels = self.levels.read().unwrap_or_else(PoisonError::into_inner);
+        json!({ "level": levels.level, "modules": levels.modules })
+    }
 
-        let mut file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&self.out_file)
-            .unwrap();
+    /// Has the writer reopen the log files before its next batch, once an external tool
+    /// has moved them away.
+    pub fn reopen(&self) {
+        self.queue.reopen.store(true, Ordering::Relaxed);
+        self.queue.available.notify_all();
+    }
 
-        Write::write_fmt(&mut file, format_args!("{}\n", log_entry)).unwrap();
+    /// Entries lost to a full queue or a failed write, and those the Kafka producer lost
+    /// when there is one.
+    pub fn dropped(&self) -> (u64, Option<u64>) {
+        (
+            self.queue.dropped.load(Ordering::Relaxed),
+            self.kafka.as_ref().map(|kafka| kafka.dropped()),
+        )
+    }
 
-        if self.use_kafka {
-            self.send_to_kafka(&log_entry);
-        }
+    /// Entries waiting to be written, entries lost to a full queue or a failed write, and
+    /// the levels in effect.
+    pub fn usage(&self) -> Value {
+        json!({
+            "levels": self.levels(),
+            "queued": self.queue.lock().len(),
+            "capacity": self.queue.capacity,
+            "dropped": self.queue.dropped.load(Ordering::Relaxed),
+            "kafka": self.kafka.as_ref().map(|kafka| kafka.usage()),
+        })
     }
 
-    pub fn err(&self, message: &str, params: &[(&str, &str)]) {
-        let log_entry = self.build_log_entry("err", message, params);
+    /// The entries kept by the memory sink, oldest first, `None` when there is none.
+    pub fn entries(&self) -> Option<Value> {
+        let entries = self
+            .memory
+            .as_ref()?
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let entries: Vec<Value> = entries
+            .iter()
+            .map(|line| serde_json::from_str(line).unwrap_or_else(|_| json!(line)))
+            .collect();
+        Some(json!(entries))
+    }
+
+    /// Redacts params the way every entry is, for those logged through `access` or
+    /// `access_line`.
+    pub fn redact<'a>(&self, params: &[(&'a str, &str)]) -> Vec<(&'a str, String)> {
+        self.redactor.params(params)
+    }
 
-        let mut file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&self.err_file)
-            .unwrap();
+    /// Logs a request in the JSON shape of every other entry, whatever the level. Its
+    /// params are expected to be redacted already.
+    pub fn access(&self, params: &[(&str, String)]) {
+        let line = self.build_log_entry(Level::Info.as_str(), "access", params);
+        self.access_line(line);
+    }
 
-        Write::write_fmt(&mut file, format_args!("{}\n", log_entry)).unwrap();
+    /// Logs a request already rendered, and redacted, in another format such as Common
+    /// Log Format.
+    pub fn access_line(&self, line: String) {
+        self.queue.push(Entry {
+            level: Level::Info,
+            access: true,
+            line,
+        });
+    }
 
-        if self.use_kafka {
-            self.send_to_kafka(&log_entry);
+    #[track_caller]
+    fn log(&self, level: Level, message: &str, params: &[(&str, &str)]) {
+        let enabled = self
+            .levels
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .for_file(Location::caller().file());
+        if level < enabled {
+            return;
         }
+        let message = self.redactor.text(message);
+        let params = self.redactor.params(params);
+        let line = self.build_log_entry(level.as_str(), &message, &params);
+        self.queue.push(Entry {
+            level,
+            access: false,
+            line,
+        });
     }
 
-    fn build_log_entry(&self, level: &str, message: &str, params: &[(&str, &str)]) -> String {
+    fn build_log_entry(&self, level: &str, message: &str, params: &[(&str, String)]) -> String {
         let timestamp = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
 
         let mut log_obj = json!({
@@ -103,32 +272,155 @@ impl Logger {
             "message": message,
         });
 
+        if let Some(trace) = crate::trace::current() {
+            log_obj["trace_id"] = json!(trace.trace_id());
+            log_obj["span_id"] = json!(trace.span_id());
+        }
+
         let additional_params: HashMap<_, _> = params.iter().cloned().collect();
 
         if !additional_params.is_empty() {
             log_obj["params"] = json!(additional_params);
         }
 
-        serde_json::to_string(&log_obj).unwrap()
+        log_obj.to_string()
     }
+}
 
-    fn send_to_kafka(&self, message: &str) {
-        let kafka_host = match &self.kafka_host {
-            Some(host) => host,
-            None => return,
-        };
-        let mut producer = match Producer::from_hosts(vec![kafka_host.to_owned()])
-            .with_ack_timeout(Duration::from_secs(1))
-            .with_required_acks(RequiredAcks::One)
-            .create()
-        {
-            Ok(producer) => Some(producer),
-            Err(_) => None,
+impl Drop for Logger {
+    fn drop(&mut self) {
+        self.queue.closed.store(true, Ordering::Relaxed);
+        self.queue.available.notify_all();
+        self.queue.space.notify_all();
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+struct Entry {
+    level: Level,
+    access: bool,
+    line: String,
+}
+
+/// Entries waiting for the writer. Callers hold the lock only long enough to push.
+struct Queue {
+    entries: Mutex<VecDeque<Entry>>,
+    available: Condvar,
+    space: Condvar,
+    capacity: usize,
+    overflow: OverflowPolicy,
+    dropped: AtomicU64,
+    closed: AtomicBool,
+    reopen: AtomicBool,
+}
+
+impl Queue {
+    fn lock(&self) -> MutexGuard<'_, VecDeque<Entry>> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn push(&self, entry: Entry) {
+        let mut entries = self.lock();
+        while entries.len() >= self.capacity {
+            match self.overflow {
+                OverflowPolicy::DropNewest => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+                OverflowPolicy::DropOldest => {
+                    entries.pop_front();
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                OverflowPolicy::Block => {
+                    if self.closed.load(Ordering::Relaxed) {
+                        self.dropped.fetch_add(1, Ordering::Relaxed);
+                        return;
+                    }
+                    entries = self.wait_for_space(entries);
+                }
+            }
+        }
+        entries.push_back(entry);
+        drop(entries);
+        self.available.notify_one();
+    }
+
+    /// Blocks until the writer takes entries. Request handlers log from async tasks, so
+    /// a runtime worker first hands its other tasks to another thread.
+    fn wait_for_space<'a>(
+        &self,
+        entries: MutexGuard<'a, VecDeque<Entry>>,
+    ) -> MutexGuard<'a, VecDeque<Entry>> {
+        let wait = || {
+            self.space
+                .wait(entries)
+                .unwrap_or_else(PoisonError::into_inner)
         };
-        if let Some(producer) = &mut producer {
-            producer
-                .send(&Record::from_value(&self.kafka_topic, message.as_bytes()))
-                .unwrap();
+        match Handle::try_current() {
+            Ok(runtime) if runtime.runtime_flavor() == RuntimeFlavor::MultiThread => {
+                tokio::task::block_in_place(wait)
+            }
+            _ => wait(),
+        }
+    }
+
+    /// Up to `max` entries, waiting at most `timeout` for the first one. `None` once the
+    /// logger is closed and everything has been handed out.
+    fn take(&self, max: usize, timeout: Duration) -> Option<Vec<Entry>> {
+        let mut entries = self.lock();
+        if entries.is_empty() && !self.closed.load(Ordering::Relaxed) {
+            entries = self
+                .available
+                .wait_timeout(entries, timeout)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+        if entries.is_empty() && self.closed.load(Ordering::Relaxed) {
+            return None;
+        }
+        let count = entries.len().min(max);
+        let batch = entries.drain(..count).collect();
+        drop(entries);
+        self.space.notify_all();
+        Some(batch)
+    }
+}
+
+/// Drains the queue in batches into the sinks, flushing them at most once per interval.
+struct Writer {
+    queue: Arc<Queue>,
+    sinks: Vec<Box<dyn LogSink>>,
+    batch_size: usize,
+    flush_interval: Duration,
+}
+
+impl Writer {
+    fn run(mut self) {
+        let mut last_flush = Instant::now();
+        while let Some(batch) = self.queue.take(self.batch_size, self.flush_interval) {
+            if self.queue.reopen.swap(false, Ordering::Relaxed) {
+                self.sinks.iter_mut().for_each(|sink| sink.reopen());
+            }
+            for entry in batch {
+                for sink in &mut self.sinks {
+                    if entry.level < sink.level() {
+                        continue;
+                    }
+                    let written = match entry.access {
+                        true => sink.write_access(&entry.line),
+                        false => sink.write(entry.level, &entry.line),
+                    };
+                    if !written {
+                        self.queue.dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }
+            if last_flush.elapsed() >= self.flush_interval {
+                self.sinks.iter_mut().for_each(|sink| sink.flush());
+                last_flush = Instant::now();
+            }
         }
     }
 }
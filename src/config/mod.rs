@@ -1,3 +1,7 @@
+pub mod kafka_producer;
+pub mod log_file;
+pub mod log_sink;
 pub mod logger;
-pub mod parser;
 pub mod openapi;
+pub mod parser;
+pub mod redaction;
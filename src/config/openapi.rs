@@ -30,10 +30,7 @@ impl OpenApiMerger {
             .filter_map(|e| e.ok())
         {
             let path = entry.path();
-            if path
-                .extension()
-                .map_or(false, |e| e == "yaml" || e == "yml")
-            {
+            if path.extension().is_some_and(|e| e == "yaml" || e == "yml") {
                 let content = fs::read_to_string(path)?;
                 let spec: OpenAPI = serde_yaml::from_str(&content)?;
                 let service_name = path.file_stem().unwrap().to_str().unwrap().to_string();
@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
 use serde_yaml;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::Read;
 
@@ -8,6 +9,179 @@ pub struct ServiceConfig {
     pub path: String,
     pub target_service: String,
     pub target_port: String,
+    pub max_in_flight: Option<usize>,
+    pub ip_filter: Option<IpFilterConfig>,
+    pub max_body_bytes: Option<usize>,
+    pub cors: Option<CorsConfig>,
+    pub security_headers: Option<SecurityHeadersConfig>,
+    pub route: Option<String>,
+    pub rewrite: Option<RewriteConfig>,
+    pub headers: Option<HeaderRulesConfig>,
+    pub host_header: Option<String>,
+    pub upgrade: Option<UpgradeConfig>,
+    pub streaming: Option<StreamingConfig>,
+    pub grpc: Option<GrpcConfig>,
+    pub cache: Option<RouteCacheConfig>,
+    pub coalesce: Option<CoalesceConfig>,
+    pub compression: Option<CompressionConfig>,
+    pub decompress: Option<DecompressConfig>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct CompressionConfig {
+    pub enabled: bool,
+    pub algorithms: Vec<String>,
+    pub min_bytes: u16,
+    pub content_types: Vec<String>,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        CompressionConfig {
+            enabled: true,
+            algorithms: vec!["gzip".to_string(), "br".to_string(), "zstd".to_string()],
+            min_bytes: 1024,
+            content_types: vec![
+                "text/".to_string(),
+                "application/json".to_string(),
+                "application/javascript".to_string(),
+                "application/xml".to_string(),
+                "application/yaml".to_string(),
+                "image/svg+xml".to_string(),
+            ],
+        }
+    }
+}
+
+/// Decompresses gzip request bodies for the backend. `max_bytes` caps the decompressed
+/// size, the route's body limit when unset.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct DecompressConfig {
+    pub max_bytes: Option<usize>,
+}
+
+/// Opts a route into sharing one backend call between identical concurrent GETs. Requests
+/// only match when their `key_headers` match too, the caller's cookies by default.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct CoalesceConfig {
+    pub key_headers: Vec<String>,
+    pub max_wait_ms: u64,
+    pub max_body_bytes: usize,
+}
+
+impl Default for CoalesceConfig {
+    fn default() -> Self {
+        CoalesceConfig {
+            key_headers: vec!["cookie".to_string(), "authorization".to_string()],
+            max_wait_ms: 5000,
+            max_body_bytes: 1024 * 1024,
+        }
+    }
+}
+
+/// Opts a route into response caching. `ttl_secs` overrides the freshness the backend
+/// gives, `default_ttl_secs` only applies when it gives none. The cache key includes the
+/// `key_claims` of the caller's auth identity, or a hash of the request's credentials
+/// when unset.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct RouteCacheConfig {
+    pub ttl_secs: Option<u64>,
+    pub default_ttl_secs: Option<u64>,
+    pub key_claims: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct CacheConfig {
+    pub max_bytes: usize,
+    pub max_entry_bytes: usize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            max_bytes: 64 * 1024 * 1024,
+            max_entry_bytes: 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct GrpcConfig {
+    pub web: bool,
+}
+
+impl Default for GrpcConfig {
+    fn default() -> Self {
+        GrpcConfig { web: true }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct StreamingConfig {
+    pub idle_timeout_secs: u64,
+}
+
+impl Default for StreamingConfig {
+    fn default() -> Self {
+        StreamingConfig {
+            idle_timeout_secs: 60,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct UpgradeConfig {
+    pub idle_timeout_secs: u64,
+}
+
+impl Default for UpgradeConfig {
+    fn default() -> Self {
+        UpgradeConfig {
+            idle_timeout_secs: 300,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct HeaderRulesConfig {
+    pub request: Vec<HeaderRuleConfig>,
+    pub response: Vec<HeaderRuleConfig>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct HeaderRuleConfig {
+    pub action: String,
+    pub name: String,
+    pub value: Option<String>,
+    pub to: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct RewriteConfig {
+    pub strip_prefix: Option<String>,
+    pub add_prefix: Option<String>,
+    pub regex: Option<String>,
+    pub replacement: Option<String>,
+    pub template: Option<String>,
+    pub query: QueryRewriteConfig,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct QueryRewriteConfig {
+    pub add: HashMap<String, String>,
+    pub remove: Vec<String>,
+    pub rename: HashMap<String, String>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -24,6 +198,96 @@ pub struct GatewayConfig {
     pub services: Vec<ServiceConfig>,
     pub endpoints_without_auth: Vec<NoAuthEndpoints>,
     pub logger_config: LoggerConfig,
+    pub admin_url: Option<String>,
+    #[serde(default)]
+    pub limits: LimitsConfig,
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
+    #[serde(default)]
+    pub ip_filter: IpFilterConfig,
+    pub cors: Option<CorsConfig>,
+    #[serde(default)]
+    pub security_headers: SecurityHeadersConfig,
+    #[serde(default = "SecurityHeadersConfig::docs")]
+    pub docs_security_headers: SecurityHeadersConfig,
+    #[serde(default)]
+    pub headers: HeaderRulesConfig,
+    #[serde(default)]
+    pub streaming: StreamingConfig,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    #[serde(default)]
+    pub compression: CompressionConfig,
+    #[serde(default)]
+    pub access_log: AccessLogConfig,
+    #[serde(default)]
+    pub request_id: RequestIdConfig,
+    #[serde(default)]
+    pub tracing: TracingConfig,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct CorsConfig {
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    pub exposed_headers: Vec<String>,
+    pub max_age_secs: Option<u64>,
+    pub allow_credentials: bool,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        CorsConfig {
+            allowed_origins: vec![],
+            allowed_methods: ["GET", "POST", "PUT", "DELETE", "OPTIONS"]
+                .iter()
+                .map(|method| method.to_string())
+                .collect(),
+            allowed_headers: vec!["content-type".to_string()],
+            exposed_headers: vec![],
+            max_age_secs: None,
+            allow_credentials: false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct IpFilterConfig {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct LimitsConfig {
+    pub max_connections: usize,
+    pub max_in_flight: usize,
+    pub queue_size: usize,
+    pub queue_timeout_ms: u64,
+    pub retry_after_secs: u64,
+    pub max_body_bytes: usize,
+    pub max_headers: usize,
+    pub max_header_bytes: usize,
+    pub max_uri_length: usize,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig {
+            max_connections: 10000,
+            max_in_flight: 1024,
+            queue_size: 256,
+            queue_timeout_ms: 1000,
+            retry_after_secs: 1,
+            max_body_bytes: 10 * 1024 * 1024,
+            max_headers: 100,
+            max_header_bytes: 16 * 1024,
+            max_uri_length: 8 * 1024,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -34,6 +298,368 @@ pub struct LoggerConfig {
     pub out_file: String,
     pub err_file: String,
     pub debug_file: String,
+    #[serde(default)]
+    pub queue: LogQueueConfig,
+    #[serde(default)]
+    pub kafka: KafkaConfig,
+    #[serde(default)]
+    pub rotation: RotationConfig,
+    #[serde(default)]
+    pub level: Level,
+    #[serde(default)]
+    pub modules: HashMap<String, Level>,
+    #[serde(default)]
+    pub sink_levels: SinkLevels,
+    #[serde(default = "LoggerConfig::default_sinks")]
+    pub sinks: Vec<SinkKind>,
+    #[serde(default = "LoggerConfig::default_memory_capacity")]
+    pub memory_capacity: usize,
+    pub access_file: Option<String>,
+    #[serde(default)]
+    pub redaction: RedactionConfig,
+}
+
+impl LoggerConfig {
+    fn default_sinks() -> Vec<SinkKind> {
+        vec![SinkKind::File]
+    }
+
+    fn default_memory_capacity() -> usize {
+        1000
+    }
+}
+
+/// Where log entries are written, besides Kafka which `use_kafka` turns on. `stdout`
+/// writes errors to stderr and everything else to stdout, `memory` keeps the latest
+/// `memory_capacity` entries for `GET /logger/entries` on the admin listener.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SinkKind {
+    File,
+    Stdout,
+    Memory,
+}
+
+/// Severity of a log entry. Debug and trace entries go to `debug_file`.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Level {
+    Trace,
+    Debug,
+    #[default]
+    Info,
+    Warn,
+    #[serde(alias = "error")]
+    Err,
+}
+
+impl Level {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Level::Trace => "trace",
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warn => "warn",
+            Level::Err => "err",
+        }
+    }
+}
+
+impl std::str::FromStr for Level {
+    type Err = String;
+
+    fn from_str(level: &str) -> Result<Level, String> {
+        match level {
+            "trace" => Ok(Level::Trace),
+            "debug" => Ok(Level::Debug),
+            "info" => Ok(Level::Info),
+            "warn" => Ok(Level::Warn),
+            "err" | "error" => Ok(Level::Err),
+            _ => Err(format!("Unknown log level: {}", level)),
+        }
+    }
+}
+
+/// Rules rewriting sensitive values before an entry reaches any sink. `salt` is mixed
+/// into hashed values so they cannot be looked up in a precomputed table.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct RedactionConfig {
+    pub salt: String,
+    pub rules: Vec<RedactionRuleConfig>,
+}
+
+/// One of `header`, `query`, `json_path` or `pattern`, and what to do with the values
+/// it matches.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RedactionRuleConfig {
+    pub header: Option<String>,
+    pub query: Option<String>,
+    pub json_path: Option<String>,
+    pub pattern: Option<String>,
+    #[serde(default)]
+    pub action: RedactionAction,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RedactionAction {
+    #[default]
+    Mask,
+    Hash,
+    Drop,
+}
+
+/// The least severe entries each sink takes, on top of the logger's own level.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct SinkLevels {
+    pub file: Option<Level>,
+    pub kafka: Option<Level>,
+    pub stdout: Option<Level>,
+    pub memory: Option<Level>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RotationInterval {
+    Hourly,
+    Daily,
+}
+
+/// When the log files are rotated, by size, by time or both. Rotated files are named
+/// `out.log.1`, `out.log.2` and so on, newest first, and only `max_files` are kept.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct RotationConfig {
+    pub max_bytes: Option<u64>,
+    pub interval: Option<RotationInterval>,
+    pub max_files: usize,
+    pub compress: bool,
+}
+
+impl Default for RotationConfig {
+    fn default() -> Self {
+        RotationConfig {
+            max_bytes: None,
+            interval: None,
+            max_files: 7,
+            compress: false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessLogFormat {
+    #[default]
+    Json,
+    Common,
+    Combined,
+}
+
+/// One entry per request once its response has been sent. `fields` picks the JSON fields,
+/// all of them by default. `sample_rate` is the share of requests logged, server errors
+/// are always logged. The caller is named by the `identity_claim` of the auth response.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct AccessLogConfig {
+    pub enabled: bool,
+    pub format: AccessLogFormat,
+    pub fields: Option<Vec<String>>,
+    pub sample_rate: f64,
+    pub identity_claim: String,
+}
+
+impl Default for AccessLogConfig {
+    fn default() -> Self {
+        AccessLogConfig {
+            enabled: true,
+            format: AccessLogFormat::default(),
+            fields: None,
+            sample_rate: 1.0,
+            identity_claim: "userId".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RequestIdFormat {
+    #[default]
+    Uuidv4,
+    Uuidv7,
+    Ulid,
+}
+
+/// How each request gets its `x-request-id`. An inbound id is kept, when `accept_inbound`
+/// is set, if the peer is one of `trusted_sources`, the trusted proxies when unset, and
+/// the id is at most `max_length` letters, digits, `-`, `_`, `.` or `:`.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct RequestIdConfig {
+    pub format: RequestIdFormat,
+    pub accept_inbound: bool,
+    pub trusted_sources: Option<Vec<String>>,
+    pub max_length: usize,
+}
+
+impl Default for RequestIdConfig {
+    fn default() -> Self {
+        RequestIdConfig {
+            format: RequestIdFormat::default(),
+            accept_inbound: false,
+            trusted_sources: None,
+            max_length: 128,
+        }
+    }
+}
+
+/// Spans exported over OTLP/HTTP to the collector at `endpoint`. New traces are sampled
+/// at `sample_rate`, traces started upstream follow the caller's decision when
+/// `parent_based` is set. Finished spans are sent every `export_interval_ms` or every
+/// `batch_size` spans, and dropped when `queue_capacity` of them are already waiting.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct TracingConfig {
+    pub enabled: bool,
+    pub endpoint: String,
+    pub service_name: String,
+    pub sample_rate: f64,
+    pub parent_based: bool,
+    pub batch_size: usize,
+    pub export_interval_ms: u64,
+    pub queue_capacity: usize,
+}
+
+impl Default for TracingConfig {
+    fn default() -> Self {
+        TracingConfig {
+            enabled: false,
+            endpoint: "http://localhost:4318/v1/traces".to_string(),
+            service_name: "hypergate".to_string(),
+            sample_rate: 1.0,
+            parent_based: true,
+            batch_size: 512,
+            export_interval_ms: 1000,
+            queue_capacity: 2048,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum KafkaCompression {
+    None,
+    #[default]
+    Gzip,
+    Snappy,
+}
+
+/// Batching and failure handling of the Kafka log producer. While the broker is down,
+/// entries go to `spill_file`, or are dropped when there is none, and are sent once it
+/// is reachable again.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct KafkaConfig {
+    pub batch_size: usize,
+    pub linger_ms: u64,
+    pub compression: KafkaCompression,
+    pub buffer_capacity: usize,
+    pub ack_timeout_ms: u64,
+    pub backoff_initial_ms: u64,
+    pub backoff_max_ms: u64,
+    pub spill_file: Option<String>,
+    pub spill_max_bytes: u64,
+}
+
+impl Default for KafkaConfig {
+    fn default() -> Self {
+        KafkaConfig {
+            batch_size: 500,
+            linger_ms: 100,
+            compression: KafkaCompression::default(),
+            buffer_capacity: 10_000,
+            ack_timeout_ms: 1000,
+            backoff_initial_ms: 500,
+            backoff_max_ms: 30_000,
+            spill_file: None,
+            spill_max_bytes: 64 * 1024 * 1024,
+        }
+    }
+}
+
+/// What a full log queue does with a new entry: evict the oldest queued one, discard the
+/// new one, or make the caller wait for room.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    DropOldest,
+    #[default]
+    DropNewest,
+    Block,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct LogQueueConfig {
+    pub capacity: usize,
+    pub batch_size: usize,
+    pub flush_interval_ms: u64,
+    pub overflow: OverflowPolicy,
+}
+
+impl Default for LogQueueConfig {
+    fn default() -> Self {
+        LogQueueConfig {
+            capacity: 10_000,
+            batch_size: 256,
+            flush_interval_ms: 200,
+            overflow: OverflowPolicy::default(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct SecurityHeadersConfig {
+    pub hsts: Option<String>,
+    pub content_type_options: Option<String>,
+    pub frame_options: Option<String>,
+    pub referrer_policy: Option<String>,
+    pub permissions_policy: Option<String>,
+    pub content_security_policy: Option<String>,
+    pub strip_headers: Vec<String>,
+}
+
+impl Default for SecurityHeadersConfig {
+    fn default() -> Self {
+        SecurityHeadersConfig {
+            hsts: Some("max-age=31536000; includeSubDomains".to_string()),
+            content_type_options: Some("nosniff".to_string()),
+            frame_options: Some("DENY".to_string()),
+            referrer_policy: Some("no-referrer".to_string()),
+            permissions_policy: Some("camera=(), microphone=(), geolocation=()".to_string()),
+            content_security_policy: Some("default-src 'none'; frame-ancestors 'none'".to_string()),
+            strip_headers: vec!["server".to_string(), "x-powered-by".to_string()],
+        }
+    }
+}
+
+impl SecurityHeadersConfig {
+    /// Relaxed profile for the Swagger UI, which loads its assets from cdnjs.
+    pub fn docs() -> Self {
+        SecurityHeadersConfig {
+            frame_options: Some("SAMEORIGIN".to_string()),
+            content_security_policy: Some(
+                "default-src 'self'; script-src 'self' 'unsafe-inline' https://cdnjs.cloudflare.com; \
+                 style-src 'self' 'unsafe-inline' https://cdnjs.cloudflare.com; img-src 'self' data:"
+                    .to_string(),
+            ),
+            ..Default::default()
+        }
+    }
 }
 
 pub fn load_config(config_path: &str) -> GatewayConfig {
@@ -0,0 +1,223 @@
+use super::parser::{RedactionAction, RedactionConfig, RedactionRuleConfig};
+use regex::Regex;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+/// Masks, hashes or drops sensitive values before an entry is queued, so no sink ever
+/// sees them.
+pub struct Redactor {
+    salt: String,
+    rules: Vec<Rule>,
+}
+
+struct Rule {
+    target: Target,
+    action: RedactionAction,
+}
+
+enum Target {
+    /// Params named after a header, ignoring case and taking `-` and `_` as the same.
+    Header(String),
+    /// A query param, wherever a query string or URL is logged.
+    Query(Regex),
+    /// A field of params holding a JSON document. Arrays are looked through and `*`
+    /// matches every field.
+    JsonPath(Vec<String>),
+    /// Any text matching, such as an email address or a card number.
+    Pattern(Regex),
+}
+
+impl Rule {
+    fn from_config(config: &RedactionRuleConfig) -> Rule {
+        let target = match (
+            &config.header,
+            &config.query,
+            &config.json_path,
+            &config.pattern,
+        ) {
+            (Some(header), None, None, None) => Target::Header(header.clone()),
+            (None, Some(query), None, None) => Target::Query(
+                Regex::new(&format!(
+                    r#"(^|[?&])({})=([^&#\s"]*)"#,
+                    regex::escape(query)
+                ))
+                .unwrap(),
+            ),
+            (None, None, Some(path), None) => {
+                Target::JsonPath(path.split('.').map(str::to_string).collect())
+            }
+            (None, None, None, Some(pattern)) => Target::Pattern(
+                Regex::new(pattern)
+                    .unwrap_or_else(|err| panic!("Invalid redaction pattern {}: {}", pattern, err)),
+            ),
+            _ => panic!("A redaction rule needs one of header, query, json_path or pattern"),
+        };
+        Rule {
+            target,
+            action: config.action,
+        }
+    }
+}
+
+impl Redactor {
+    pub fn from_config(config: &RedactionConfig) -> Redactor {
+        Redactor {
+            salt: config.salt.clone(),
+            rules: config.rules.iter().map(Rule::from_config).collect(),
+        }
+    }
+
+    /// The params with their sensitive values redacted, leaving out the dropped ones.
+    pub fn params<'a>(&self, params: &[(&'a str, &str)]) -> Vec<(&'a str, String)> {
+        params
+            .iter()
+            .filter_map(|(name, value)| Some((*name, self.param(name, value)?)))
+            .collect()
+    }
+
+    /// Redacts the query params and patterns found in free text, such as a message.
+    pub fn text(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        for rule in &self.rules {
+            match &rule.target {
+                Target::Query(regex) => text = self.query(regex, rule.action, &text),
+                Target::Pattern(regex) => {
+                    text = regex
+                        .replace_all(&text, |caps: &regex::Captures| {
+                            self.apply(rule.action, &caps[0]).unwrap_or_default()
+                        })
+                        .into_owned();
+                }
+                Target::Header(_) | Target::JsonPath(_) => {}
+            }
+        }
+        text
+    }
+
+    fn param(&self, name: &str, value: &str) -> Option<String> {
+        let header = self.rules.iter().find(|rule| match &rule.target {
+            Target::Header(header) => same_header(header, name),
+            _ => false,
+        });
+        match header {
+            Some(rule) => self.apply(rule.action, value),
+            None => Some(self.json(self.text(value))),
+        }
+    }
+
+    /// Rewrites `name=value` pairs, removing the pair and one of its `&` when dropped.
+    fn query(&self, regex: &Regex, action: RedactionAction, text: &str) -> String {
+        let mut redacted = String::with_capacity(text.len());
+        let mut last = 0;
+        for caps in regex.captures_iter(text) {
+            let whole = caps.get(0).unwrap();
+            // The `&` in front of this pair went with the previous, dropped one
+            let separator = match whole.start() < last {
+                true => "",
+                false => caps.get(1).unwrap().as_str(),
+            };
+            redacted.push_str(&text[last..whole.start().max(last)]);
+            last = whole.end();
+            match self.apply(action, &caps[3]) {
+                Some(value) => {
+                    redacted.push_str(&format!("{}{}={}", separator, &caps[2], value));
+                }
+                None if separator == "&" => {}
+                None => {
+                    redacted.push_str(separator);
+                    if text[last..].starts_with('&') {
+                        last += 1;
+                    }
+                }
+            }
+        }
+        redacted.push_str(&text[last..]);
+        redacted
+    }
+
+    fn json(&self, value: String) -> String {
+        let paths: Vec<_> = self
+            .rules
+            .iter()
+            .filter_map(|rule| match &rule.target {
+                Target::JsonPath(path) => Some((path, rule.action)),
+                _ => None,
+            })
+            .collect();
+        if paths.is_empty() || !value.trim_start().starts_with(['{', '[']) {
+            return value;
+        }
+        let Ok(mut json) = serde_json::from_str::<Value>(&value) else {
+            return value;
+        };
+        for (path, action) in paths {
+            self.json_path(&mut json, path, action);
+        }
+        json.to_string()
+    }
+
+    fn json_path(&self, value: &mut Value, path: &[String], action: RedactionAction) {
+        let Some((first, rest)) = path.split_first() else {
+            return;
+        };
+        match value {
+            Value::Array(items) => {
+                for item in items {
+                    self.json_path(item, path, action);
+                }
+            }
+            Value::Object(fields) => {
+                let names: Vec<String> = match first.as_str() {
+                    "*" => fields.keys().cloned().collect(),
+                    _ if fields.contains_key(first) => vec![first.clone()],
+                    _ => Vec::new(),
+                };
+                for name in names {
+                    if !rest.is_empty() {
+                        self.json_path(&mut fields[&name], rest, action);
+                        continue;
+                    }
+                    let raw = match &fields[&name] {
+                        Value::String(raw) => raw.clone(),
+                        other => other.to_string(),
+                    };
+                    match self.apply(action, &raw) {
+                        Some(redacted) => fields[&name] = Value::String(redacted),
+                        None => {
+                            fields.remove(&name);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The redacted value, `None` when it is dropped. Hashes keep equal values
+    /// recognisable across entries without revealing them.
+    fn apply(&self, action: RedactionAction, value: &str) -> Option<String> {
+        match action {
+            RedactionAction::Mask => Some("***".to_string()),
+            RedactionAction::Hash => {
+                let digest = Sha256::new()
+                    .chain_update(&self.salt)
+                    .chain_update(value)
+                    .finalize();
+                let hex: String = digest[..8].iter().map(|b| format!("{:02x}", b)).collect();
+                Some(format!("sha256:{}", hex))
+            }
+            RedactionAction::Drop => None,
+        }
+    }
+}
+
+fn same_header(header: &str, name: &str) -> bool {
+    header.len() == name.len()
+        && header.bytes().zip(name.bytes()).all(|(a, b)| {
+            let normalize = |c: u8| match c {
+                b'_' => b'-',
+                c => c.to_ascii_lowercase(),
+            };
+            normalize(a) == normalize(b)
+        })
+}
@@ -0,0 +1,35 @@
+use crate::middleware::auth::AuthIdentity;
+use crate::utils::route::RouteMatch;
+use std::net::IpAddr;
+
+/// What the gateway knows about a request while proxying it.
+pub struct RequestContext {
+    pub request_id: String,
+    pub client_ip: IpAddr,
+    pub route: Option<RouteMatch>,
+    pub identity: AuthIdentity,
+}
+
+impl RequestContext {
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.route
+            .as_ref()
+            .and_then(|route| route.params.get(name))
+            .map(String::as_str)
+    }
+
+    /// Resolves a template variable: `request_id`, `client_ip`, `param.<name>`,
+    /// `auth.<claim>` or `env.<variable>`.
+    pub fn variable(&self, name: &str) -> Option<String> {
+        match name.split_once('.') {
+            Some(("param", param)) => self.param(param).map(str::to_string),
+            Some(("auth", claim)) => self.identity.get(claim).map(str::to_string),
+            Some(("env", variable)) => std::env::var(variable).ok(),
+            _ => match name {
+                "request_id" => Some(self.request_id.clone()),
+                "client_ip" => Some(self.client_ip.to_string()),
+                _ => None,
+            },
+        }
+    }
+}
@@ -1,37 +1,85 @@
+mod admin;
 mod config;
+mod context;
+mod metrics;
+mod middleware;
+mod proxy;
+mod trace;
 mod utils;
 
 use clap::{Arg, Command};
 use config::logger::Logger;
 use config::openapi::OpenApiMerger;
 use config::parser::{load_config, GatewayConfig, NoAuthEndpoints, ServiceConfig};
+use context::RequestContext;
 use http_body_util::BodyExt;
 use hyper::body::{Bytes, Incoming};
-use hyper::header::HeaderValue;
-use hyper::header::CONTENT_TYPE;
+use hyper::header::{HeaderValue, TE};
 use hyper::http::request::Parts;
-use hyper::server::conn::http1;
-use hyper::{Method, Request, Response, StatusCode};
-use hyper_util::client::legacy::Client;
+use hyper::http::uri::PathAndQuery;
+use hyper::{Method, Request, Response, StatusCode, Uri, Version};
+use hyper_util::client::legacy::{Client, Error as ClientError};
 use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
 use hyper_util::service::TowerToHyperService;
-use iptools::ipv4;
-use iptools::ipv6;
+use metrics::Metrics;
+use middleware::access_log::{AccessDetails, AccessLog};
+use middleware::auth::AuthIdentity;
+use middleware::compression::{self, CompressionPolicies};
+use middleware::cors::CorsPolicies;
+use middleware::forwarded::TrustedProxies;
+use middleware::headers::HeaderTransforms;
+use middleware::hop_by_hop::{append_via, apply_host_policy, strip_hop_by_hop};
+use middleware::ip_filter::IpFilter;
+use middleware::limits::Limiter;
+use middleware::request_id::{RequestIds, X_REQUEST_ID};
+use middleware::rewrite::Rewrites;
+use middleware::security_headers::SecurityHeaders;
+use middleware::size_limits;
 use openapiv3::OpenAPI;
-use reqwest::header::{HeaderMap, COOKIE};
-use std::net::SocketAddr;
+use proxy::cache::{Lookup, ResponseCache};
+use proxy::coalesce::{Coalescer, Flight};
+use proxy::grpc::{self, grpc_web, is_grpc};
+use proxy::streaming::{self, is_event_stream, is_streaming_request, IdleTimeout};
+use proxy::upgrade::{requested_protocol, restore_upgrade_headers, tunnel};
+use reqwest::header::{HeaderMap, COOKIE, RETRY_AFTER, UPGRADE};
+use std::net::{IpAddr, SocketAddr};
+use std::process::ExitCode;
 use std::result::Result;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::net::TcpListener;
-use tower::ServiceBuilder;
-use tower_http::cors::{AllowOrigin, CorsLayer};
-use utils::http::{full, BoxBody};
-use uuid::Uuid;
+use tower::{service_fn, ServiceBuilder, ServiceExt};
+use trace::{Span, SpanKind, Tracer};
+use utils::http::{boxed, full, is_body_too_large, limited, BoxBody};
+use utils::route::Routes;
 
 type GenericError = Box<dyn std::error::Error + Send + Sync>;
 
+struct GatewayState {
+    config: GatewayConfig,
+    logger: Arc<Logger>,
+    access_log: AccessLog,
+    request_ids: RequestIds,
+    metrics: Metrics,
+    tracer: Tracer,
+    limiter: Limiter,
+    trusted_proxies: TrustedProxies,
+    ip_filter: IpFilter,
+    cors: CorsPolicies,
+    compression: CompressionPolicies,
+    security_headers: SecurityHeaders,
+    routes: Routes,
+    rewrites: Rewrites,
+    header_transforms: HeaderTransforms,
+    cache: Arc<ResponseCache>,
+    coalescer: Arc<Coalescer>,
+    openapi_path: String,
+    html_path: String,
+}
+
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
     let matches = Command::new("HyperGate")
         .version("0.1.0")
         .author("@adrrf @AntonioRodriguezRuiz @alvarobernal2412")
@@ -85,8 +133,12 @@ async fn main() {
                 .get_one::<String>("output")
                 .expect("Output path is required.");
             println!("Merging OpenAPI specs...");
-            if let Err(err) = merge_openapi_specs(specs, output).await {
-                eprintln!("Error merging OpenAPI specs: {:?}", err);
+            match merge_openapi_specs(specs, output).await {
+                Ok(_) => ExitCode::SUCCESS,
+                Err(err) => {
+                    eprintln!("Error merging OpenAPI specs: {:?}", err);
+                    ExitCode::FAILURE
+                }
             }
         }
         Some(("serve", sub_matches)) => {
@@ -99,85 +151,144 @@ async fn main() {
             let html = sub_matches
                 .get_one::<String>("html")
                 .expect("HTML path is required.");
-            if let Err(err) = api_gateway(config, spec, html).await {
-                println!("Error: {:?}", err);
-            }
+            api_gateway(config, spec, html).await
+        }
+        _ => {
+            eprintln!("Invalid command");
+            ExitCode::FAILURE
         }
-        _ => println!("Invalid command"),
     }
 }
 
-async fn api_gateway(
-    config_path: &str,
-    openapi_spec: &str,
-    html_path: &str,
-) -> Result<(), GenericError> {
-    let config = Arc::new(load_config(config_path));
-    let logger = Arc::new(Logger::from_config(&config.logger_config));
+/// Serves until the listener fails. Errors are logged, except for an invalid config,
+/// which panics before there is a logger to report it.
+async fn api_gateway(config_path: &str, openapi_spec: &str, html_path: &str) -> ExitCode {
+    let config = load_config(config_path);
+    let state = Arc::new(GatewayState {
+        logger: Arc::new(Logger::from_config(&config.logger_config)),
+        access_log: AccessLog::from_config(&config),
+        request_ids: RequestIds::from_config(&config),
+        metrics: Metrics::default(),
+        tracer: Tracer::from_config(&config),
+        limiter: Limiter::from_config(&config),
+        trusted_proxies: TrustedProxies::from_config(&config.trusted_proxies),
+        ip_filter: IpFilter::from_config(&config),
+        cors: CorsPolicies::from_config(&config),
+        compression: CompressionPolicies::from_config(&config),
+        security_headers: SecurityHeaders::from_config(&config),
+        routes: Routes::from_config(&config),
+        rewrites: Rewrites::from_config(&config),
+        header_transforms: HeaderTransforms::from_config(&config),
+        cache: Arc::new(ResponseCache::from_config(&config)),
+        coalescer: Arc::new(Coalescer::default()),
+        config,
+        openapi_path: openapi_spec.to_string(),
+        html_path: html_path.to_string(),
+    });
+
+    if let Err(err) = serve_connections(state.clone()).await {
+        state
+            .logger
+            .err("Gateway stopped", &[("reason", &err.to_string())]);
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
 
+async fn serve_connections(state: Arc<GatewayState>) -> Result<(), GenericError> {
     let url = format!(
         "{}://{}",
-        if config.is_https { "https" } else { "http" },
-        config.api_gateway_url
+        if state.config.is_https {
+            "https"
+        } else {
+            "http"
+        },
+        state.config.api_gateway_url
     );
 
-    println!(
-        "HyperGate🚀 listening at {} \n \tOpenAPI specification at {}/docs",
-        url, url
+    let listener = TcpListener::bind(&state.config.api_gateway_url).await?;
+    state.logger.info(
+        "HyperGate🚀 listening",
+        &[("url", &url), ("docs", &format!("{}/docs", url))],
     );
-    let listener = TcpListener::bind(&config.api_gateway_url).await?;
-
-    let cors = CorsLayer::new()
-        .allow_methods([
-            Method::GET,
-            Method::POST,
-            Method::OPTIONS,
-            Method::PUT,
-            Method::DELETE,
-        ])
-        .allow_origin(AllowOrigin::mirror_request())
-        .allow_headers([CONTENT_TYPE])
-        .allow_credentials(true);
+
+    if let Some(admin_url) = &state.config.admin_url {
+        let admin_listener = TcpListener::bind(admin_url).await?;
+        state.logger.info(
+            "Admin endpoints listening",
+            &[("url", &format!("http://{}", admin_url))],
+        );
+        tokio::task::spawn(admin::serve_admin(admin_listener, state.clone()));
+    }
+
+    #[cfg(unix)]
+    tokio::task::spawn(reopen_logs_on_signal(state.clone()));
 
     loop {
         // Accept incoming connections
         let (stream, conn_addr) = listener.accept().await?;
+
+        // Shed the connection without spawning a task when we are at capacity
+        let conn_permit = match state.limiter.try_connection() {
+            Some(permit) => permit,
+            None => {
+                let _ = stream.try_write(
+                    format!(
+                        "HTTP/1.1 503 Service Unavailable\r\nRetry-After: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                        state.limiter.retry_after_secs()
+                    )
+                    .as_bytes(),
+                );
+                state.logger.warn(
+                    "Connection shed",
+                    &[("ip", conn_addr.ip().to_string().as_str())],
+                );
+                continue;
+            }
+        };
+
+        // Stream chunks are small, do not hold them back waiting for more data
+        let _ = stream.set_nodelay(true);
         let io = TokioIo::new(stream);
-        let config = config.clone();
-        let logger = logger.clone();
-        let html = html_path.to_string();
-        let spec = openapi_spec.to_string();
-        let cors = cors.clone();
+        let state = state.clone();
 
         tokio::task::spawn(async move {
-            let request_id = Uuid::new_v4().to_string();
-            let html = &html;
-            let spec = &spec;
-            let cors = cors.clone();
+            let _conn_permit = conn_permit;
+            let connection_id = state.request_ids.generate();
 
-            logger.info(
+            state.logger.info(
                 "New connection",
                 &[
-                    ("request_id", &request_id),
+                    ("connection_id", &connection_id),
                     ("ip", conn_addr.ip().to_string().as_str()),
                 ],
             );
 
-            let service = ServiceBuilder::new().layer(cors).service_fn(move |req| {
-                handle_request(
-                    req,
-                    conn_addr,
-                    config.clone(),
-                    logger.clone(),
-                    request_id.to_owned(),
-                    spec,
-                    html,
-                )
-            });
+            let limits = state.config.limits.clone();
+            let conn_state = state.clone();
+            let service = service_fn(move |req| serve_request(req, conn_addr, state.clone()));
             let service = TowerToHyperService::new(service);
 
-            if let Err(err) = http1::Builder::new().serve_connection(io, service).await {
-                println!("Failed to serve connection: {:?}", err);
+            // HTTP/1 and HTTP/2 with prior knowledge, which gRPC clients use, on the same port
+            let mut builder = auto::Builder::new(TokioExecutor::new());
+            // Heads over the limits are refused while they are read rather than once
+            // buffered. Hyper reads HTTP/1 into a buffer of at least 8 KiB.
+            builder
+                .http1()
+                .max_headers(limits.max_headers)
+                .max_buf_size(size_limits::max_head_bytes(&limits).max(8192));
+            builder
+                .http2()
+                .max_header_list_size(size_limits::max_header_list_size(&limits));
+            if let Err(err) = builder.serve_connection_with_upgrades(io, service).await {
+                conn_state.logger.warn(
+                    "Failed to serve connection",
+                    &[
+                        ("connection_id", &connection_id),
+                        ("ip", conn_addr.ip().to_string().as_str()),
+                        ("reason", &err.to_string()),
+                    ],
+                );
             }
         });
     }
@@ -195,28 +306,171 @@ async fn merge_openapi_specs(
     Ok(merged_spec)
 }
 
-async fn handle_request(
+/// Reopens the log files on SIGUSR1, sent by external tools such as logrotate once they
+/// have moved the files away.
+#[cfg(unix)]
+async fn reopen_logs_on_signal(state: Arc<GatewayState>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut signals = match signal(SignalKind::user_defined1()) {
+        Ok(signals) => signals,
+        Err(err) => {
+            state.logger.err(
+                "Failed to listen for SIGUSR1",
+                &[("reason", &err.to_string())],
+            );
+            return;
+        }
+    };
+    while signals.recv().await.is_some() {
+        state.logger.reopen();
+    }
+}
+
+/// Applies the response policies of the matched route around `handle_request`. The CORS
+/// layer answers preflight requests itself, responses are compressed for the client and
+/// failed gRPC calls get a gRPC status.
+async fn serve_request(
     req: Request<Incoming>,
     conn_addr: SocketAddr,
-    config: Arc<GatewayConfig>,
-    logger: Arc<Logger>,
+    state: Arc<GatewayState>,
+) -> Result<Response<BoxBody>, GenericError> {
+    // Every request on a keep-alive connection gets its own id
+    let request_id = state.request_ids.for_request(conn_addr.ip(), req.headers());
+    let started = Instant::now();
+    let method = req.method().clone();
+    let is_docs = matches!(req.uri().path(), "/docs" | "/docs/spec");
+    let service_config = get_service_config(req.uri().path(), &state.config.services);
+    // Route templates rather than paths keep the number of metric series bounded
+    let (service, route) = match service_config {
+        Some(service_config) => {
+            let route = service_config.route.as_deref().filter(|_| {
+                state
+                    .routes
+                    .matches(service_config, req.uri().path())
+                    .is_some()
+            });
+            (
+                service_config.path.as_str(),
+                route.unwrap_or(&service_config.path),
+            )
+        }
+        None if is_docs => ("docs", "docs"),
+        None => ("unmatched", "unmatched"),
+    };
+    let service_path = service_config.map(|service_config| service_config.path.clone());
+    let grpc_call = service_config
+        .filter(|service_config| service_config.grpc.is_some() && is_grpc(req.headers()));
+    let web = grpc_call.and_then(|service_config| grpc_web(service_config, req.headers()));
+    let cors = state.cors.for_service(service_path.as_deref());
+    let compression = state.compression.for_service(service_path.as_deref());
+    let client_ip = state
+        .trusted_proxies
+        .client_ip(conn_addr.ip(), req.headers());
+    let mut span = state
+        .tracer
+        .start_request(format!("{} {}", method, route), req.headers());
+    span.set_str("http.request.method", method.as_str());
+    span.set_str("url.path", req.uri().path());
+    span.set_str("http.route", route);
+    span.set_str("client.address", &client_ip.to_string());
+    span.set_str("hypergate.service", service);
+    span.set_str("hypergate.request_id", &request_id);
+    let trace_id = span.context().map(|context| context.trace_id());
+    let access = state
+        .access_log
+        .start(&req, client_ip, &request_id, trace_id);
+    let details = access.as_ref().map(|record| record.details());
+
+    // Checked ahead of the CORS layer so that denied clients do not get preflights answered
+    let ip_check = state.ip_filter.check(service_config, &client_ip);
+    // Log entries and the spans of the auth and upstream calls belong to this trace
+    let handled = async {
+        if let Err(rule) = ip_check {
+            return deny_ip(&req, client_ip, &rule, &state.logger, &request_id);
+        }
+        let response = ServiceBuilder::new()
+            .layer(cors)
+            .layer(compression)
+            .service_fn(|req| {
+                let details = details.clone();
+                handle_request(
+                    req,
+                    conn_addr,
+                    client_ip,
+                    state.clone(),
+                    request_id.clone(),
+                    details,
+                )
+            })
+            .oneshot(req)
+            .await?;
+        Ok(response.map(boxed))
+    };
+    let mut response = trace::scope(span.context(), handled).await?;
+    if grpc_call.is_some() {
+        response = grpc::error_to_grpc(response, web);
+    }
+
+    let security_headers = if is_docs {
+        state.security_headers.docs()
+    } else {
+        state.security_headers.for_service(service_path.as_deref())
+    };
+    security_headers.apply(response.headers_mut());
+    state.request_ids.apply(&request_id, response.headers_mut());
+    state.metrics.observe_request(
+        service,
+        route,
+        &method,
+        response.status(),
+        started.elapsed(),
+    );
+
+    span.set_int(
+        "http.response.status_code",
+        response.status().as_u16().into(),
+    );
+    if response.status().is_server_error() {
+        span.set_error();
+    }
+    span.end();
+
+    if let Some(record) = access {
+        response = state.access_log.finish(record, response, &state.logger);
+    }
+    Ok(response)
+}
+
+async fn handle_request(
+    mut req: Request<Incoming>,
+    conn_addr: SocketAddr,
+    client_ip: IpAddr,
+    state: Arc<GatewayState>,
     request_id: String,
-    openapi_path: &str,
-    html_path: &str,
+    details: Option<AccessDetails>,
 ) -> Result<Response<BoxBody>, GenericError> {
-    if req.method() == Method::OPTIONS {
-        let response = Response::builder()
-            .status(StatusCode::NO_CONTENT)
-            .body(full(Bytes::new()))
-            .unwrap();
-        return Ok(response);
+    let config = &state.config;
+    let logger = &state.logger;
+    logger.trace(
+        "Request received",
+        &[
+            ("request_id", &request_id),
+            ("ip", client_ip.to_string().as_str()),
+            ("method", req.method().as_str()),
+            ("url", req.uri().path()),
+        ],
+    );
+
+    if let Err(status) = size_limits::check_head(&req, &config.limits) {
+        return reject_size(req.method(), req.uri(), status, logger, &request_id);
     }
 
     let path = req.uri().path();
 
     match path {
-        "/docs/spec" => return serve_openapi_spec(openapi_path).await,
-        "/docs" => return serve_swagger_ui(html_path).await,
+        "/docs/spec" => return serve_openapi_spec(&state.openapi_path).await,
+        "/docs" => return serve_swagger_ui(&state.html_path).await,
         _ => (),
     }
 
@@ -227,7 +481,7 @@ async fn handle_request(
                 &format!("Path not found: {}", path),
                 &[
                     ("request_id", &request_id),
-                    ("ip", conn_addr.ip().to_string().as_str()),
+                    ("ip", client_ip.to_string().as_str()),
                     ("method", req.method().as_str()),
                     ("url", req.uri().path().to_string().as_str()),
                     ("params", req.uri().query().unwrap_or("")),
@@ -237,20 +491,91 @@ async fn handle_request(
         }
     };
 
-    if needs_auth(path, req.method().as_str(), &config.endpoints_without_auth) {
-        match authorize_user(req.headers(), &config.authorization_api_url, &request_id).await {
+    let mut ctx = RequestContext {
+        request_id: request_id.clone(),
+        client_ip,
+        route: state.routes.matches(service_config, path),
+        identity: AuthIdentity::default(),
+    };
+
+    let max_body_bytes = size_limits::max_body_bytes(service_config, &config.limits);
+    if let Err(status) = size_limits::check_content_length(&req, max_body_bytes) {
+        return reject_size(req.method(), req.uri(), status, logger, &request_id);
+    }
+
+    let requires_auth = needs_auth(path, req.method().as_str(), &config.endpoints_without_auth);
+
+    let permit = match state.limiter.acquire(service_config, requires_auth).await {
+        Some(permit) => permit,
+        None => {
+            logger.warn(
+                "Request shed",
+                &[
+                    ("request_id", &request_id),
+                    ("ip", client_ip.to_string().as_str()),
+                    ("method", req.method().as_str()),
+                    ("url", req.uri().path().to_string().as_str()),
+                ],
+            );
+            return too_many_requests_in_flight(state.limiter.retry_after_secs());
+        }
+    };
+
+    if requires_auth {
+        let mut auth_span = state.tracer.start_child(
+            "auth".to_string(),
+            SpanKind::Client,
+            trace::current().as_ref(),
+        );
+        auth_span.set_str("url.full", &config.authorization_api_url);
+        let auth_started = Instant::now();
+        let authorized = authorize_user(
+            req.headers(),
+            &config.authorization_api_url,
+            &request_id,
+            &auth_span,
+        )
+        .await;
+        let outcome = match &authorized {
+            Ok(res) if res.status().is_success() => "allowed",
+            Ok(_) => "denied",
+            Err(_) => "error",
+        };
+        state.metrics.observe_auth(outcome, auth_started.elapsed());
+        match &authorized {
+            Ok(res) => auth_span.set_int("http.response.status_code", res.status().as_u16().into()),
+            Err(_) => auth_span.set_error(),
+        }
+        auth_span.set_str("hypergate.auth.outcome", outcome);
+        auth_span.end();
+        match authorized {
             Ok(res) if !res.status().is_success() => {
                 logger.info(
                     "Connection closed",
                     &[
                         ("request_id", &request_id),
-                        ("ip", conn_addr.ip().to_string().as_str()),
+                        ("ip", client_ip.to_string().as_str()),
                         ("status", res.status().as_str()),
                     ],
                 );
                 return Ok(res);
             }
-            Ok(_) => (),
+            Ok(res) => {
+                ctx.identity = AuthIdentity::from_response(res).await;
+                logger.debug(
+                    "Caller authorized",
+                    &[
+                        ("request_id", &request_id),
+                        ("identity", ctx.identity.document()),
+                    ],
+                );
+                if let Some(details) = &details {
+                    details.lock().unwrap().identity = ctx
+                        .identity
+                        .get(state.access_log.identity_claim())
+                        .map(str::to_string);
+                }
+            }
             Err(_) => {
                 logger.err(
                     &format!(
@@ -259,7 +584,7 @@ async fn handle_request(
                     ),
                     &[
                         ("request_id", &request_id),
-                        ("ip", conn_addr.ip().to_string().as_str()),
+                        ("ip", client_ip.to_string().as_str()),
                         ("method", req.method().as_str()),
                         ("url", req.uri().path().to_string().as_str()),
                         ("params", req.uri().query().unwrap_or("")),
@@ -270,27 +595,274 @@ async fn handle_request(
         };
     }
 
-    let (parts, body) = req.into_parts();
+    let cache_key = state.cache.key(service_config, &req, &ctx);
+    let mut stale = None;
+    if let Some(key) = &cache_key {
+        match state.cache.lookup(key, req.headers()) {
+            Lookup::Hit(mut res) => {
+                state
+                    .header_transforms
+                    .apply_response(service_config, res.headers_mut(), &ctx);
+                logger.info(
+                    "Connection closed",
+                    &[
+                        ("request_id", &request_id),
+                        ("ip", client_ip.to_string().as_str()),
+                        ("status", res.status().as_str()),
+                        ("cache", "HIT"),
+                    ],
+                );
+                return Ok(res);
+            }
+            Lookup::Stale(entry) => stale = Some(entry),
+            Lookup::Miss => (),
+        }
+    }
+
+    // Revalidations are specific to the stale entry, they are never shared
+    let coalesce_key = match stale {
+        Some(_) => None,
+        None => state.coalescer.key(service_config, &req),
+    };
+    let mut leader = None;
+    if let Some(key) = coalesce_key {
+        match state.coalescer.join(key) {
+            Flight::Leader(flight) => leader = Some(flight),
+            Flight::Follower(flight) => {
+                if let Some(mut res) = flight.wait().await {
+                    state
+                        .header_transforms
+                        .apply_response(service_config, res.headers_mut(), &ctx);
+                    logger.info(
+                        "Connection closed",
+                        &[
+                            ("request_id", &request_id),
+                            ("ip", client_ip.to_string().as_str()),
+                            ("status", res.status().as_str()),
+                            ("coalesced", "true"),
+                        ],
+                    );
+                    return Ok(res);
+                }
+            }
+        }
+    }
+
+    // Taken before the request is split so the client connection is not handed downstream
+    let upgrade = match (&service_config.upgrade, requested_protocol(req.headers())) {
+        (Some(upgrade_config), Some(protocol)) => {
+            Some((upgrade_config, protocol, hyper::upgrade::on(&mut req)))
+        }
+        _ => None,
+    };
+
+    let (mut parts, body) = req.into_parts();
+    let web = grpc_web(service_config, &parts.headers);
+    let mut downstream_body = limited(body, max_body_bytes);
+    if let Some(web) = web {
+        downstream_body = grpc::request_from_web(&mut parts.headers, downstream_body, web);
+    }
+    if let Some(decompress) = &service_config.decompress {
+        let max_bytes = decompress.max_bytes.unwrap_or(max_body_bytes);
+        downstream_body =
+            compression::decompress_request(&mut parts.headers, downstream_body, max_bytes);
+    }
 
     // For logging
     let cloned_parts = parts.clone();
 
-    let downstream_req =
-        build_downstream_request(parts, body, conn_addr, &request_id, service_config).await?;
+    let mut upstream_span = state.tracer.start_child(
+        parts.method.to_string(),
+        SpanKind::Client,
+        trace::current().as_ref(),
+    );
+    let mut downstream_req = match build_downstream_request(
+        parts,
+        downstream_body,
+        conn_addr,
+        &ctx,
+        service_config,
+        &state,
+        &upstream_span,
+    ) {
+        Ok(downstream_req) => downstream_req,
+        Err(status) => {
+            state.metrics.upstream_error(&service_config.path, "build");
+            upstream_span.set_str("error.type", "build");
+            upstream_span.set_error();
+            upstream_span.end();
+            logger.err(
+                &format!(
+                    "Failed to build request for downstream service {}",
+                    &service_config.target_service
+                ),
+                &[
+                    ("request_id", &request_id),
+                    ("ip", client_ip.to_string().as_str()),
+                    ("status", status.as_str()),
+                    ("method", cloned_parts.method.as_str()),
+                    ("url", cloned_parts.uri.path().to_string().as_str()),
+                    ("params", cloned_parts.uri.query().unwrap_or("")),
+                ],
+            );
+            return error_response(status);
+        }
+    };
+    if let Some((_, protocol, _)) = &upgrade {
+        restore_upgrade_headers(downstream_req.headers_mut(), protocol.clone());
+    }
+    if let Some(stale) = &stale {
+        stale.add_validators(downstream_req.headers_mut());
+    }
+    logger.debug(
+        "Forwarding request to downstream service",
+        &[
+            ("request_id", &request_id),
+            ("method", downstream_req.method().as_str()),
+            ("target", downstream_req.uri().to_string().as_str()),
+        ],
+    );
 
-    match forward_request(downstream_req).await {
+    let upstream = downstream_req.uri().to_string();
+    upstream_span.set_str("http.request.method", downstream_req.method().as_str());
+    upstream_span.set_str("url.full", &upstream);
+    upstream_span.set_str("hypergate.service", &service_config.path);
+    let started = Instant::now();
+    let forwarded = forward_request(downstream_req).await;
+    match &forwarded {
         Ok(res) => {
+            upstream_span.set_int("http.response.status_code", res.status().as_u16().into());
+            if res.status().is_server_error() {
+                upstream_span.set_error();
+            }
+        }
+        Err(err) => {
+            upstream_span.set_str(
+                "error.type",
+                if err.is_connect() {
+                    "connect"
+                } else {
+                    "request"
+                },
+            );
+            upstream_span.set_error();
+        }
+    }
+    upstream_span.end();
+    if let Some(details) = &details {
+        let mut details = details.lock().unwrap();
+        details.upstream = Some(upstream);
+        details.upstream_latency = Some(started.elapsed());
+    }
+
+    match forwarded {
+        Ok(mut res) => {
+            let switched_protocol = match res.status() {
+                StatusCode::SWITCHING_PROTOCOLS => res.headers().get(UPGRADE).cloned(),
+                _ => None,
+            };
+            strip_hop_by_hop(res.headers_mut());
+            let version = res.version();
+            append_via(res.headers_mut(), version);
+            if let Some(key) = cache_key {
+                res = match stale {
+                    Some(stale) if res.status() == StatusCode::NOT_MODIFIED => {
+                        state.cache.refresh(&key, stale, &res)
+                    }
+                    _ => state.cache.store(key, &cloned_parts.headers, res),
+                };
+            }
+            if let Some(leader) = leader {
+                res = leader.share(res);
+            }
+            state
+                .header_transforms
+                .apply_response(service_config, res.headers_mut(), &ctx);
             logger.info(
                 "Connection closed",
                 &[
                     ("request_id", &request_id),
-                    ("ip", conn_addr.ip().to_string().as_str()),
+                    ("ip", client_ip.to_string().as_str()),
                     ("status", res.status().as_str()),
                 ],
             );
-            Ok(res)
+
+            if let (Some((upgrade_config, _, client)), Some(protocol)) =
+                (upgrade, switched_protocol)
+            {
+                let upstream = hyper::upgrade::on(&mut res);
+                restore_upgrade_headers(res.headers_mut(), protocol.clone());
+                let idle_timeout = Duration::from_secs(upgrade_config.idle_timeout_secs);
+                let state = state.clone();
+                let protocol = protocol.to_str().unwrap_or_default().to_string();
+                let url = cloned_parts.uri.path().to_string();
+                let ip = client_ip.to_string();
+                tokio::task::spawn(async move {
+                    let params = [
+                        ("request_id", request_id.as_str()),
+                        ("ip", ip.as_str()),
+                        ("url", url.as_str()),
+                        ("protocol", protocol.as_str()),
+                    ];
+                    // The permit is held while the tunnel stays open
+                    tunnel(
+                        client,
+                        upstream,
+                        idle_timeout,
+                        &state.logger,
+                        &params,
+                        permit,
+                    )
+                    .await;
+                });
+                return Ok(res);
+            }
+
+            let is_stream = is_streaming_request(service_config, &cloned_parts.headers)
+                || is_event_stream(res.headers());
+            // Ask proxies in front of us not to buffer streams either
+            if is_stream {
+                res.headers_mut()
+                    .insert("x-accel-buffering", HeaderValue::from_static("no"));
+            }
+
+            // Only streams are cut off when idle, a slow download may pause for longer
+            let idle_timeout = is_stream.then(|| streaming::idle_timeout(service_config, config));
+            // Keep the permit until the response body has been fully sent
+            let res = res.map(|body| {
+                let body = match idle_timeout {
+                    Some(idle_timeout) => IdleTimeout::new(body, idle_timeout).boxed(),
+                    None => body,
+                };
+                body.map_frame(move |frame| {
+                    let _ = &permit;
+                    frame
+                })
+                .boxed()
+            });
+            match web {
+                Some(web) => Ok(grpc::response_to_web(res, web)),
+                None => Ok(res),
+            }
         }
-        Err(_) => {
+        Err(err) if is_body_too_large(&err) => reject_size(
+            &cloned_parts.method,
+            &cloned_parts.uri,
+            StatusCode::PAYLOAD_TOO_LARGE,
+            logger,
+            &request_id,
+        ),
+        Err(err) => {
+            const REASON: &str = "Failed to connect to downstream service";
+            let kind = if err.is_connect() {
+                "connect"
+            } else {
+                "request"
+            };
+            state.metrics.upstream_error(&service_config.path, kind);
+            if let Some(leader) = leader {
+                leader.fail(StatusCode::SERVICE_UNAVAILABLE, REASON);
+            }
             logger.err(
                 &format!(
                     "Failed to connect to downstream service {}",
@@ -298,13 +870,13 @@ async fn handle_request(
                 ),
                 &[
                     ("request_id", &request_id),
-                    ("ip", conn_addr.ip().to_string().as_str()),
+                    ("ip", client_ip.to_string().as_str()),
                     ("method", cloned_parts.method.as_str()),
                     ("url", cloned_parts.uri.path().to_string().as_str()),
                     ("params", cloned_parts.uri.query().unwrap_or("")),
                 ],
             );
-            service_unavailable("Failed to connect to downstream service")
+            service_unavailable(REASON)
         }
     }
 }
@@ -363,18 +935,20 @@ async fn authorize_user(
     headers: &HeaderMap,
     auth_api_url: &str,
     request_id: &str,
+    span: &Span,
 ) -> Result<Response<BoxBody>, ()> {
     let cookies_header_value = match headers.get(COOKIE) {
         Some(value) => value.to_str().unwrap_or_default(),
         None => "",
     };
 
-    let auth_request = Request::builder()
+    let mut auth_request = Request::builder()
         .uri(auth_api_url)
         .header(COOKIE, cookies_header_value)
         .header("x-request-id", request_id)
         .body(BoxBody::default())
         .unwrap();
+    span.inject(auth_request.headers_mut());
 
     match forward_request(auth_request).await {
         Ok(res) => Ok(res),
@@ -382,55 +956,148 @@ async fn authorize_user(
     }
 }
 
-async fn build_downstream_request(
+/// Builds the request sent to the backend. Fails with the status to answer the client
+/// with: 400 when the rewritten path is not a valid URI and 502 when the service target is
+/// misconfigured.
+fn build_downstream_request(
     mut parts: Parts,
-    body: Incoming,
+    body: BoxBody,
     conn_addr: SocketAddr,
-    request_id: &str,
+    ctx: &RequestContext,
     service_config: &ServiceConfig,
-) -> Result<Request<BoxBody>, GenericError> {
-    let uri = format!(
-        "{}:{}{}?{}",
-        service_config.target_service,
-        service_config.target_port,
+    state: &GatewayState,
+    span: &Span,
+) -> Result<Request<BoxBody>, StatusCode> {
+    let (path, query) = state.rewrites.rewrite(
+        service_config,
         parts.uri.path(),
-        parts.uri.query().unwrap_or("")
+        parts.uri.query(),
+        ctx.route.as_ref(),
     );
 
-    let forwarded_for = match parts.headers.get("x-forwarded-for") {
-        Some(value)
-            if ipv4::validate_ip(value.to_str().unwrap_or_default())
-                || ipv6::validate_ip(value.to_str().unwrap_or_default()) =>
-        {
-            value.clone()
-        }
-        _ => HeaderValue::from_str(&conn_addr.ip().to_string()).unwrap(),
+    let target = format!(
+        "{}:{}",
+        service_config.target_service, service_config.target_port
+    )
+    .parse::<Uri>()
+    .map_err(|_| StatusCode::BAD_GATEWAY)?;
+    let path_and_query = match query.filter(|query| !query.is_empty()) {
+        Some(query) => format!("{}?{}", path, query),
+        None => path,
     };
+    let path_and_query =
+        PathAndQuery::try_from(path_and_query).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let mut uri = Uri::builder().path_and_query(path_and_query);
+    if let Some(scheme) = target.scheme() {
+        uri = uri.scheme(scheme.clone());
+    }
+    if let Some(authority) = target.authority() {
+        uri = uri.authority(authority.clone());
+    }
+    parts.uri = uri.build().map_err(|_| StatusCode::BAD_GATEWAY)?;
+
+    strip_hop_by_hop(&mut parts.headers);
+    let listen_port = state
+        .config
+        .api_gateway_url
+        .rsplit(':')
+        .next()
+        .unwrap_or_default();
+    // Reads the Host the client asked for, so it must run before the host policy
+    state.trusted_proxies.apply_forwarding_headers(
+        &mut parts.headers,
+        conn_addr.ip(),
+        state.config.is_https,
+        listen_port,
+    );
+    apply_host_policy(
+        &mut parts.headers,
+        service_config.host_header.as_deref(),
+        &target,
+    );
+    append_via(&mut parts.headers, parts.version);
 
-    let request_id_header = HeaderValue::from_str(request_id).unwrap();
+    if service_config.grpc.is_some() {
+        // gRPC needs HTTP/2 to the backend and trailers, whatever the client spoke
+        parts.version = Version::HTTP_2;
+        parts
+            .headers
+            .insert(TE, HeaderValue::from_static("trailers"));
+    } else if parts.version == Version::HTTP_2 {
+        parts.version = Version::HTTP_11;
+    }
 
-    parts.uri = uri.parse().unwrap();
-    parts.headers.insert("x-forwarded-for", forwarded_for);
-    parts.headers.insert("x-request-id", request_id_header);
+    let request_id_header =
+        HeaderValue::from_str(&ctx.request_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    parts.headers.insert(X_REQUEST_ID, request_id_header);
+    span.inject(&mut parts.headers);
 
-    // Rebuild the request with the new URI and headers
-    let req = Request::from_parts(parts, body.boxed());
+    state
+        .header_transforms
+        .apply_request(service_config, &mut parts.headers, ctx);
 
-    Ok(req)
+    // Rebuild the request with the new URI and headers
+    Ok(Request::from_parts(parts, body))
 }
 
-async fn forward_request(req: Request<BoxBody>) -> Result<Response<BoxBody>, ()> {
-    match Client::builder(TokioExecutor::new())
+async fn forward_request(req: Request<BoxBody>) -> Result<Response<BoxBody>, ClientError> {
+    let res = Client::builder(TokioExecutor::new())
+        .http2_only(req.version() == Version::HTTP_2)
         .build_http()
         .request(req)
-        .await
-    {
-        Ok(res) => {
-            let (parts, body) = res.into_parts();
-            Ok(Response::from_parts(parts, body.boxed()))
-        }
-        Err(_) => Err(()),
-    }
+        .await?;
+    Ok(res.map(boxed))
+}
+
+fn deny_ip(
+    req: &Request<Incoming>,
+    client_ip: IpAddr,
+    rule: &str,
+    logger: &Logger,
+    request_id: &str,
+) -> Result<Response<BoxBody>, GenericError> {
+    logger.warn(
+        "IP address denied",
+        &[
+            ("request_id", request_id),
+            ("ip", client_ip.to_string().as_str()),
+            ("rule", rule),
+            ("method", req.method().as_str()),
+            ("url", req.uri().path()),
+        ],
+    );
+    let response = Response::builder()
+        .status(StatusCode::FORBIDDEN)
+        .body(full(Bytes::from("Forbidden")))
+        .unwrap();
+    Ok(response)
+}
+
+fn reject_size(
+    method: &Method,
+    uri: &Uri,
+    status: StatusCode,
+    logger: &Logger,
+    request_id: &str,
+) -> Result<Response<BoxBody>, GenericError> {
+    logger.warn(
+        "Request rejected by size limits",
+        &[
+            ("request_id", request_id),
+            ("status", status.as_str()),
+            ("method", method.as_str()),
+            ("url", uri.path()),
+        ],
+    );
+    error_response(status)
+}
+
+fn error_response(status: StatusCode) -> Result<Response<BoxBody>, GenericError> {
+    let response = Response::builder()
+        .status(status)
+        .body(full(status.canonical_reason().unwrap_or_default()))
+        .unwrap();
+    Ok(response)
 }
 
 fn not_found() -> Result<Response<BoxBody>, GenericError> {
@@ -448,3 +1115,12 @@ fn service_unavailable<T: Into<Bytes>>(reason: T) -> Result<Response<BoxBody>, G
         .unwrap();
     Ok(response)
 }
+
+fn too_many_requests_in_flight(retry_after_secs: u64) -> Result<Response<BoxBody>, GenericError> {
+    let response = Response::builder()
+        .status(StatusCode::SERVICE_UNAVAILABLE)
+        .header(RETRY_AFTER, retry_after_secs)
+        .body(full("Gateway is over capacity, retry later"))
+        .unwrap();
+    Ok(response)
+}
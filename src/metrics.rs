@@ -0,0 +1,219 @@
+use hyper::{Method, StatusCode};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+/// Upper bounds of the latency histogram buckets, in seconds.
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Traffic counters served at `GET /metrics` on the admin listener, in the Prometheus
+/// text format. Labels only take values from the config, such as service paths and route
+/// templates, or from small fixed sets, so the number of series stays bounded.
+#[derive(Default)]
+pub struct Metrics {
+    requests: Mutex<BTreeMap<String, Histogram>>,
+    auth: Mutex<BTreeMap<String, Histogram>>,
+    upstream_errors: Mutex<BTreeMap<String, u64>>,
+}
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        if let Some(bucket) = BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.buckets[bucket] += 1;
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+impl Metrics {
+    /// Counts a request answered with `status`, `route` being the route template or the
+    /// service path, never the request path.
+    pub fn observe_request(
+        &self,
+        service: &str,
+        route: &str,
+        method: &Method,
+        status: StatusCode,
+        duration: Duration,
+    ) {
+        let labels = labels(&[
+            ("service", service),
+            ("route", route),
+            ("method", method_label(method)),
+            ("status", status_class(status)),
+        ]);
+        lock(&self.requests)
+            .entry(labels)
+            .or_default()
+            .observe(duration);
+    }
+
+    /// Times a call to the authorization API, whose `outcome` is `allowed`, `denied` or
+    /// `error`.
+    pub fn observe_auth(&self, outcome: &str, duration: Duration) {
+        lock(&self.auth)
+            .entry(labels(&[("outcome", outcome)]))
+            .or_default()
+            .observe(duration);
+    }
+
+    /// Counts a request that could not be proxied, by what went wrong.
+    pub fn upstream_error(&self, service: &str, kind: &str) {
+        *lock(&self.upstream_errors)
+            .entry(labels(&[("service", service), ("kind", kind)]))
+            .or_default() += 1;
+    }
+
+    pub fn render(&self, out: &mut String) {
+        let requests = lock(&self.requests);
+        header(
+            out,
+            "hypergate_requests_total",
+            "counter",
+            "Requests answered by the gateway.",
+        );
+        for (labels, histogram) in requests.iter() {
+            let _ = writeln!(
+                out,
+                "hypergate_requests_total{{{}}} {}",
+                labels, histogram.count
+            );
+        }
+        render_histograms(
+            out,
+            "hypergate_request_duration_seconds",
+            "Time until the response head was ready.",
+            &requests,
+        );
+        drop(requests);
+
+        header(
+            out,
+            "hypergate_upstream_errors_total",
+            "counter",
+            "Requests that could not be proxied to their service.",
+        );
+        for (labels, count) in lock(&self.upstream_errors).iter() {
+            let _ = writeln!(
+                out,
+                "hypergate_upstream_errors_total{{{}}} {}",
+                labels, count
+            );
+        }
+
+        render_histograms(
+            out,
+            "hypergate_auth_duration_seconds",
+            "Calls to the authorization API, by outcome.",
+            &lock(&self.auth),
+        );
+    }
+}
+
+/// Writes a metric with a single, unlabeled value.
+pub fn render_value(out: &mut String, name: &str, kind: &str, help: &str, value: f64) {
+    header(out, name, kind, help);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+/// Writes a metric with a value per label set.
+pub fn render_values(
+    out: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    values: &[(&[(&str, &str)], f64)],
+) {
+    header(out, name, kind, help);
+    for (label_values, value) in values {
+        let _ = writeln!(out, "{}{{{}}} {}", name, labels(label_values), value);
+    }
+}
+
+fn render_histograms(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    histograms: &BTreeMap<String, Histogram>,
+) {
+    header(out, name, "histogram", help);
+    for (labels, histogram) in histograms {
+        let mut cumulative = 0;
+        for (bound, count) in BUCKETS.iter().zip(histogram.buckets) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{},le=\"{}\"}} {}",
+                name, labels, bound, cumulative
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_bucket{{{},le=\"+Inf\"}} {}",
+            name, labels, histogram.count
+        );
+        let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, histogram.sum);
+        let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, histogram.count);
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn labels(labels: &[(&str, &str)]) -> String {
+    labels
+        .iter()
+        .map(|(name, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", name, value)
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// The method, or `OTHER` for extension methods which clients can make up at will.
+fn method_label(method: &Method) -> &str {
+    match *method {
+        Method::GET
+        | Method::HEAD
+        | Method::POST
+        | Method::PUT
+        | Method::DELETE
+        | Method::PATCH
+        | Method::OPTIONS
+        | Method::CONNECT
+        | Method::TRACE => method.as_str(),
+        _ => "OTHER",
+    }
+}
+
+fn status_class(status: StatusCode) -> &'static str {
+    match status.as_u16() {
+        100..=199 => "1xx",
+        200..=299 => "2xx",
+        300..=399 => "3xx",
+        400..=499 => "4xx",
+        _ => "5xx",
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
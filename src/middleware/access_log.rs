@@ -0,0 +1,254 @@
+use crate::config::logger::Logger;
+use crate::config::parser::{AccessLogConfig, AccessLogFormat, GatewayConfig};
+use crate::utils::http::{BoxBody, BoxError};
+use chrono::Utc;
+use http_body_util::BodyExt;
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+use hyper::header::{REFERER, USER_AGENT};
+use hyper::{Request, Response, StatusCode};
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, Instant};
+
+/// What handling a request found out about it: the caller and the call to the backend.
+#[derive(Default)]
+pub struct Details {
+    pub identity: Option<String>,
+    pub upstream: Option<String>,
+    pub upstream_latency: Option<Duration>,
+}
+
+pub type AccessDetails = Arc<Mutex<Details>>;
+
+/// The access log, sampling requests at the configured rate.
+pub struct AccessLog {
+    config: Arc<AccessLogConfig>,
+    seen: AtomicU64,
+}
+
+impl AccessLog {
+    pub fn from_config(config: &GatewayConfig) -> AccessLog {
+        AccessLog {
+            config: Arc::new(config.access_log.clone()),
+            seen: AtomicU64::new(0),
+        }
+    }
+
+    pub fn identity_claim(&self) -> &str {
+        &self.config.identity_claim
+    }
+
+    /// Starts the record of a request, `None` when the access log is off.
+    pub fn start<B>(
+        &self,
+        req: &Request<B>,
+        client_ip: IpAddr,
+        request_id: &str,
+        trace_id: Option<String>,
+    ) -> Option<AccessRecord> {
+        if !self.config.enabled {
+            return None;
+        }
+        let header = |name| {
+            req.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        Some(AccessRecord {
+            started: Instant::now(),
+            method: req.method().to_string(),
+            path: req.uri().path().to_string(),
+            query: req.uri().query().map(str::to_string),
+            version: format!("{:?}", req.version()),
+            client_ip,
+            request_id: request_id.to_string(),
+            trace_id,
+            user_agent: header(USER_AGENT),
+            referer: header(REFERER),
+            details: AccessDetails::default(),
+            status: StatusCode::OK,
+            bytes: 0,
+        })
+    }
+
+    /// Logs the request once its response body has been sent, or dropped when the client
+    /// went away. Server errors are logged whatever the sample rate.
+    pub fn finish(
+        &self,
+        mut record: AccessRecord,
+        res: Response<BoxBody>,
+        logger: &Arc<Logger>,
+    ) -> Response<BoxBody> {
+        if !res.status().is_server_error() && !self.sample() {
+            return res;
+        }
+        record.status = res.status();
+        let (parts, body) = res.into_parts();
+        let body = AccessLogBody {
+            inner: body,
+            record: Some(record),
+            config: self.config.clone(),
+            logger: logger.clone(),
+        };
+        Response::from_parts(parts, body.boxed())
+    }
+
+    /// Whether to log the next request. Counting keeps the rate exact without randomness.
+    fn sample(&self) -> bool {
+        let rate = self.config.sample_rate;
+        let seen = self.seen.fetch_add(1, Ordering::Relaxed) as f64;
+        ((seen + 1.0) * rate).floor() > (seen * rate).floor()
+    }
+}
+
+/// A request being served, logged once its response is done.
+pub struct AccessRecord {
+    started: Instant,
+    method: String,
+    path: String,
+    query: Option<String>,
+    version: String,
+    client_ip: IpAddr,
+    request_id: String,
+    trace_id: Option<String>,
+    user_agent: Option<String>,
+    referer: Option<String>,
+    details: AccessDetails,
+    status: StatusCode,
+    bytes: u64,
+}
+
+impl AccessRecord {
+    /// Where handling the request notes what it finds out.
+    pub fn details(&self) -> AccessDetails {
+        self.details.clone()
+    }
+
+    fn fields(&self) -> Vec<(&'static str, String)> {
+        let details = self.details.lock().unwrap_or_else(PoisonError::into_inner);
+        let millis = |duration: Duration| format!("{:.3}", duration.as_secs_f64() * 1000.0);
+        let fields = [
+            ("request_id", Some(self.request_id.clone())),
+            ("trace_id", self.trace_id.clone()),
+            ("ip", Some(self.client_ip.to_string())),
+            ("method", Some(self.method.clone())),
+            ("path", Some(self.path.clone())),
+            ("query", self.query.clone()),
+            ("protocol", Some(self.version.clone())),
+            ("status", Some(self.status.as_u16().to_string())),
+            ("bytes", Some(self.bytes.to_string())),
+            ("upstream", details.upstream.clone()),
+            ("upstream_latency_ms", details.upstream_latency.map(millis)),
+            ("latency_ms", Some(millis(self.started.elapsed()))),
+            ("user_agent", self.user_agent.clone()),
+            ("referer", self.referer.clone()),
+            ("identity", details.identity.clone()),
+        ];
+        fields
+            .into_iter()
+            .filter_map(|(name, value)| Some((name, value?)))
+            .collect()
+    }
+}
+
+/// The request in Common Log Format, with the referer and user agent appended for the
+/// Combined Log Format. Fields left out, or dropped by redaction, are shown as `-`.
+fn common_line(fields: &[(&str, String)], combined: bool) -> String {
+    let field = |name| {
+        fields
+            .iter()
+            .find(|(field, _)| *field == name)
+            .map_or("-", |(_, value)| value.as_str())
+    };
+    let target = match field("query") {
+        "-" => field("path").to_string(),
+        query => format!("{}?{}", field("path"), query),
+    };
+    let mut line = format!(
+        "{} - {} [{}] \"{} {} {}\" {} {}",
+        field("ip"),
+        field("identity"),
+        Utc::now().format("%d/%b/%Y:%H:%M:%S %z"),
+        field("method"),
+        target,
+        field("protocol"),
+        field("status"),
+        field("bytes"),
+    );
+    if combined {
+        let quoted = |name| field(name).replace('"', "\\\"");
+        line.push_str(&format!(
+            " \"{}\" \"{}\"",
+            quoted("referer"),
+            quoted("user_agent")
+        ));
+    }
+    line
+}
+
+/// Response body counting the bytes sent, logging the request when it is dropped.
+struct AccessLogBody {
+    inner: BoxBody,
+    record: Option<AccessRecord>,
+    config: Arc<AccessLogConfig>,
+    logger: Arc<Logger>,
+}
+
+impl Body for AccessLogBody {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = &mut *self;
+        let frame = ready!(Pin::new(&mut this.inner).poll_frame(cx));
+        if let (Some(Ok(frame)), Some(record)) = (&frame, &mut this.record) {
+            record.bytes += frame.data_ref().map_or(0, |data| data.len() as u64);
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for AccessLogBody {
+    fn drop(&mut self) {
+        let Some(record) = self.record.take() else {
+            return;
+        };
+        let fields = record.fields();
+        let fields: Vec<(&str, &str)> = fields
+            .iter()
+            .map(|(name, value)| (*name, value.as_str()))
+            .collect();
+        let fields = self.logger.redact(&fields);
+        match self.config.format {
+            AccessLogFormat::Json => {
+                let selected: Vec<(&str, String)> = fields
+                    .into_iter()
+                    .filter(|(name, _)| {
+                        self.config
+                            .fields
+                            .as_ref()
+                            .is_none_or(|fields| fields.iter().any(|field| field == name))
+                    })
+                    .collect();
+                self.logger.access(&selected);
+            }
+            AccessLogFormat::Common => self.logger.access_line(common_line(&fields, false)),
+            AccessLogFormat::Combined => self.logger.access_line(common_line(&fields, true)),
+        }
+    }
+}
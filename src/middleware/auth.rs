@@ -0,0 +1,73 @@
+use crate::utils::http::{collect_limited, BoxBody};
+use hyper::Response;
+use serde_json::Value;
+use std::collections::HashMap;
+
+const MAX_AUTH_BODY_BYTES: usize = 64 * 1024;
+
+/// What the Authorization API told us about the caller: the top-level scalar fields of
+/// its JSON response and its response headers.
+#[derive(Debug, Clone, Default)]
+pub struct AuthIdentity {
+    claims: HashMap<String, String>,
+    headers: HashMap<String, String>,
+    document: String,
+}
+
+impl AuthIdentity {
+    pub async fn from_response(res: Response<BoxBody>) -> AuthIdentity {
+        let (parts, body) = res.into_parts();
+
+        let headers = parts
+            .headers
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+
+        let (claims, document) = match collect_limited(body, MAX_AUTH_BODY_BYTES).await {
+            Some(bytes) => match serde_json::from_slice::<Value>(&bytes) {
+                Ok(Value::Object(fields)) => {
+                    let document = Value::Object(fields.clone()).to_string();
+                    let claims = fields
+                        .into_iter()
+                        .filter_map(|(key, value)| Some((key, scalar(value)?)))
+                        .collect();
+                    (claims, document)
+                }
+                _ => (HashMap::new(), String::new()),
+            },
+            None => (HashMap::new(), String::new()),
+        };
+
+        AuthIdentity {
+            claims,
+            headers,
+            document,
+        }
+    }
+
+    /// The whole JSON response, nested fields included, empty when it was not an object.
+    pub fn document(&self) -> &str {
+        &self.document
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.claims
+            .get(name)
+            .or_else(|| self.headers.get(&name.to_ascii_lowercase()))
+            .map(String::as_str)
+    }
+}
+
+fn scalar(value: Value) -> Option<String> {
+    match value {
+        Value::String(value) => Some(value),
+        Value::Number(value) => Some(value.to_string()),
+        Value::Bool(value) => Some(value.to_string()),
+        Value::Array(values) => {
+            let values: Vec<String> = values.into_iter().filter_map(scalar).collect();
+            Some(values.join(","))
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,199 @@
+use crate::config::parser::{CompressionConfig, GatewayConfig};
+use crate::middleware::route_layers::RouteLayers;
+use crate::utils::http::{BodyTooLarge, BoxBody, BoxError};
+use flate2::write::GzDecoder;
+use http_body_util::BodyExt;
+use hyper::body::{Body, Bytes, Frame};
+use hyper::header::{CACHE_CONTROL, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE};
+use hyper::{HeaderMap, Response, StatusCode};
+use std::io::{self, Write};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use tower_http::compression::predicate::{Predicate, SizeAbove};
+use tower_http::compression::CompressionLayer;
+
+/// Decides which responses are compressed: large enough ones with an allowed content
+/// type. Event streams are never compressed, so their events are not held back.
+#[derive(Clone)]
+pub struct CompressionPolicy {
+    enabled: bool,
+    min_size: SizeAbove,
+    content_types: Arc<[String]>,
+}
+
+impl Predicate for CompressionPolicy {
+    fn should_compress<B>(&self, response: &Response<B>) -> bool
+    where
+        B: Body,
+    {
+        let headers = response.headers();
+        let content_type = headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        let no_transform = headers
+            .get_all(CACHE_CONTROL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .any(|value| value.contains("no-transform"));
+
+        self.enabled
+            && !matches!(
+                response.status(),
+                StatusCode::SWITCHING_PROTOCOLS | StatusCode::NO_CONTENT | StatusCode::NOT_MODIFIED
+            )
+            && !no_transform
+            && !content_type.starts_with("text/event-stream")
+            && self
+                .content_types
+                .iter()
+                .any(|allowed| content_type.starts_with(allowed.as_str()))
+            && self.min_size.should_compress(response)
+    }
+}
+
+/// The compression layer of every route, for the encodings the client accepts.
+/// Responses the backend already encoded are passed through untouched.
+pub type CompressionPolicies = RouteLayers<CompressionLayer<CompressionPolicy>>;
+
+impl CompressionPolicies {
+    pub fn from_config(config: &GatewayConfig) -> CompressionPolicies {
+        RouteLayers::build(
+            config,
+            &config.compression,
+            |s| s.compression.as_ref(),
+            build_layer,
+        )
+    }
+}
+
+fn build_layer(config: &CompressionConfig) -> CompressionLayer<CompressionPolicy> {
+    let enabled = |algorithm: &str| config.algorithms.iter().any(|name| name == algorithm);
+    CompressionLayer::new()
+        .gzip(enabled("gzip"))
+        .br(enabled("br"))
+        .zstd(enabled("zstd"))
+        .no_deflate()
+        .compress_when(CompressionPolicy {
+            enabled: config.enabled,
+            min_size: SizeAbove::new(config.min_bytes),
+            content_types: config.content_types.clone().into(),
+        })
+}
+
+/// Decompresses a `Content-Encoding: gzip` request body as it streams to the backend,
+/// failing once it inflates past `max_bytes`. Other bodies are left as they are.
+pub fn decompress_request(headers: &mut HeaderMap, body: BoxBody, max_bytes: usize) -> BoxBody {
+    let gzip = headers
+        .get(CONTENT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.trim().eq_ignore_ascii_case("gzip"));
+    if !gzip {
+        return body;
+    }
+
+    headers.remove(CONTENT_ENCODING);
+    headers.remove(CONTENT_LENGTH);
+    Gunzip {
+        inner: body,
+        decoder: GzDecoder::new(Capped {
+            buffer: Vec::new(),
+            remaining: max_bytes,
+            exceeded: false,
+        }),
+        finished: false,
+    }
+    .boxed()
+}
+
+/// Collects decompressed output, refusing to grow past its limit. The decoder inflates in
+/// small steps, so a zip bomb is stopped before it takes up memory.
+struct Capped {
+    buffer: Vec<u8>,
+    remaining: usize,
+    exceeded: bool,
+}
+
+impl Write for Capped {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if data.len() > self.remaining {
+            self.exceeded = true;
+            return Err(io::Error::other("decompressed body too large"));
+        }
+        self.remaining -= data.len();
+        self.buffer.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct Gunzip {
+    inner: BoxBody,
+    decoder: GzDecoder<Capped>,
+    finished: bool,
+}
+
+impl Gunzip {
+    fn error(&self, err: io::Error) -> BoxError {
+        match self.decoder.get_ref().exceeded {
+            true => BodyTooLarge.into(),
+            false => err.into(),
+        }
+    }
+
+    fn take_output(&mut self) -> Bytes {
+        Bytes::from(std::mem::take(&mut self.decoder.get_mut().buffer))
+    }
+}
+
+impl Body for Gunzip {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = &mut *self;
+        if this.finished {
+            return Poll::Ready(None);
+        }
+        loop {
+            match ready!(Pin::new(&mut this.inner).poll_frame(cx)) {
+                Some(Ok(frame)) => match frame.into_data() {
+                    Ok(data) => {
+                        if let Err(err) = this.decoder.write_all(&data) {
+                            this.finished = true;
+                            return Poll::Ready(Some(Err(this.error(err))));
+                        }
+                        let output = this.take_output();
+                        if !output.is_empty() {
+                            return Poll::Ready(Some(Ok(Frame::data(output))));
+                        }
+                    }
+                    Err(frame) => return Poll::Ready(Some(Ok(frame))),
+                },
+                Some(Err(err)) => return Poll::Ready(Some(Err(err))),
+                None => {
+                    this.finished = true;
+                    if let Err(err) = this.decoder.try_finish() {
+                        return Poll::Ready(Some(Err(this.error(err))));
+                    }
+                    let output = this.take_output();
+                    return match output.is_empty() {
+                        true => Poll::Ready(None),
+                        false => Poll::Ready(Some(Ok(Frame::data(output)))),
+                    };
+                }
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.finished
+    }
+}
@@ -0,0 +1,125 @@
+use crate::config::parser::{CorsConfig, GatewayConfig};
+use crate::middleware::route_layers::RouteLayers;
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::Method;
+use regex::Regex;
+use std::time::Duration;
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, Any, CorsLayer, ExposeHeaders};
+
+/// The CORS layer of every route. It answers preflights itself, so they get the policy
+/// of the route they ask about.
+pub type CorsPolicies = RouteLayers<CorsLayer>;
+
+impl CorsPolicies {
+    pub fn from_config(config: &GatewayConfig) -> CorsPolicies {
+        // Not defaulted, a forgotten policy would silently refuse every cross-origin call
+        let global = config
+            .cors
+            .as_ref()
+            .expect("No CORS policy configured, set cors.allowed_origins, empty to allow none");
+        RouteLayers::build(config, global, |s| s.cors.as_ref(), build_layer)
+    }
+}
+
+enum OriginRule {
+    Exact(String),
+    /// `https://*.example.com` is stored as the scheme and the domain suffix.
+    Subdomain(String, String),
+    Regex(Regex),
+}
+
+impl OriginRule {
+    fn parse(origin: &str) -> OriginRule {
+        if let Some(pattern) = origin.strip_prefix("regex:") {
+            let regex = Regex::new(pattern)
+                .unwrap_or_else(|err| panic!("Invalid CORS origin regex {}: {}", pattern, err));
+            return OriginRule::Regex(regex);
+        }
+        match origin.split_once("*.") {
+            Some((scheme, domain)) => {
+                OriginRule::Subdomain(scheme.to_string(), format!(".{}", domain))
+            }
+            None => OriginRule::Exact(origin.to_string()),
+        }
+    }
+
+    fn matches(&self, origin: &str) -> bool {
+        match self {
+            OriginRule::Exact(exact) => origin == exact,
+            OriginRule::Subdomain(scheme, domain) => origin
+                .strip_prefix(scheme.as_str())
+                .and_then(|host| host.strip_suffix(domain.as_str()))
+                .is_some_and(|subdomain| !subdomain.is_empty() && !subdomain.contains('/')),
+            OriginRule::Regex(regex) => regex.is_match(origin),
+        }
+    }
+}
+
+fn build_layer(config: &CorsConfig) -> CorsLayer {
+    let wildcard = |values: &[String]| values.iter().any(|value| value == "*");
+    if config.allow_credentials
+        && (wildcard(&config.allowed_origins)
+            || wildcard(&config.allowed_methods)
+            || wildcard(&config.allowed_headers)
+            || wildcard(&config.exposed_headers))
+    {
+        panic!("CORS cannot allow credentials together with a \"*\" wildcard");
+    }
+
+    let allow_origin = if wildcard(&config.allowed_origins) {
+        AllowOrigin::any()
+    } else {
+        let rules: Vec<OriginRule> = config
+            .allowed_origins
+            .iter()
+            .map(|origin| OriginRule::parse(origin))
+            .collect();
+        AllowOrigin::predicate(move |origin: &HeaderValue, _| {
+            origin
+                .to_str()
+                .is_ok_and(|origin| rules.iter().any(|rule| rule.matches(origin)))
+        })
+    };
+
+    let allow_methods = if wildcard(&config.allowed_methods) {
+        AllowMethods::any()
+    } else {
+        AllowMethods::list(config.allowed_methods.iter().map(|method| {
+            Method::from_bytes(method.as_bytes())
+                .unwrap_or_else(|_| panic!("Invalid CORS method: {}", method))
+        }))
+    };
+
+    let allow_headers = if wildcard(&config.allowed_headers) {
+        AllowHeaders::from(Any)
+    } else {
+        AllowHeaders::list(header_names(&config.allowed_headers))
+    };
+
+    let expose_headers = if wildcard(&config.exposed_headers) {
+        ExposeHeaders::from(Any)
+    } else {
+        ExposeHeaders::list(header_names(&config.exposed_headers))
+    };
+
+    let mut layer = CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods(allow_methods)
+        .allow_headers(allow_headers)
+        .expose_headers(expose_headers)
+        .allow_credentials(config.allow_credentials);
+    if let Some(max_age) = config.max_age_secs {
+        layer = layer.max_age(Duration::from_secs(max_age));
+    }
+    layer
+}
+
+fn header_names(names: &[String]) -> Vec<HeaderName> {
+    names
+        .iter()
+        .map(|name| {
+            HeaderName::from_bytes(name.as_bytes())
+                .unwrap_or_else(|_| panic!("Invalid CORS header name: {}", name))
+        })
+        .collect()
+}
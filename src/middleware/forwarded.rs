@@ -0,0 +1,183 @@
+use crate::utils::ip::{parse_cidrs, Cidr};
+use hyper::header::{HeaderName, HeaderValue, FORWARDED, HOST};
+use hyper::HeaderMap;
+use std::net::IpAddr;
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+const X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+const X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
+const X_FORWARDED_PORT: HeaderName = HeaderName::from_static("x-forwarded-port");
+
+/// Proxies in front of the gateway whose forwarding headers can be believed.
+pub struct TrustedProxies {
+    proxies: Vec<Cidr>,
+}
+
+impl TrustedProxies {
+    pub fn from_config(trusted_proxies: &[String]) -> TrustedProxies {
+        TrustedProxies {
+            proxies: parse_cidrs(trusted_proxies)
+                .into_iter()
+                .map(|(_, cidr)| cidr)
+                .collect(),
+        }
+    }
+
+    pub fn is_trusted(&self, ip: &IpAddr) -> bool {
+        self.proxies.iter().any(|cidr| cidr.contains(ip))
+    }
+
+    /// Walks the forwarding chain from right to left, starting at the peer address, and
+    /// returns the first hop that is not a trusted proxy. `x-forwarded-for` is preferred,
+    /// the `for=` parameters of `Forwarded` are used when it is absent.
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let mut client = peer;
+        if !self.is_trusted(&client) {
+            return client;
+        }
+
+        let mut hops = forwarded_for_hops(headers);
+        if hops.is_empty() {
+            hops = forwarded_hops(headers);
+        }
+
+        for hop in hops.iter().rev() {
+            match parse_node(hop) {
+                Some(ip) => client = ip,
+                // A malformed or obfuscated hop cannot be trusted, so the last trusted proxy wins
+                None => break,
+            }
+            if !self.is_trusted(&client) {
+                break;
+            }
+        }
+
+        client
+    }
+
+    /// Sets `x-forwarded-*` and `Forwarded` for the downstream request. The incoming chain
+    /// is extended when the peer is a trusted proxy and replaced otherwise, so clients
+    /// cannot inject addresses of their own.
+    pub fn apply_forwarding_headers(
+        &self,
+        headers: &mut HeaderMap,
+        peer: IpAddr,
+        is_https: bool,
+        listen_port: &str,
+    ) {
+        let trusted = self.is_trusted(&peer);
+        let proto = if is_https { "https" } else { "http" };
+        let host = headers
+            .get(HOST)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+
+        let forwarded_for = match forwarded_for_hops(headers) {
+            hops if trusted && !hops.is_empty() => format!("{}, {}", hops.join(", "), peer),
+            _ => peer.to_string(),
+        };
+        set_header(headers, X_FORWARDED_FOR, &forwarded_for);
+
+        let mut element = format!("for={};proto={}", forwarded_node(&peer), proto);
+        if let Some(host) = &host {
+            element.push_str(&format!(";host=\"{}\"", host.replace('"', "")));
+        }
+        let forwarded = match joined(headers, &FORWARDED) {
+            Some(previous) if trusted => format!("{}, {}", previous, element),
+            _ => element,
+        };
+        set_header(headers, FORWARDED, &forwarded);
+
+        if !trusted || !headers.contains_key(X_FORWARDED_PROTO) {
+            set_header(headers, X_FORWARDED_PROTO, proto);
+        }
+        if !trusted || !headers.contains_key(X_FORWARDED_HOST) {
+            match &host {
+                Some(host) => set_header(headers, X_FORWARDED_HOST, host),
+                None => {
+                    headers.remove(X_FORWARDED_HOST);
+                }
+            }
+        }
+        if !trusted || !headers.contains_key(X_FORWARDED_PORT) {
+            let port = host
+                .as_deref()
+                .and_then(host_port)
+                .unwrap_or(listen_port)
+                .to_string();
+            set_header(headers, X_FORWARDED_PORT, &port);
+        }
+    }
+}
+
+fn forwarded_for_hops(headers: &HeaderMap) -> Vec<String> {
+    headers
+        .get_all(X_FORWARDED_FOR)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|hop| hop.trim().to_string())
+        .filter(|hop| !hop.is_empty())
+        .collect()
+}
+
+/// Extracts the `for=` parameter of every `Forwarded` element, in order.
+fn forwarded_hops(headers: &HeaderMap) -> Vec<String> {
+    headers
+        .get_all(FORWARDED)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (key, value) = pair.trim().split_once('=')?;
+                key.eq_ignore_ascii_case("for")
+                    .then(|| value.trim().trim_matches('"').to_string())
+            })
+        })
+        .collect()
+}
+
+/// Parses a hop as written in either header: `1.2.3.4`, `1.2.3.4:80`, `[::1]` or `[::1]:80`.
+fn parse_node(hop: &str) -> Option<IpAddr> {
+    if let Ok(ip) = hop.parse() {
+        return Some(ip);
+    }
+    if let Some(rest) = hop.strip_prefix('[') {
+        return rest.split(']').next()?.parse().ok();
+    }
+    hop.rsplit_once(':')?.0.parse().ok()
+}
+
+/// Formats an address as a RFC 7239 node, quoting and bracketing IPv6.
+fn forwarded_node(ip: &IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => format!("\"[{}]\"", ip),
+    }
+}
+
+fn host_port(host: &str) -> Option<&str> {
+    let (_, port) = host.rsplit_once(':')?;
+    (!port.is_empty() && port.chars().all(|c| c.is_ascii_digit())).then_some(port)
+}
+
+fn joined(headers: &HeaderMap, name: &HeaderName) -> Option<String> {
+    let values: Vec<&str> = headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect();
+    (!values.is_empty()).then(|| values.join(", "))
+}
+
+fn set_header(headers: &mut HeaderMap, name: HeaderName, value: &str) {
+    match HeaderValue::from_str(value) {
+        Ok(value) => {
+            headers.insert(name, value);
+        }
+        Err(_) => {
+            headers.remove(name);
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use crate::config::parser::{GatewayConfig, HeaderRuleConfig, HeaderRulesConfig, ServiceConfig};
+use crate::context::RequestContext;
+use crate::utils::route::render_template;
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::HeaderMap;
+use std::collections::HashMap;
+
+enum HeaderRule {
+    Set(HeaderName, String),
+    Append(HeaderName, String),
+    Remove(HeaderName),
+    Rename(HeaderName, HeaderName),
+}
+
+impl HeaderRule {
+    fn from_config(config: &HeaderRuleConfig) -> HeaderRule {
+        let name = header_name(&config.name);
+        let value = || {
+            config
+                .value
+                .clone()
+                .unwrap_or_else(|| panic!("Header rule for {} needs a value", config.name))
+        };
+
+        match config.action.as_str() {
+            "set" => HeaderRule::Set(name, value()),
+            "append" => HeaderRule::Append(name, value()),
+            "remove" => HeaderRule::Remove(name),
+            "rename" => {
+                let to = config
+                    .to
+                    .as_ref()
+                    .unwrap_or_else(|| panic!("Header rename for {} needs a target", config.name));
+                HeaderRule::Rename(name, header_name(to))
+            }
+            action => panic!("Unknown header rule action: {}", action),
+        }
+    }
+
+    fn apply(&self, headers: &mut HeaderMap, ctx: &RequestContext) {
+        let render = |template: &str| {
+            HeaderValue::from_str(&render_template(template, |name| ctx.variable(name))).ok()
+        };
+
+        match self {
+            HeaderRule::Set(name, template) => match render(template) {
+                Some(value) => {
+                    headers.insert(name, value);
+                }
+                None => {
+                    headers.remove(name);
+                }
+            },
+            HeaderRule::Append(name, template) => {
+                if let Some(value) = render(template) {
+                    headers.append(name, value);
+                }
+            }
+            HeaderRule::Remove(name) => {
+                headers.remove(name);
+            }
+            HeaderRule::Rename(from, to) => {
+                let values: Vec<HeaderValue> = headers.get_all(from).iter().cloned().collect();
+                if !values.is_empty() {
+                    headers.remove(from);
+                    headers.remove(to);
+                    for value in values {
+                        headers.append(to, value);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct HeaderRules {
+    request: Vec<HeaderRule>,
+    response: Vec<HeaderRule>,
+}
+
+impl HeaderRules {
+    fn from_config(config: &HeaderRulesConfig) -> HeaderRules {
+        HeaderRules {
+            request: config.request.iter().map(HeaderRule::from_config).collect(),
+            response: config
+                .response
+                .iter()
+                .map(HeaderRule::from_config)
+                .collect(),
+        }
+    }
+}
+
+/// Declarative header rules, global ones first and then those of the route.
+pub struct HeaderTransforms {
+    global: HeaderRules,
+    services: HashMap<String, HeaderRules>,
+}
+
+impl HeaderTransforms {
+    pub fn from_config(config: &GatewayConfig) -> HeaderTransforms {
+        let services = config
+            .services
+            .iter()
+            .filter_map(|s| {
+                s.headers
+                    .as_ref()
+                    .map(|headers| (s.path.clone(), HeaderRules::from_config(headers)))
+            })
+            .collect();
+
+        HeaderTransforms {
+            global: HeaderRules::from_config(&config.headers),
+            services,
+        }
+    }
+
+    pub fn apply_request(
+        &self,
+        service_config: &ServiceConfig,
+        headers: &mut HeaderMap,
+        ctx: &RequestContext,
+    ) {
+        for rule in self.rules(service_config, |rules| &rules.request) {
+            rule.apply(headers, ctx);
+        }
+    }
+
+    pub fn apply_response(
+        &self,
+        service_config: &ServiceConfig,
+        headers: &mut HeaderMap,
+        ctx: &RequestContext,
+    ) {
+        for rule in self.rules(service_config, |rules| &rules.response) {
+            rule.apply(headers, ctx);
+        }
+    }
+
+    fn rules<'a>(
+        &'a self,
+        service_config: &ServiceConfig,
+        select: fn(&HeaderRules) -> &Vec<HeaderRule>,
+    ) -> impl Iterator<Item = &'a HeaderRule> {
+        let service = self.services.get(&service_config.path);
+        select(&self.global)
+            .iter()
+            .chain(service.map(select).into_iter().flatten())
+    }
+}
+
+fn header_name(name: &str) -> HeaderName {
+    HeaderName::from_bytes(name.as_bytes())
+        .unwrap_or_else(|_| panic!("Invalid header name in header rules: {}", name))
+}
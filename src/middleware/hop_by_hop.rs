@@ -0,0 +1,75 @@
+use hyper::header::{
+    HeaderName, HeaderValue, CONNECTION, HOST, TE, TRAILER, TRANSFER_ENCODING, UPGRADE, VIA,
+};
+use hyper::{HeaderMap, Uri, Version};
+
+const VIA_PSEUDONYM: &str = "hypergate";
+
+/// Headers that only apply to a single connection, RFC 7230 section 6.1.
+const HOP_BY_HOP: [HeaderName; 9] = [
+    CONNECTION,
+    HeaderName::from_static("keep-alive"),
+    HeaderName::from_static("proxy-connection"),
+    HeaderName::from_static("proxy-authenticate"),
+    HeaderName::from_static("proxy-authorization"),
+    TE,
+    TRAILER,
+    TRANSFER_ENCODING,
+    UPGRADE,
+];
+
+/// Removes hop-by-hop headers, including any header named by `Connection`.
+pub fn strip_hop_by_hop(headers: &mut HeaderMap) {
+    let listed: Vec<HeaderName> = headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+        .collect();
+
+    for name in listed.iter().chain(HOP_BY_HOP.iter()) {
+        headers.remove(name);
+    }
+}
+
+/// Appends this gateway to the `Via` chain of a forwarded message.
+pub fn append_via(headers: &mut HeaderMap, version: Version) {
+    let protocol = match version {
+        Version::HTTP_09 => "0.9",
+        Version::HTTP_10 => "1.0",
+        Version::HTTP_2 => "2",
+        Version::HTTP_3 => "3",
+        _ => "1.1",
+    };
+    let mut hops: Vec<&str> = headers
+        .get_all(VIA)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect();
+    let hop = format!("{} {}", protocol, VIA_PSEUDONYM);
+    hops.push(&hop);
+
+    let value = HeaderValue::from_str(&hops.join(", ")).unwrap();
+    headers.insert(VIA, value);
+}
+
+/// Sets `Host` according to the service's `host_header` policy: `target` (the default)
+/// uses the backend authority, `preserve` keeps the client's and anything else is sent
+/// as is.
+pub fn apply_host_policy(headers: &mut HeaderMap, policy: Option<&str>, target: &Uri) {
+    let host = match policy {
+        Some("preserve") => return,
+        None | Some("target") => target.authority().map(|authority| authority.to_string()),
+        Some(host) => Some(host.to_string()),
+    };
+
+    match host.and_then(|host| HeaderValue::from_str(&host).ok()) {
+        Some(value) => {
+            headers.insert(HOST, value);
+        }
+        None => {
+            headers.remove(HOST);
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use crate::config::parser::{GatewayConfig, IpFilterConfig, ServiceConfig};
+use crate::utils::ip::{parse_cidrs, Cidr};
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+struct IpRules {
+    allow: Vec<(String, Cidr)>,
+    deny: Vec<(String, Cidr)>,
+}
+
+impl IpRules {
+    fn from_config(config: &IpFilterConfig) -> IpRules {
+        IpRules {
+            allow: parse_cidrs(&config.allow),
+            deny: parse_cidrs(&config.deny),
+        }
+    }
+
+    /// Deny rules win over allow rules. On rejection returns the rule that matched.
+    fn check(&self, ip: &IpAddr) -> Result<(), String> {
+        if let Some((rule, _)) = self.deny.iter().find(|(_, cidr)| cidr.contains(ip)) {
+            return Err(format!("deny {}", rule));
+        }
+        if !self.allow.is_empty() && !self.allow.iter().any(|(_, cidr)| cidr.contains(ip)) {
+            return Err("not in allow list".to_string());
+        }
+        Ok(())
+    }
+}
+
+pub struct IpFilter {
+    global: IpRules,
+    services: HashMap<String, IpRules>,
+}
+
+impl IpFilter {
+    pub fn from_config(config: &GatewayConfig) -> IpFilter {
+        let services = config
+            .services
+            .iter()
+            .filter_map(|s| {
+                s.ip_filter
+                    .as_ref()
+                    .map(|filter| (s.path.clone(), IpRules::from_config(filter)))
+            })
+            .collect();
+
+        IpFilter {
+            global: IpRules::from_config(&config.ip_filter),
+            services,
+        }
+    }
+
+    /// Checks the global rules, then those of the route when there is one.
+    pub fn check(&self, service_config: Option<&ServiceConfig>, ip: &IpAddr) -> Result<(), String> {
+        self.global.check(ip)?;
+        match service_config.and_then(|service_config| self.services.get(&service_config.path)) {
+            Some(rules) => rules.check(ip),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use crate::config::parser::{GatewayConfig, ServiceConfig};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+
+/// A bounded pool of permits with a bounded number of waiters.
+pub struct Limit {
+    semaphore: Arc<Semaphore>,
+    max: usize,
+    waiting: AtomicUsize,
+    shed: AtomicU64,
+}
+
+impl Limit {
+    pub fn new(max: usize) -> Limit {
+        Limit {
+            semaphore: Arc::new(Semaphore::new(max)),
+            max,
+            waiting: AtomicUsize::new(0),
+            shed: AtomicU64::new(0),
+        }
+    }
+
+    pub fn try_acquire(&self) -> Option<OwnedSemaphorePermit> {
+        match self.semaphore.clone().try_acquire_owned() {
+            Ok(permit) => Some(permit),
+            Err(_) => {
+                self.shed.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// Waits for a permit if there is room in the queue, giving up at `deadline`.
+    pub async fn acquire(
+        &self,
+        queue_size: usize,
+        deadline: Instant,
+    ) -> Option<OwnedSemaphorePermit> {
+        if let Ok(permit) = self.semaphore.clone().try_acquire_owned() {
+            return Some(permit);
+        }
+
+        // Leaves the queue however the wait ends, including when the request is dropped
+        let waiting = Waiting::join(&self.waiting);
+        if waiting.position >= queue_size {
+            self.shed.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+
+        let permit =
+            tokio::time::timeout_at(deadline, self.semaphore.clone().acquire_owned()).await;
+
+        match permit {
+            Ok(Ok(permit)) => Some(permit),
+            _ => {
+                self.shed.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    pub fn in_use(&self) -> usize {
+        self.max - self.semaphore.available_permits()
+    }
+
+    pub fn usage(&self) -> Value {
+        json!({
+            "in_use": self.in_use(),
+            "max": self.max,
+            "waiting": self.waiting.load(Ordering::Relaxed),
+            "shed": self.shed.load(Ordering::Relaxed),
+        })
+    }
+}
+
+/// A place in the queue of a `Limit`, given up when dropped.
+struct Waiting<'a> {
+    waiting: &'a AtomicUsize,
+    position: usize,
+}
+
+impl<'a> Waiting<'a> {
+    fn join(waiting: &'a AtomicUsize) -> Waiting<'a> {
+        Waiting {
+            position: waiting.fetch_add(1, Ordering::AcqRel),
+            waiting,
+        }
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.waiting.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Permits held for the lifetime of a proxied request.
+pub struct RequestPermit {
+    _global: OwnedSemaphorePermit,
+    _service: Option<OwnedSemaphorePermit>,
+}
+
+pub struct Limiter {
+    connections: Limit,
+    in_flight: Limit,
+    services: HashMap<String, Limit>,
+    queue_size: usize,
+    queue_timeout: Duration,
+    retry_after_secs: u64,
+}
+
+impl Limiter {
+    pub fn from_config(config: &GatewayConfig) -> Limiter {
+        let services = config
+            .services
+            .iter()
+            .filter_map(|s| s.max_in_flight.map(|max| (s.path.clone(), Limit::new(max))))
+            .collect();
+
+        Limiter {
+            connections: Limit::new(config.limits.max_connections),
+            in_flight: Limit::new(config.limits.max_in_flight),
+            services,
+            queue_size: config.limits.queue_size,
+            queue_timeout: Duration::from_millis(config.limits.queue_timeout_ms),
+            retry_after_secs: config.limits.retry_after_secs,
+        }
+    }
+
+    pub fn try_connection(&self) -> Option<OwnedSemaphorePermit> {
+        self.connections.try_acquire()
+    }
+
+    /// Unauthenticated requests never queue, so they are the first to be shed under load.
+    /// The service permit comes first, so a saturated service queues its own requests
+    /// without holding global permits the other services need. Both waits share one
+    /// queue timeout.
+    pub async fn acquire(
+        &self,
+        service_config: &ServiceConfig,
+        authenticated: bool,
+    ) -> Option<RequestPermit> {
+        let queue_size = if authenticated { self.queue_size } else { 0 };
+        let deadline = Instant::now() + self.queue_timeout;
+
+        let service = match self.services.get(&service_config.path) {
+            Some(limit) => Some(limit.acquire(queue_size, deadline).await?),
+            None => None,
+        };
+        let global = self.in_flight.acquire(queue_size, deadline).await?;
+
+        Some(RequestPermit {
+            _global: global,
+            _service: service,
+        })
+    }
+
+    /// Client connections open on the public listener.
+    pub fn open_connections(&self) -> usize {
+        self.connections.in_use()
+    }
+
+    /// Requests being proxied, until their response body has been sent.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.in_use()
+    }
+
+    pub fn retry_after_secs(&self) -> u64 {
+        self.retry_after_secs
+    }
+
+    pub fn usage(&self) -> Value {
+        let services: HashMap<_, _> = self
+            .services
+            .iter()
+            .map(|(path, limit)| (path.clone(), limit.usage()))
+            .collect();
+
+        json!({
+            "connections": self.connections.usage(),
+            "in_flight": self.in_flight.usage(),
+            "services": services,
+        })
+    }
+}
@@ -0,0 +1,14 @@
+pub mod access_log;
+pub mod auth;
+pub mod compression;
+pub mod cors;
+pub mod forwarded;
+pub mod headers;
+pub mod hop_by_hop;
+pub mod ip_filter;
+pub mod limits;
+pub mod request_id;
+pub mod rewrite;
+pub mod route_layers;
+pub mod security_headers;
+pub mod size_limits;
@@ -0,0 +1,68 @@
+use crate::config::parser::{GatewayConfig, RequestIdConfig, RequestIdFormat};
+use crate::middleware::forwarded::TrustedProxies;
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::HeaderMap;
+use std::net::IpAddr;
+use ulid::Ulid;
+use uuid::Uuid;
+
+pub const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Gives every request its own id, keeping the one set by a trusted load balancer or
+/// client when configured to.
+pub struct RequestIds {
+    config: RequestIdConfig,
+    trusted_sources: TrustedProxies,
+}
+
+impl RequestIds {
+    pub fn from_config(config: &GatewayConfig) -> RequestIds {
+        let trusted_sources = config
+            .request_id
+            .trusted_sources
+            .as_ref()
+            .unwrap_or(&config.trusted_proxies);
+        RequestIds {
+            config: config.request_id.clone(),
+            trusted_sources: TrustedProxies::from_config(trusted_sources),
+        }
+    }
+
+    /// The id of a request sent by `peer`: its inbound `x-request-id` when that is
+    /// accepted and valid, a new one otherwise.
+    pub fn for_request(&self, peer: IpAddr, headers: &HeaderMap) -> String {
+        let inbound = headers
+            .get(X_REQUEST_ID)
+            .and_then(|value| value.to_str().ok())
+            .filter(|id| self.is_valid(id));
+        match inbound {
+            Some(id) if self.config.accept_inbound && self.trusted_sources.is_trusted(&peer) => {
+                id.to_string()
+            }
+            _ => self.generate(),
+        }
+    }
+
+    pub fn generate(&self) -> String {
+        match self.config.format {
+            RequestIdFormat::Uuidv4 => Uuid::new_v4().to_string(),
+            RequestIdFormat::Uuidv7 => Uuid::now_v7().to_string(),
+            RequestIdFormat::Ulid => Ulid::new().to_string(),
+        }
+    }
+
+    /// Echoes the id to the client.
+    pub fn apply(&self, request_id: &str, headers: &mut HeaderMap) {
+        if let Ok(value) = HeaderValue::from_str(request_id) {
+            headers.insert(X_REQUEST_ID, value);
+        }
+    }
+
+    fn is_valid(&self, id: &str) -> bool {
+        !id.is_empty()
+            && id.len() <= self.config.max_length
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'))
+    }
+}
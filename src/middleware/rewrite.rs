@@ -0,0 +1,132 @@
+use crate::config::parser::{GatewayConfig, RewriteConfig, ServiceConfig};
+use crate::utils::route::{render_template, RouteMatch};
+use regex::Regex;
+use std::collections::HashMap;
+
+/// Path and query rewrites of a single service, compiled at startup.
+struct Rewrite {
+    config: RewriteConfig,
+    regex: Option<Regex>,
+}
+
+impl Rewrite {
+    fn from_config(service_config: &ServiceConfig, config: &RewriteConfig) -> Rewrite {
+        let regex = config.regex.as_ref().map(|regex| {
+            Regex::new(regex)
+                .unwrap_or_else(|err| panic!("Invalid rewrite regex {}: {}", regex, err))
+        });
+        if config.template.is_some() && service_config.route.is_none() {
+            panic!(
+                "Rewrite template for {} needs a route to take params from",
+                service_config.path
+            );
+        }
+
+        Rewrite {
+            config: config.clone(),
+            regex,
+        }
+    }
+
+    /// Applies, in order: the route template, prefix stripping, the regex replacement
+    /// and prefix adding.
+    fn path(&self, path: &str, route_match: Option<&RouteMatch>) -> String {
+        let mut path = path.to_string();
+
+        if let (Some(template), Some(route_match)) = (&self.config.template, route_match) {
+            let rendered = render_template(template, |name| route_match.params.get(name).cloned());
+            path = format!("{}{}", rendered, route_match.rest);
+        }
+
+        if let Some(prefix) = &self.config.strip_prefix {
+            if let Some(stripped) = path.strip_prefix(prefix.trim_end_matches('/')) {
+                if stripped.is_empty() || stripped.starts_with('/') {
+                    path = stripped.to_string();
+                }
+            }
+        }
+
+        if let Some(regex) = &self.regex {
+            let replacement = self.config.replacement.as_deref().unwrap_or_default();
+            path = regex.replace(&path, replacement).into_owned();
+        }
+
+        if let Some(prefix) = &self.config.add_prefix {
+            path = format!("{}{}", prefix.trim_end_matches('/'), path);
+        }
+
+        if !path.starts_with('/') {
+            path.insert(0, '/');
+        }
+        path
+    }
+
+    fn query(&self, query: Option<&str>) -> Option<String> {
+        let query_config = &self.config.query;
+        // Leave the original encoding alone when there is nothing to change
+        if query_config.add.is_empty()
+            && query_config.remove.is_empty()
+            && query_config.rename.is_empty()
+        {
+            return query.map(str::to_string);
+        }
+
+        let mut pairs: Vec<(String, String)> =
+            form_urlencoded::parse(query.unwrap_or_default().as_bytes())
+                .into_owned()
+                .filter(|(key, _)| !query_config.remove.contains(key))
+                .map(|(key, value)| match query_config.rename.get(&key) {
+                    Some(renamed) => (renamed.clone(), value),
+                    None => (key, value),
+                })
+                .collect();
+
+        for (key, value) in &query_config.add {
+            pairs.retain(|(existing, _)| existing != key);
+            pairs.push((key.clone(), value.clone()));
+        }
+
+        if pairs.is_empty() {
+            return None;
+        }
+        Some(
+            form_urlencoded::Serializer::new(String::new())
+                .extend_pairs(pairs)
+                .finish(),
+        )
+    }
+}
+
+pub struct Rewrites {
+    services: HashMap<String, Rewrite>,
+}
+
+impl Rewrites {
+    pub fn from_config(config: &GatewayConfig) -> Rewrites {
+        let services = config
+            .services
+            .iter()
+            .filter_map(|s| {
+                s.rewrite
+                    .as_ref()
+                    .map(|rewrite| (s.path.clone(), Rewrite::from_config(s, rewrite)))
+            })
+            .collect();
+
+        Rewrites { services }
+    }
+
+    /// Returns the downstream path and query for a request to `service_config`.
+    pub fn rewrite(
+        &self,
+        service_config: &ServiceConfig,
+        path: &str,
+        query: Option<&str>,
+        route_match: Option<&RouteMatch>,
+    ) -> (String, Option<String>) {
+        match self.services.get(&service_config.path) {
+            Some(rewrite) => (rewrite.path(path, route_match), rewrite.query(query)),
+            None => (path.to_string(), query.map(str::to_string)),
+        }
+    }
+}
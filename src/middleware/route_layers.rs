@@ -0,0 +1,36 @@
+use crate::config::parser::{GatewayConfig, ServiceConfig};
+use std::collections::HashMap;
+
+/// A tower layer for every route, built once at startup: the global one, or the route's
+/// own when its config overrides the global config.
+pub struct RouteLayers<L> {
+    global: L,
+    services: HashMap<String, L>,
+}
+
+impl<L: Clone> RouteLayers<L> {
+    pub fn build<C>(
+        config: &GatewayConfig,
+        global: &C,
+        service_config: impl Fn(&ServiceConfig) -> Option<&C>,
+        build_layer: impl Fn(&C) -> L,
+    ) -> RouteLayers<L> {
+        let services = config
+            .services
+            .iter()
+            .filter_map(|s| service_config(s).map(|config| (s.path.clone(), build_layer(config))))
+            .collect();
+
+        RouteLayers {
+            global: build_layer(global),
+            services,
+        }
+    }
+
+    pub fn for_service(&self, service_path: Option<&str>) -> L {
+        service_path
+            .and_then(|path| self.services.get(path))
+            .unwrap_or(&self.global)
+            .clone()
+    }
+}
@@ -0,0 +1,98 @@
+use crate::config::parser::{GatewayConfig, SecurityHeadersConfig};
+use hyper::header::{
+    HeaderName, HeaderValue, CONTENT_SECURITY_POLICY, REFERRER_POLICY, STRICT_TRANSPORT_SECURITY,
+    X_CONTENT_TYPE_OPTIONS, X_FRAME_OPTIONS,
+};
+use hyper::HeaderMap;
+use std::collections::HashMap;
+
+const PERMISSIONS_POLICY: HeaderName = HeaderName::from_static("permissions-policy");
+
+/// Headers to set on, and to strip from, every response of a route.
+pub struct HeaderPolicy {
+    set: Vec<(HeaderName, HeaderValue)>,
+    strip: Vec<HeaderName>,
+}
+
+impl HeaderPolicy {
+    fn from_config(config: &SecurityHeadersConfig, is_https: bool) -> HeaderPolicy {
+        let hsts = if is_https { config.hsts.as_ref() } else { None };
+        let set = [
+            (STRICT_TRANSPORT_SECURITY, hsts),
+            (X_CONTENT_TYPE_OPTIONS, config.content_type_options.as_ref()),
+            (X_FRAME_OPTIONS, config.frame_options.as_ref()),
+            (REFERRER_POLICY, config.referrer_policy.as_ref()),
+            (PERMISSIONS_POLICY, config.permissions_policy.as_ref()),
+            (
+                CONTENT_SECURITY_POLICY,
+                config.content_security_policy.as_ref(),
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| {
+            let value = HeaderValue::from_str(value?)
+                .unwrap_or_else(|_| panic!("Invalid value for security header {}", name));
+            Some((name, value))
+        })
+        .collect();
+
+        let strip = config
+            .strip_headers
+            .iter()
+            .map(|name| {
+                HeaderName::from_bytes(name.as_bytes())
+                    .unwrap_or_else(|_| panic!("Invalid header name to strip: {}", name))
+            })
+            .collect();
+
+        HeaderPolicy { set, strip }
+    }
+
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        for name in &self.strip {
+            headers.remove(name);
+        }
+        for (name, value) in &self.set {
+            headers.insert(name, value.clone());
+        }
+    }
+}
+
+pub struct SecurityHeaders {
+    global: HeaderPolicy,
+    docs: HeaderPolicy,
+    services: HashMap<String, HeaderPolicy>,
+}
+
+impl SecurityHeaders {
+    pub fn from_config(config: &GatewayConfig) -> SecurityHeaders {
+        let services = config
+            .services
+            .iter()
+            .filter_map(|s| {
+                s.security_headers.as_ref().map(|headers| {
+                    (
+                        s.path.clone(),
+                        HeaderPolicy::from_config(headers, config.is_https),
+                    )
+                })
+            })
+            .collect();
+
+        SecurityHeaders {
+            global: HeaderPolicy::from_config(&config.security_headers, config.is_https),
+            docs: HeaderPolicy::from_config(&config.docs_security_headers, config.is_https),
+            services,
+        }
+    }
+
+    pub fn docs(&self) -> &HeaderPolicy {
+        &self.docs
+    }
+
+    pub fn for_service(&self, service_path: Option<&str>) -> &HeaderPolicy {
+        service_path
+            .and_then(|path| self.services.get(path))
+            .unwrap_or(&self.global)
+    }
+}
@@ -0,0 +1,59 @@
+use crate::config::parser::{LimitsConfig, ServiceConfig};
+use hyper::header::CONTENT_LENGTH;
+use hyper::{Request, StatusCode};
+
+/// Checks the request target and headers, returning the status to reject with.
+pub fn check_head<B>(req: &Request<B>, limits: &LimitsConfig) -> Result<(), StatusCode> {
+    let uri_length = req
+        .uri()
+        .path_and_query()
+        .map_or(0, |path| path.as_str().len());
+    if uri_length > limits.max_uri_length {
+        return Err(StatusCode::URI_TOO_LONG);
+    }
+
+    let header_bytes: usize = req
+        .headers()
+        .iter()
+        .map(|(name, value)| name.as_str().len() + value.len())
+        .sum();
+    if req.headers().len() > limits.max_headers || header_bytes > limits.max_header_bytes {
+        return Err(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+    }
+
+    Ok(())
+}
+
+/// The most a request head within the limits takes on the wire: the request line, then
+/// `: ` and a line break around every header.
+pub fn max_head_bytes(limits: &LimitsConfig) -> usize {
+    limits.max_uri_length + 64 + limits.max_header_bytes + 4 * limits.max_headers
+}
+
+/// The same bound in HTTP/2 terms, which count 32 bytes per header, pseudo-headers
+/// such as `:path` included.
+pub fn max_header_list_size(limits: &LimitsConfig) -> u32 {
+    let size = limits.max_uri_length + limits.max_header_bytes + 32 * (limits.max_headers + 4);
+    u32::try_from(size).unwrap_or(u32::MAX)
+}
+
+pub fn max_body_bytes(service_config: &ServiceConfig, limits: &LimitsConfig) -> usize {
+    service_config
+        .max_body_bytes
+        .unwrap_or(limits.max_body_bytes)
+}
+
+/// Rejects early when the declared length is already over the limit. Bodies without
+/// a usable Content-Length are limited while streaming instead.
+pub fn check_content_length<B>(req: &Request<B>, max_body_bytes: usize) -> Result<(), StatusCode> {
+    let content_length = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
+
+    match content_length {
+        Some(length) if length > max_body_bytes => Err(StatusCode::PAYLOAD_TOO_LARGE),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,571 @@
+use crate::config::parser::{GatewayConfig, RouteCacheConfig, ServiceConfig};
+use crate::context::RequestContext;
+use crate::proxy::streaming::{is_event_stream, is_streaming_request};
+use crate::utils::http::{full, BoxBody, BoxError};
+use bytes::BytesMut;
+use http_body_util::BodyExt;
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+use hyper::header::{
+    HeaderName, HeaderValue, AGE, AUTHORIZATION, CACHE_CONTROL, CONTENT_LENGTH, COOKIE, ETAG,
+    IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, SET_COOKIE, UPGRADE, VARY,
+};
+use hyper::{HeaderMap, Method, Request, Response, StatusCode};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, Instant};
+
+const X_CACHE: &str = "x-cache";
+
+/// Statuses that may be stored without explicit freshness, RFC 9110 section 15.1.
+const CACHEABLE: [StatusCode; 7] = [
+    StatusCode::OK,
+    StatusCode::NON_AUTHORITATIVE_INFORMATION,
+    StatusCode::NO_CONTENT,
+    StatusCode::MULTIPLE_CHOICES,
+    StatusCode::MOVED_PERMANENTLY,
+    StatusCode::NOT_FOUND,
+    StatusCode::GONE,
+];
+
+/// The request header values a stored response was selected with, from its `Vary`.
+type Variant = Vec<(HeaderName, Option<HeaderValue>)>;
+
+/// Where a request to a caching route goes in the cache.
+pub struct CacheKey {
+    key: String,
+    route: String,
+    /// The key tells callers apart, so private responses may be stored under it
+    scoped: bool,
+    /// The client asked for a response from the backend
+    bypass: bool,
+    no_store: bool,
+    config: RouteCacheConfig,
+}
+
+#[derive(Clone)]
+struct Entry {
+    route: String,
+    variant: Variant,
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    stored_at: Instant,
+    ttl: Duration,
+    size: usize,
+    last_used: u64,
+}
+
+impl Entry {
+    fn matches(&self, headers: &HeaderMap) -> bool {
+        self.variant
+            .iter()
+            .all(|(name, value)| headers.get(name) == value.as_ref())
+    }
+
+    fn is_fresh(&self) -> bool {
+        self.stored_at.elapsed() < self.ttl
+    }
+
+    fn response(&self) -> Response<BoxBody> {
+        let mut res = Response::new(full(self.body.clone()));
+        *res.status_mut() = self.status;
+        *res.headers_mut() = self.headers.clone();
+        let age = self.stored_at.elapsed().as_secs();
+        res.headers_mut().insert(AGE, HeaderValue::from(age));
+        res.headers_mut()
+            .insert(X_CACHE, HeaderValue::from_static("HIT"));
+        res
+    }
+
+    /// Whether the client's `If-None-Match` already names this entry.
+    fn is_not_modified(&self, headers: &HeaderMap) -> bool {
+        let etag = match self.headers.get(ETAG).and_then(|etag| etag.to_str().ok()) {
+            Some(etag) => etag.trim_start_matches("W/"),
+            None => return false,
+        };
+        headers
+            .get_all(IF_NONE_MATCH)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|tag| tag.trim())
+            .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+    }
+}
+
+/// A stored response past its freshness that can be revalidated with the backend.
+pub struct Stale {
+    entry: Entry,
+}
+
+impl Stale {
+    /// Makes the backend request conditional on the stored validators.
+    pub fn add_validators(&self, headers: &mut HeaderMap) {
+        if let Some(etag) = self.entry.headers.get(ETAG) {
+            headers.insert(IF_NONE_MATCH, etag.clone());
+        }
+        if let Some(last_modified) = self.entry.headers.get(LAST_MODIFIED) {
+            headers.insert(IF_MODIFIED_SINCE, last_modified.clone());
+        }
+    }
+}
+
+pub enum Lookup {
+    Hit(Response<BoxBody>),
+    Stale(Stale),
+    Miss,
+}
+
+#[derive(Default)]
+struct Store {
+    entries: HashMap<String, Vec<Entry>>,
+    bytes: usize,
+    clock: u64,
+}
+
+impl Store {
+    /// Drops the entries `drop` selects, returning how many were dropped.
+    fn remove(&mut self, drop: impl Fn(&str, &Entry) -> bool) -> usize {
+        let mut removed = 0;
+        let mut freed = 0;
+        for (key, entries) in self.entries.iter_mut() {
+            entries.retain(|entry| {
+                let dropped = drop(key, entry);
+                if dropped {
+                    removed += 1;
+                    freed += entry.size;
+                }
+                !dropped
+            });
+        }
+        self.entries.retain(|_, entries| !entries.is_empty());
+        self.bytes -= freed;
+        removed
+    }
+
+    fn evict_least_recently_used(&mut self) -> bool {
+        let oldest = self
+            .entries
+            .values()
+            .flatten()
+            .map(|entry| entry.last_used)
+            .min();
+        match oldest {
+            Some(oldest) => self.remove(|_, entry| entry.last_used == oldest) > 0,
+            None => false,
+        }
+    }
+}
+
+/// In-memory HTTP cache for GET responses of the routes that opt in, bounded by the total
+/// size of the stored responses. The least recently used entries are evicted first.
+pub struct ResponseCache {
+    store: Mutex<Store>,
+    max_bytes: usize,
+    max_entry_bytes: usize,
+}
+
+impl ResponseCache {
+    pub fn from_config(config: &GatewayConfig) -> ResponseCache {
+        ResponseCache {
+            store: Mutex::new(Store::default()),
+            max_bytes: config.cache.max_bytes,
+            max_entry_bytes: config.cache.max_entry_bytes,
+        }
+    }
+
+    /// The cache key of a request, `None` when the route does not cache or the request is
+    /// not a plain GET: streams and upgrades are never cached.
+    pub fn key<B>(
+        &self,
+        service_config: &ServiceConfig,
+        req: &Request<B>,
+        ctx: &RequestContext,
+    ) -> Option<CacheKey> {
+        let config = service_config.cache.as_ref()?;
+        if req.method() != Method::GET
+            || req.headers().contains_key(UPGRADE)
+            || is_streaming_request(service_config, req.headers())
+        {
+            return None;
+        }
+
+        let path = req.uri().path_and_query().map_or("/", |path| path.as_str());
+        let (caller, scoped) = caller(ctx, config.key_claims.as_deref(), req.headers());
+        let key = match caller.is_empty() {
+            true => path.to_string(),
+            false => format!("{} #{}", path, caller),
+        };
+        let directives = directives(req.headers());
+        let no_store = directives.contains_key("no-store");
+
+        Some(CacheKey {
+            key,
+            route: service_config.path.clone(),
+            scoped,
+            bypass: no_store
+                || directives.contains_key("no-cache")
+                || seconds(&directives, "max-age") == Some(0),
+            no_store,
+            config: config.clone(),
+        })
+    }
+
+    pub fn lookup(&self, key: &CacheKey, headers: &HeaderMap) -> Lookup {
+        if key.bypass {
+            return Lookup::Miss;
+        }
+        let mut store = self.store.lock().unwrap();
+        store.clock += 1;
+        let clock = store.clock;
+        let entry = match store
+            .entries
+            .get_mut(&key.key)
+            .and_then(|entries| entries.iter_mut().find(|entry| entry.matches(headers)))
+        {
+            Some(entry) => entry,
+            None => return Lookup::Miss,
+        };
+
+        if entry.is_fresh() {
+            entry.last_used = clock;
+            let mut res = entry.response();
+            if entry.is_not_modified(headers) {
+                *res.status_mut() = StatusCode::NOT_MODIFIED;
+                *res.body_mut() = BoxBody::default();
+                res.headers_mut().remove(CONTENT_LENGTH);
+            }
+            return Lookup::Hit(res);
+        }
+
+        // Clients revalidating their own copy are answered by the backend
+        let conditional =
+            headers.contains_key(IF_NONE_MATCH) || headers.contains_key(IF_MODIFIED_SINCE);
+        if has_validators(&entry.headers) && !conditional {
+            Lookup::Stale(Stale {
+                entry: entry.clone(),
+            })
+        } else {
+            Lookup::Miss
+        }
+    }
+
+    /// Serves a stale entry the backend confirmed with a 304, taking the freshness and
+    /// headers of the 304.
+    pub fn refresh(
+        &self,
+        key: &CacheKey,
+        stale: Stale,
+        not_modified: &Response<BoxBody>,
+    ) -> Response<BoxBody> {
+        let mut entry = stale.entry;
+        for name in not_modified.headers().keys() {
+            if name != CONTENT_LENGTH {
+                entry.headers.remove(name);
+            }
+        }
+        for (name, value) in not_modified.headers() {
+            if name != CONTENT_LENGTH {
+                entry.headers.append(name, value.clone());
+            }
+        }
+        entry.stored_at = Instant::now();
+
+        match freshness(key, entry.status, &entry.headers) {
+            Some(ttl) => {
+                entry.ttl = ttl;
+                let res = entry.response();
+                self.insert(key.key.clone(), entry);
+                res
+            }
+            None => {
+                let variant = entry.variant.clone();
+                self.store.lock().unwrap().remove(|stored_key, stored| {
+                    stored_key == key.key && stored.variant == variant
+                });
+                entry.response()
+            }
+        }
+    }
+
+    /// Passes a backend response on, storing it once its body has been read to the end
+    /// when it may be stored.
+    pub fn store(
+        self: &Arc<Self>,
+        key: CacheKey,
+        req_headers: &HeaderMap,
+        mut res: Response<BoxBody>,
+    ) -> Response<BoxBody> {
+        let headers = res.headers().clone();
+        res.headers_mut()
+            .insert(X_CACHE, HeaderValue::from_static("MISS"));
+        if key.no_store || is_event_stream(&headers) {
+            return res;
+        }
+        let ttl = match freshness(&key, res.status(), &headers) {
+            Some(ttl) => ttl,
+            None => return res,
+        };
+        let variant = match variant(&headers, req_headers) {
+            Some(variant) => variant,
+            None => return res,
+        };
+        if res.body().size_hint().lower() > self.max_entry_bytes as u64 {
+            return res;
+        }
+
+        let entry = Entry {
+            route: key.route,
+            variant,
+            status: res.status(),
+            headers,
+            body: Bytes::new(),
+            stored_at: Instant::now(),
+            ttl,
+            size: 0,
+            last_used: 0,
+        };
+        let (parts, body) = res.into_parts();
+        if body.is_end_stream() {
+            self.insert(key.key, entry);
+            return Response::from_parts(parts, body);
+        }
+        let fill = CacheFill {
+            inner: body,
+            cache: self.clone(),
+            key: key.key,
+            entry: Some(entry),
+            buffer: BytesMut::new(),
+        };
+        Response::from_parts(parts, fill.boxed())
+    }
+
+    fn insert(&self, key: String, mut entry: Entry) {
+        entry.size = key.len()
+            + entry.body.len()
+            + entry
+                .headers
+                .iter()
+                .map(|(name, value)| name.as_str().len() + value.len())
+                .sum::<usize>();
+        if entry.size > self.max_entry_bytes {
+            return;
+        }
+
+        let mut store = self.store.lock().unwrap();
+        store.clock += 1;
+        entry.last_used = store.clock;
+        entry.stored_at = Instant::now();
+        store.remove(|stored_key, stored| stored_key == key && stored.variant == entry.variant);
+        store.bytes += entry.size;
+        store.entries.entry(key).or_default().push(entry);
+        while store.bytes > self.max_bytes && store.evict_least_recently_used() {}
+    }
+
+    /// Drops the entries of a route, of a key or, given neither, every entry. Returns how
+    /// many entries were dropped.
+    pub fn purge(&self, route: Option<&str>, key: Option<&str>) -> usize {
+        self.store.lock().unwrap().remove(|stored_key, entry| {
+            route.is_none_or(|route| entry.route == route)
+                && key.is_none_or(|key| stored_key == key)
+        })
+    }
+
+    pub fn usage(&self) -> Value {
+        let store = self.store.lock().unwrap();
+        json!({
+            "entries": store.entries.values().map(Vec::len).sum::<usize>(),
+            "bytes": store.bytes,
+            "max_bytes": self.max_bytes,
+        })
+    }
+}
+
+/// What tells callers apart in the cache key, and whether it does. With `key_claims` the
+/// key holds those claims, sorted so it is stable, and is only scoped to the caller when
+/// they are all there: the authorization API may answer with nothing about the caller.
+/// Without them the key holds a hash of the credentials the request carries.
+fn caller(
+    ctx: &RequestContext,
+    key_claims: Option<&[String]>,
+    headers: &HeaderMap,
+) -> (String, bool) {
+    let Some(names) = key_claims else {
+        return match credentials(headers) {
+            Some(hash) => (format!("credentials={}", hash), true),
+            None => (String::new(), false),
+        };
+    };
+    let mut claims: Vec<(&str, &str)> = names
+        .iter()
+        .filter_map(|name| Some((name.as_str(), ctx.identity.get(name)?)))
+        .collect();
+    let scoped = !names.is_empty() && claims.len() == names.len();
+    claims.sort();
+    let claims = claims
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<String>>()
+        .join("&");
+    (claims, scoped)
+}
+
+/// A hash of the `Authorization` and `Cookie` headers, `None` when there are none.
+fn credentials(headers: &HeaderMap) -> Option<String> {
+    let mut digest = Sha256::new();
+    let mut found = false;
+    for name in [AUTHORIZATION, COOKIE] {
+        for value in headers.get_all(&name) {
+            found = true;
+            digest.update(name.as_str());
+            digest.update(b":");
+            digest.update(value.as_bytes());
+            digest.update(b"\n");
+        }
+    }
+    let digest = digest.finalize();
+    found.then(|| digest[..16].iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// The `Cache-Control` directives of a message, names lowercased.
+fn directives(headers: &HeaderMap) -> HashMap<String, Option<String>> {
+    headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|directive| !directive.is_empty())
+        .map(|directive| match directive.split_once('=') {
+            Some((name, value)) => (
+                name.trim().to_ascii_lowercase(),
+                Some(value.trim().trim_matches('"').to_string()),
+            ),
+            None => (directive.to_ascii_lowercase(), None),
+        })
+        .collect()
+}
+
+fn seconds(directives: &HashMap<String, Option<String>>, name: &str) -> Option<u64> {
+    directives.get(name)?.as_deref()?.parse().ok()
+}
+
+fn has_validators(headers: &HeaderMap) -> bool {
+    headers.contains_key(ETAG) || headers.contains_key(LAST_MODIFIED)
+}
+
+/// How long a response stays fresh, `None` when it must not be stored. A shared cache
+/// keeps private responses only under keys scoped to the caller.
+fn freshness(key: &CacheKey, status: StatusCode, headers: &HeaderMap) -> Option<Duration> {
+    let directives = directives(headers);
+    if !CACHEABLE.contains(&status)
+        || directives.contains_key("no-store")
+        || (directives.contains_key("private") && !key.scoped)
+        || headers.contains_key(SET_COOKIE)
+    {
+        return None;
+    }
+
+    let ttl = match directives.contains_key("no-cache") {
+        true => 0,
+        false => key
+            .config
+            .ttl_secs
+            .or_else(|| seconds(&directives, "s-maxage"))
+            .or_else(|| seconds(&directives, "max-age"))
+            .or(key.config.default_ttl_secs)
+            .unwrap_or(0),
+    };
+    // Without validators a response that is never fresh is of no use
+    if ttl == 0 && !has_validators(headers) {
+        return None;
+    }
+    Some(Duration::from_secs(ttl))
+}
+
+/// The request header values a response varies on, `None` when it varies on everything.
+fn variant(res_headers: &HeaderMap, req_headers: &HeaderMap) -> Option<Variant> {
+    let mut variant = Vec::new();
+    for name in res_headers
+        .get_all(VARY)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+    {
+        if name == "*" {
+            return None;
+        }
+        if let Ok(name) = HeaderName::from_bytes(name.as_bytes()) {
+            let value = req_headers.get(&name).cloned();
+            variant.push((name, value));
+        }
+    }
+    Some(variant)
+}
+
+/// Passes a response body on while keeping a copy, stored once the body ends. Bodies that
+/// fail, grow too large or carry trailers are not stored.
+struct CacheFill {
+    inner: BoxBody,
+    cache: Arc<ResponseCache>,
+    key: String,
+    entry: Option<Entry>,
+    buffer: BytesMut,
+}
+
+impl CacheFill {
+    fn finish(&mut self) {
+        if let Some(mut entry) = self.entry.take() {
+            entry.body = std::mem::take(&mut self.buffer).freeze();
+            self.cache.insert(std::mem::take(&mut self.key), entry);
+        }
+    }
+}
+
+impl Body for CacheFill {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = &mut *self;
+        match ready!(Pin::new(&mut this.inner).poll_frame(cx)) {
+            Some(Ok(frame)) => {
+                match frame.data_ref() {
+                    Some(data) if this.buffer.len() + data.len() <= this.cache.max_entry_bytes => {
+                        this.buffer.extend_from_slice(data)
+                    }
+                    _ => this.entry = None,
+                }
+                // The body may not be polled again once it reports its end
+                if this.inner.is_end_stream() {
+                    this.finish();
+                }
+                Poll::Ready(Some(Ok(frame)))
+            }
+            Some(Err(err)) => {
+                this.entry = None;
+                Poll::Ready(Some(Err(err)))
+            }
+            None => {
+                this.finish();
+                Poll::Ready(None)
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
@@ -0,0 +1,271 @@
+use crate::config::parser::ServiceConfig;
+use crate::proxy::streaming::is_streaming_request;
+use crate::utils::http::{full, BoxBody, BoxError};
+use bytes::BytesMut;
+use http_body_util::BodyExt;
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+use hyper::header::{IF_MODIFIED_SINCE, IF_NONE_MATCH, UPGRADE};
+use hyper::{HeaderMap, Method, Request, Response, StatusCode};
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::timeout;
+
+/// How a shared backend call ended.
+#[derive(Clone)]
+enum Outcome {
+    Response {
+        status: StatusCode,
+        headers: HeaderMap,
+        body: Bytes,
+    },
+    Failed {
+        status: StatusCode,
+        reason: &'static str,
+    },
+}
+
+impl Outcome {
+    fn response(self) -> Response<BoxBody> {
+        let (status, headers, body) = match self {
+            Outcome::Response {
+                status,
+                headers,
+                body,
+            } => (status, headers, full(body)),
+            Outcome::Failed { status, reason } => (status, HeaderMap::new(), full(reason)),
+        };
+        let mut res = Response::new(body);
+        *res.status_mut() = status;
+        *res.headers_mut() = headers;
+        res
+    }
+}
+
+/// Identifies requests that may share a backend call.
+pub struct CoalesceKey {
+    key: String,
+    max_wait: Duration,
+    max_body_bytes: usize,
+}
+
+pub enum Flight {
+    Leader(Leader),
+    Follower(Follower),
+}
+
+/// The request making the backend call for a flight. The flight ends, and its outcome is
+/// no longer handed out, as soon as the call completes or the leader goes away.
+pub struct Leader {
+    coalescer: Arc<Coalescer>,
+    key: String,
+    id: u64,
+    max_body_bytes: usize,
+    sender: watch::Sender<Option<Outcome>>,
+}
+
+impl Leader {
+    fn publish(self, outcome: Outcome) {
+        self.coalescer.land(&self.key, self.id);
+        self.sender.send_replace(Some(outcome));
+    }
+
+    /// Passes the backend response on while keeping a copy, handed to the followers once
+    /// the body has been read to the end. Bodies too large to copy end the flight, and the
+    /// followers make their own call.
+    pub fn share(self, res: Response<BoxBody>) -> Response<BoxBody> {
+        let (parts, body) = res.into_parts();
+        if body.size_hint().lower() > self.max_body_bytes as u64 {
+            return Response::from_parts(parts, body);
+        }
+        let mut fill = ShareFill {
+            inner: body,
+            status: parts.status,
+            headers: parts.headers.clone(),
+            buffer: BytesMut::new(),
+            leader: Some(self),
+        };
+        if fill.inner.is_end_stream() {
+            fill.finish();
+        }
+        Response::from_parts(parts, fill.boxed())
+    }
+
+    /// Hands a failure to the requests already waiting. Later requests try again.
+    pub fn fail(self, status: StatusCode, reason: &'static str) {
+        self.publish(Outcome::Failed { status, reason });
+    }
+}
+
+impl Drop for Leader {
+    fn drop(&mut self) {
+        self.coalescer.land(&self.key, self.id);
+    }
+}
+
+/// A request waiting on the backend call of an identical one.
+pub struct Follower {
+    receiver: watch::Receiver<Option<Outcome>>,
+    max_wait: Duration,
+}
+
+impl Follower {
+    /// A copy of the leader's response, `None` when the flight ended without one or took
+    /// longer than the route's max wait, in which case the request goes on its own.
+    pub async fn wait(mut self) -> Option<Response<BoxBody>> {
+        let outcome = timeout(self.max_wait, self.receiver.wait_for(Option::is_some))
+            .await
+            .ok()?
+            .ok()?
+            .clone()?;
+        Some(outcome.response())
+    }
+}
+
+/// A flight in progress: its id, and where its outcome will be published.
+type InFlight = (u64, watch::Receiver<Option<Outcome>>);
+
+/// Single-flight coalescing of identical GET requests on the routes that opt in.
+#[derive(Default)]
+pub struct Coalescer {
+    flights: Mutex<HashMap<String, InFlight>>,
+    next_id: AtomicU64,
+}
+
+impl Coalescer {
+    /// The key of a request, `None` when the route does not coalesce or the request is not
+    /// a plain GET. Conditional headers are always part of the key.
+    pub fn key<B>(&self, service_config: &ServiceConfig, req: &Request<B>) -> Option<CoalesceKey> {
+        let config = service_config.coalesce.as_ref()?;
+        if req.method() != Method::GET
+            || req.headers().contains_key(UPGRADE)
+            || is_streaming_request(service_config, req.headers())
+        {
+            return None;
+        }
+
+        let path = req.uri().path_and_query().map_or("/", |path| path.as_str());
+        let mut key = format!("{} {}", req.method(), path);
+        let names = config
+            .key_headers
+            .iter()
+            .map(String::as_str)
+            .chain([IF_NONE_MATCH.as_str(), IF_MODIFIED_SINCE.as_str()]);
+        for name in names {
+            for value in req.headers().get_all(name) {
+                key.push_str(&format!(
+                    "\n{}: {}",
+                    name,
+                    String::from_utf8_lossy(value.as_bytes())
+                ));
+            }
+        }
+
+        Some(CoalesceKey {
+            key,
+            max_wait: Duration::from_millis(config.max_wait_ms),
+            max_body_bytes: config.max_body_bytes,
+        })
+    }
+
+    /// Joins the flight in progress for `key`, or starts one.
+    pub fn join(self: &Arc<Self>, key: CoalesceKey) -> Flight {
+        let mut flights = self.flights.lock().unwrap();
+        if let Some((_, receiver)) = flights.get(&key.key) {
+            return Flight::Follower(Follower {
+                receiver: receiver.clone(),
+                max_wait: key.max_wait,
+            });
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = watch::channel(None);
+        flights.insert(key.key.clone(), (id, receiver));
+        Flight::Leader(Leader {
+            coalescer: self.clone(),
+            key: key.key,
+            id,
+            max_body_bytes: key.max_body_bytes,
+            sender,
+        })
+    }
+
+    fn land(&self, key: &str, id: u64) {
+        let mut flights = self.flights.lock().unwrap();
+        if flights.get(key).is_some_and(|(flight, _)| *flight == id) {
+            flights.remove(key);
+        }
+    }
+}
+
+/// Body of the leader's response, copied for the followers as it streams.
+struct ShareFill {
+    inner: BoxBody,
+    status: StatusCode,
+    headers: HeaderMap,
+    buffer: BytesMut,
+    leader: Option<Leader>,
+}
+
+impl ShareFill {
+    fn finish(&mut self) {
+        if let Some(leader) = self.leader.take() {
+            leader.publish(Outcome::Response {
+                status: self.status,
+                headers: std::mem::take(&mut self.headers),
+                body: std::mem::take(&mut self.buffer).freeze(),
+            });
+        }
+    }
+}
+
+impl Body for ShareFill {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = &mut *self;
+        match ready!(Pin::new(&mut this.inner).poll_frame(cx)) {
+            Some(Ok(frame)) => {
+                let max_body_bytes = this
+                    .leader
+                    .as_ref()
+                    .map_or(0, |leader| leader.max_body_bytes);
+                match frame.data_ref() {
+                    Some(data) if this.buffer.len() + data.len() <= max_body_bytes => {
+                        this.buffer.extend_from_slice(data)
+                    }
+                    _ => this.leader = None,
+                }
+                // The body may not be polled again once it reports its end
+                if this.inner.is_end_stream() {
+                    this.finish();
+                }
+                Poll::Ready(Some(Ok(frame)))
+            }
+            Some(Err(err)) => {
+                this.leader = None;
+                Poll::Ready(Some(Err(err)))
+            }
+            None => {
+                this.finish();
+                Poll::Ready(None)
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
@@ -0,0 +1,250 @@
+use crate::config::parser::ServiceConfig;
+use crate::utils::http::{BoxBody, BoxError};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use bytes::{BufMut, BytesMut};
+use http_body_util::BodyExt;
+use hyper::body::{Body, Bytes, Frame};
+use hyper::header::{HeaderValue, CONTENT_LENGTH, CONTENT_TYPE};
+use hyper::{HeaderMap, Response, StatusCode};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+const GRPC: &str = "application/grpc";
+const GRPC_WEB: &str = "application/grpc-web";
+const GRPC_WEB_TEXT: &str = "application/grpc-web-text";
+
+/// Flag of the length-prefixed message that carries the trailers in a gRPC-Web body.
+const TRAILERS_FLAG: u8 = 0x80;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GrpcWeb {
+    Binary,
+    Text,
+}
+
+impl GrpcWeb {
+    fn content_type(self) -> &'static str {
+        match self {
+            GrpcWeb::Binary => GRPC_WEB,
+            GrpcWeb::Text => GRPC_WEB_TEXT,
+        }
+    }
+}
+
+fn content_type(headers: &HeaderMap) -> &str {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+}
+
+/// Replaces the base of a gRPC content type, keeping its `+proto` or `+json` suffix.
+fn replace_content_type(headers: &mut HeaderMap, from: &str, to: &str) {
+    let suffix = content_type(headers).strip_prefix(from).unwrap_or_default();
+    if let Ok(value) = HeaderValue::from_str(&format!("{}{}", to, suffix)) {
+        headers.insert(CONTENT_TYPE, value);
+    }
+}
+
+/// Whether a message is a gRPC call, gRPC-Web included.
+pub fn is_grpc(headers: &HeaderMap) -> bool {
+    content_type(headers).starts_with(GRPC)
+}
+
+/// The gRPC-Web flavour of a request to a route translating gRPC-Web for its backend.
+pub fn grpc_web(service_config: &ServiceConfig, headers: &HeaderMap) -> Option<GrpcWeb> {
+    if !service_config.grpc.as_ref().is_some_and(|grpc| grpc.web) {
+        return None;
+    }
+    let content_type = content_type(headers);
+    if content_type.starts_with(GRPC_WEB_TEXT) {
+        Some(GrpcWeb::Text)
+    } else if content_type.starts_with(GRPC_WEB) {
+        Some(GrpcWeb::Binary)
+    } else {
+        None
+    }
+}
+
+/// Turns a gRPC-Web request into a gRPC one for the backend. Text requests are decoded
+/// from base64.
+pub fn request_from_web(headers: &mut HeaderMap, body: BoxBody, web: GrpcWeb) -> BoxBody {
+    replace_content_type(headers, web.content_type(), GRPC);
+    headers.remove(CONTENT_LENGTH);
+    match web {
+        GrpcWeb::Binary => body,
+        GrpcWeb::Text => Base64Decode {
+            inner: body,
+            pending: BytesMut::new(),
+        }
+        .boxed(),
+    }
+}
+
+/// Turns the backend's gRPC response into a gRPC-Web one. Browsers cannot read HTTP
+/// trailers, so they are sent as the last message of the body.
+pub fn response_to_web(res: Response<BoxBody>, web: GrpcWeb) -> Response<BoxBody> {
+    if !is_grpc(res.headers()) {
+        return res;
+    }
+    let (mut parts, body) = res.into_parts();
+    replace_content_type(&mut parts.headers, GRPC, web.content_type());
+    parts.headers.remove(CONTENT_LENGTH);
+    Response::from_parts(parts, WebBody { inner: body, web }.boxed())
+}
+
+/// gRPC status code for an HTTP status, following gRPC's HTTP to gRPC status mapping.
+fn status_code(status: StatusCode) -> u16 {
+    match status {
+        StatusCode::BAD_REQUEST => 13,      // INTERNAL
+        StatusCode::UNAUTHORIZED => 16,     // UNAUTHENTICATED
+        StatusCode::FORBIDDEN => 7,         // PERMISSION_DENIED
+        StatusCode::NOT_FOUND => 12,        // UNIMPLEMENTED
+        StatusCode::PAYLOAD_TOO_LARGE => 8, // RESOURCE_EXHAUSTED
+        StatusCode::TOO_MANY_REQUESTS
+        | StatusCode::BAD_GATEWAY
+        | StatusCode::SERVICE_UNAVAILABLE
+        | StatusCode::GATEWAY_TIMEOUT => 14, // UNAVAILABLE
+        _ => 2,                             // UNKNOWN
+    }
+}
+
+/// Answers a gRPC call that ended in a plain HTTP response, from the gateway or from the
+/// backend, with a Trailers-Only response carrying the matching `grpc-status`. Headers
+/// such as `Retry-After` or the CORS ones are kept.
+pub fn error_to_grpc(res: Response<BoxBody>, web: Option<GrpcWeb>) -> Response<BoxBody> {
+    if is_grpc(res.headers()) {
+        return res;
+    }
+    let status = res.status();
+    let (mut parts, _) = res.into_parts();
+    parts.status = StatusCode::OK;
+    parts.headers.remove(CONTENT_LENGTH);
+    let content_type = web.map_or(GRPC, GrpcWeb::content_type);
+    parts
+        .headers
+        .insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    parts
+        .headers
+        .insert("grpc-status", HeaderValue::from(status_code(status)));
+    if let Some(reason) = status.canonical_reason() {
+        parts
+            .headers
+            .insert("grpc-message", HeaderValue::from_static(reason));
+    }
+    Response::from_parts(parts, BoxBody::default())
+}
+
+fn trailers_message(trailers: &HeaderMap) -> Bytes {
+    let mut block = Vec::new();
+    for (name, value) in trailers {
+        block.extend_from_slice(name.as_str().as_bytes());
+        block.extend_from_slice(b": ");
+        block.extend_from_slice(value.as_bytes());
+        block.extend_from_slice(b"\r\n");
+    }
+    let mut message = BytesMut::with_capacity(5 + block.len());
+    message.put_u8(TRAILERS_FLAG);
+    message.put_u32(block.len() as u32);
+    message.extend_from_slice(&block);
+    message.freeze()
+}
+
+/// Body of a gRPC-Web response: messages pass through, base64 encoded for text clients,
+/// and the trailers frame becomes a trailers message.
+struct WebBody {
+    inner: BoxBody,
+    web: GrpcWeb,
+}
+
+impl Body for WebBody {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let frame = match ready!(Pin::new(&mut self.inner).poll_frame(cx)) {
+            Some(Ok(frame)) => frame,
+            other => return Poll::Ready(other),
+        };
+        let data = match frame.into_data() {
+            Ok(data) => data,
+            Err(frame) => match frame.into_trailers() {
+                Ok(trailers) => trailers_message(&trailers),
+                Err(_) => Bytes::new(),
+            },
+        };
+        let data = match self.web {
+            GrpcWeb::Binary => data,
+            // Each chunk is encoded with its own padding, which gRPC-Web clients accept
+            GrpcWeb::Text => Bytes::from(STANDARD.encode(&data)),
+        };
+        Poll::Ready(Some(Ok(Frame::data(data))))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+}
+
+/// Decodes the body of a `grpc-web-text` request as it streams in.
+struct Base64Decode {
+    inner: BoxBody,
+    pending: BytesMut,
+}
+
+impl Body for Base64Decode {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        loop {
+            match ready!(Pin::new(&mut self.inner).poll_frame(cx)) {
+                Some(Ok(frame)) => match frame.into_data() {
+                    Ok(data) => {
+                        self.pending.extend_from_slice(&data);
+                        let complete = self.pending.len() / 4 * 4;
+                        if complete == 0 {
+                            continue;
+                        }
+                        let text = self.pending.split_to(complete);
+                        return Poll::Ready(Some(decode(&text).map(Frame::data)));
+                    }
+                    Err(frame) => return Poll::Ready(Some(Ok(frame))),
+                },
+                Some(Err(err)) => return Poll::Ready(Some(Err(err))),
+                None if self.pending.is_empty() => return Poll::Ready(None),
+                None => {
+                    self.pending.clear();
+                    return Poll::Ready(Some(Err("truncated base64 in gRPC-Web request".into())));
+                }
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream() && self.pending.is_empty()
+    }
+}
+
+/// Decodes whole base64 quanta. Clients may encode every message on its own, so padding
+/// can show up in the middle of the stream.
+fn decode(text: &[u8]) -> Result<Bytes, BoxError> {
+    let mut decoded = Vec::with_capacity(text.len() / 4 * 3);
+    let mut start = 0;
+    for (index, quantum) in text.chunks(4).enumerate() {
+        if quantum.contains(&b'=') {
+            let end = (index + 1) * 4;
+            STANDARD.decode_vec(&text[start..end], &mut decoded)?;
+            start = end;
+        }
+    }
+    STANDARD.decode_vec(&text[start..], &mut decoded)?;
+    Ok(Bytes::from(decoded))
+}
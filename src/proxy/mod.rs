@@ -0,0 +1,5 @@
+pub mod cache;
+pub mod coalesce;
+pub mod grpc;
+pub mod streaming;
+pub mod upgrade;
@@ -0,0 +1,102 @@
+use crate::config::parser::{GatewayConfig, ServiceConfig};
+use crate::utils::http::{BoxBody, BoxError};
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+use hyper::header::{ACCEPT, CONTENT_TYPE};
+use hyper::HeaderMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time::{sleep, Instant, Sleep};
+
+const EVENT_STREAM: &str = "text/event-stream";
+
+/// Whether a request is for a stream: the route is declared as streaming or the client
+/// asked for Server-Sent Events. Streams are never cached or coalesced.
+pub fn is_streaming_request(service_config: &ServiceConfig, headers: &HeaderMap) -> bool {
+    service_config.streaming.is_some()
+        || headers
+            .get_all(ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .any(|value| value.contains(EVENT_STREAM))
+}
+
+pub fn is_event_stream(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with(EVENT_STREAM))
+}
+
+/// How long a response body may go without a frame, the route's own setting first.
+pub fn idle_timeout(service_config: &ServiceConfig, config: &GatewayConfig) -> Duration {
+    let streaming = service_config
+        .streaming
+        .as_ref()
+        .unwrap_or(&config.streaming);
+    Duration::from_secs(streaming.idle_timeout_secs)
+}
+
+#[derive(Debug)]
+pub struct IdleTimeoutError(Duration);
+
+impl fmt::Display for IdleTimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "no data received from upstream for {:?}", self.0)
+    }
+}
+
+impl std::error::Error for IdleTimeoutError {}
+
+/// Ends a streamed body with an error when the upstream sends nothing for `timeout`.
+/// Every frame, heartbeat comments included, restarts the timer.
+///
+/// Frames are passed on as soon as they arrive, and dropping this body when the client
+/// goes away drops the upstream body too, which closes the upstream connection.
+pub struct IdleTimeout {
+    inner: BoxBody,
+    timeout: Duration,
+    deadline: Pin<Box<Sleep>>,
+}
+
+impl IdleTimeout {
+    pub fn new(inner: BoxBody, timeout: Duration) -> IdleTimeout {
+        IdleTimeout {
+            inner,
+            timeout,
+            deadline: Box::pin(sleep(timeout)),
+        }
+    }
+}
+
+impl Body for IdleTimeout {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        match Pin::new(&mut self.inner).poll_frame(cx) {
+            Poll::Ready(frame) => {
+                let next = Instant::now() + self.timeout;
+                self.deadline.as_mut().reset(next);
+                Poll::Ready(frame)
+            }
+            Poll::Pending => match self.deadline.as_mut().poll(cx) {
+                Poll::Ready(()) => Poll::Ready(Some(Err(IdleTimeoutError(self.timeout).into()))),
+                Poll::Pending => Poll::Pending,
+            },
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
@@ -0,0 +1,140 @@
+use crate::config::logger::Logger;
+use hyper::header::{HeaderValue, CONNECTION, UPGRADE};
+use hyper::upgrade::OnUpgrade;
+use hyper::HeaderMap;
+use hyper_util::rt::TokioIo;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// The protocol a request asks to switch to, when it carries both `Upgrade` and a
+/// `Connection: upgrade` token.
+pub fn requested_protocol(headers: &HeaderMap) -> Option<HeaderValue> {
+    let wants_upgrade = headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|token| token.trim().eq_ignore_ascii_case("upgrade"));
+
+    if wants_upgrade {
+        headers.get(UPGRADE).cloned()
+    } else {
+        None
+    }
+}
+
+/// Puts back the upgrade headers that hop-by-hop stripping removed.
+pub fn restore_upgrade_headers(headers: &mut HeaderMap, protocol: HeaderValue) {
+    headers.insert(CONNECTION, HeaderValue::from_static("upgrade"));
+    headers.insert(UPGRADE, protocol);
+}
+
+/// Byte counts and outcome of a finished tunnel.
+struct TunnelStats {
+    from_client: u64,
+    from_upstream: u64,
+    outcome: &'static str,
+}
+
+/// Waits for both sides of a `101 Switching Protocols` exchange to hand over their
+/// connections and pipes bytes between them until either side closes or nothing is sent
+/// for `idle_timeout`. `guard` is held for as long as the tunnel is open.
+pub async fn tunnel<G: Send>(
+    client: OnUpgrade,
+    upstream: OnUpgrade,
+    idle_timeout: Duration,
+    logger: &Logger,
+    params: &[(&str, &str)],
+    guard: G,
+) {
+    let _guard = guard;
+    let (client, upstream) = match tokio::try_join!(client, upstream) {
+        Ok(upgraded) => upgraded,
+        Err(err) => {
+            let mut params = params.to_vec();
+            let reason = err.to_string();
+            params.push(("reason", &reason));
+            logger.err("Failed to upgrade connection", &params);
+            return;
+        }
+    };
+
+    logger.info("Upgraded connection opened", params);
+    let started = Instant::now();
+
+    let stats = pipe(TokioIo::new(client), TokioIo::new(upstream), idle_timeout).await;
+
+    let duration = started.elapsed().as_millis().to_string();
+    let from_client = stats.from_client.to_string();
+    let from_upstream = stats.from_upstream.to_string();
+    let mut params = params.to_vec();
+    params.extend([
+        ("duration_ms", duration.as_str()),
+        ("bytes_from_client", from_client.as_str()),
+        ("bytes_from_upstream", from_upstream.as_str()),
+        ("outcome", stats.outcome),
+    ]);
+    logger.info("Upgraded connection closed", &params);
+}
+
+async fn pipe<C, U>(mut client: C, mut upstream: U, idle_timeout: Duration) -> TunnelStats
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+    U: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut stats = TunnelStats {
+        from_client: 0,
+        from_upstream: 0,
+        outcome: "closed",
+    };
+    let mut client_buf = vec![0u8; 16 * 1024];
+    let mut upstream_buf = vec![0u8; 16 * 1024];
+    let mut client_open = true;
+    let mut upstream_open = true;
+
+    while client_open || upstream_open {
+        let read = tokio::time::timeout(idle_timeout, async {
+            tokio::select! {
+                read = client.read(&mut client_buf), if client_open => (true, read),
+                read = upstream.read(&mut upstream_buf), if upstream_open => (false, read),
+            }
+        })
+        .await;
+
+        let (from_client, read) = match read {
+            Ok(read) => read,
+            Err(_) => {
+                stats.outcome = "idle timeout";
+                break;
+            }
+        };
+
+        let written = match (from_client, read) {
+            // End of stream on one side is forwarded as a half close to the other
+            (true, Ok(0)) => {
+                client_open = false;
+                upstream.shutdown().await
+            }
+            (false, Ok(0)) => {
+                upstream_open = false;
+                client.shutdown().await
+            }
+            (true, Ok(read)) => {
+                stats.from_client += read as u64;
+                upstream.write_all(&client_buf[..read]).await
+            }
+            (false, Ok(read)) => {
+                stats.from_upstream += read as u64;
+                client.write_all(&upstream_buf[..read]).await
+            }
+            (_, Err(err)) => Err(err),
+        };
+
+        if written.is_err() {
+            stats.outcome = "error";
+            break;
+        }
+    }
+
+    stats
+}
@@ -0,0 +1,408 @@
+use crate::config::parser::{GatewayConfig, TracingConfig};
+use crate::utils::http::{full, BoxBody};
+use hyper::header::{HeaderName, HeaderValue, CONTENT_TYPE};
+use hyper::{HeaderMap, Request};
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use serde_json::{json, Value};
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use uuid::Uuid;
+
+const TRACEPARENT: HeaderName = HeaderName::from_static("traceparent");
+const TRACESTATE: HeaderName = HeaderName::from_static("tracestate");
+
+tokio::task_local! {
+    static CURRENT: SpanContext;
+}
+
+/// The trace of the request being handled by the current task, for log entries.
+pub fn current() -> Option<SpanContext> {
+    CURRENT.try_with(Clone::clone).ok()
+}
+
+/// Runs `future` as part of the trace of `context`, when there is one.
+pub async fn scope<F: Future>(context: Option<SpanContext>, future: F) -> F::Output {
+    match context {
+        Some(context) => CURRENT.scope(context, future).await,
+        None => future.await,
+    }
+}
+
+/// What identifies a span across services, as carried by W3C `traceparent` and
+/// `tracestate` headers.
+#[derive(Clone)]
+pub struct SpanContext {
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+    sampled: bool,
+    state: Option<HeaderValue>,
+}
+
+impl SpanContext {
+    /// Reads `traceparent`, ignoring it when malformed, all zeros or of the reserved
+    /// `ff` version.
+    fn extract(headers: &HeaderMap) -> Option<SpanContext> {
+        let traceparent = headers.get(TRACEPARENT)?.to_str().ok()?;
+        let mut fields = traceparent.trim().split('-');
+        let (version, trace_id, span_id, flags) = (
+            fields.next()?,
+            fields.next()?,
+            fields.next()?,
+            fields.next()?,
+        );
+        if version.len() != 2 || version == "ff" || (version == "00" && fields.next().is_some()) {
+            return None;
+        }
+        let trace_id: [u8; 16] = decode_hex(trace_id)?.try_into().ok()?;
+        let span_id: [u8; 8] = decode_hex(span_id)?.try_into().ok()?;
+        let flags = decode_hex(flags).filter(|flags| flags.len() == 1)?[0];
+        if trace_id == [0; 16] || span_id == [0; 8] {
+            return None;
+        }
+        Some(SpanContext {
+            trace_id,
+            span_id,
+            sampled: flags & 1 == 1,
+            state: headers.get(TRACESTATE).cloned(),
+        })
+    }
+
+    pub fn trace_id(&self) -> String {
+        encode_hex(&self.trace_id)
+    }
+
+    pub fn span_id(&self) -> String {
+        encode_hex(&self.span_id)
+    }
+
+    fn traceparent(&self) -> String {
+        format!(
+            "00-{}-{}-{:02x}",
+            self.trace_id(),
+            self.span_id(),
+            self.sampled as u8
+        )
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum SpanKind {
+    Server,
+    Client,
+}
+
+/// A span being recorded. Spans of a disabled tracer carry no context and do nothing,
+/// those of a trace that was not sampled are propagated but never exported.
+pub struct Span {
+    context: Option<SpanContext>,
+    parent_span_id: Option<[u8; 8]>,
+    name: String,
+    kind: SpanKind,
+    start: SystemTime,
+    attributes: Vec<(&'static str, Value)>,
+    error: bool,
+    exporter: Option<Sender<FinishedSpan>>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Span {
+    pub fn context(&self) -> Option<SpanContext> {
+        self.context.clone()
+    }
+
+    pub fn set_str(&mut self, key: &'static str, value: &str) {
+        if self.context.is_some() {
+            self.attributes.push((key, json!({ "stringValue": value })));
+        }
+    }
+
+    pub fn set_int(&mut self, key: &'static str, value: i64) {
+        if self.context.is_some() {
+            // OTLP/JSON carries 64-bit integers as strings
+            self.attributes
+                .push((key, json!({ "intValue": value.to_string() })));
+        }
+    }
+
+    pub fn set_error(&mut self) {
+        self.error = true;
+    }
+
+    /// Replaces the trace headers of an outgoing request with this span's.
+    pub fn inject(&self, headers: &mut HeaderMap) {
+        let Some(context) = &self.context else {
+            return;
+        };
+        if let Ok(traceparent) = HeaderValue::from_str(&context.traceparent()) {
+            headers.insert(TRACEPARENT, traceparent);
+        }
+        match &context.state {
+            Some(state) => headers.insert(TRACESTATE, state.clone()),
+            None => headers.remove(TRACESTATE),
+        };
+    }
+
+    /// Queues the span for export when its trace is sampled.
+    pub fn end(self) {
+        let (Some(context), Some(exporter)) = (self.context, self.exporter) else {
+            return;
+        };
+        if !context.sampled {
+            return;
+        }
+        let span = FinishedSpan {
+            context,
+            parent_span_id: self.parent_span_id,
+            name: self.name,
+            kind: self.kind,
+            start: self.start,
+            end: SystemTime::now(),
+            attributes: self.attributes,
+            error: self.error,
+        };
+        if exporter.try_send(span).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+struct FinishedSpan {
+    context: SpanContext,
+    parent_span_id: Option<[u8; 8]>,
+    name: String,
+    kind: SpanKind,
+    start: SystemTime,
+    end: SystemTime,
+    attributes: Vec<(&'static str, Value)>,
+    error: bool,
+}
+
+/// Starts spans and exports the sampled ones over OTLP/HTTP from a task of its own.
+pub struct Tracer {
+    config: TracingConfig,
+    exporter: Option<Sender<FinishedSpan>>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Tracer {
+    /// Starts the exporter when tracing is enabled, so it must be called from within the
+    /// runtime.
+    pub fn from_config(config: &GatewayConfig) -> Tracer {
+        let config = config.tracing.clone();
+        let dropped = Arc::new(AtomicU64::new(0));
+        let exporter = config.enabled.then(|| {
+            let (sender, spans) = mpsc::channel(config.queue_capacity.max(1));
+            tokio::task::spawn(export(spans, config.clone(), dropped.clone()));
+            sender
+        });
+        Tracer {
+            config,
+            exporter,
+            dropped,
+        }
+    }
+
+    /// Spans lost to a full queue or a failed export.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Starts the span of a request received, continuing the trace of its `traceparent`.
+    pub fn start_request(&self, name: String, headers: &HeaderMap) -> Span {
+        let parent = SpanContext::extract(headers);
+        let trace_id = match &parent {
+            Some(parent) => parent.trace_id,
+            None => *Uuid::new_v4().as_bytes(),
+        };
+        let sampled = match &parent {
+            Some(parent) if self.config.parent_based => parent.sampled,
+            _ => self.sample(&trace_id),
+        };
+        let parent_span_id = parent.as_ref().map(|parent| parent.span_id);
+        let state = parent.and_then(|parent| parent.state);
+        self.span(
+            name,
+            SpanKind::Server,
+            trace_id,
+            parent_span_id,
+            sampled,
+            state,
+        )
+    }
+
+    /// Starts a span for a call made while handling the request of `parent`.
+    pub fn start_child(&self, name: String, kind: SpanKind, parent: Option<&SpanContext>) -> Span {
+        let Some(parent) = parent else {
+            return self.span(name, kind, [0; 16], None, false, None);
+        };
+        self.span(
+            name,
+            kind,
+            parent.trace_id,
+            Some(parent.span_id),
+            parent.sampled,
+            parent.state.clone(),
+        )
+    }
+
+    fn span(
+        &self,
+        name: String,
+        kind: SpanKind,
+        trace_id: [u8; 16],
+        parent_span_id: Option<[u8; 8]>,
+        sampled: bool,
+        state: Option<HeaderValue>,
+    ) -> Span {
+        let context = (self.exporter.is_some() && trace_id != [0; 16]).then(|| SpanContext {
+            trace_id,
+            span_id: new_span_id(),
+            sampled,
+            state,
+        });
+        Span {
+            context,
+            parent_span_id,
+            name,
+            kind,
+            start: SystemTime::now(),
+            attributes: Vec::new(),
+            error: false,
+            exporter: self.exporter.clone(),
+            dropped: self.dropped.clone(),
+        }
+    }
+
+    /// Samples a share of new traces, deciding from the random end of the trace id so
+    /// every service sampling the same way agrees.
+    fn sample(&self, trace_id: &[u8; 16]) -> bool {
+        let random = u64::from_be_bytes(trace_id[8..].try_into().unwrap());
+        (random as f64) < self.config.sample_rate * u64::MAX as f64
+    }
+}
+
+fn new_span_id() -> [u8; 8] {
+    loop {
+        let span_id: [u8; 8] = Uuid::new_v4().as_bytes()[..8].try_into().unwrap();
+        if span_id != [0; 8] {
+            return span_id;
+        }
+    }
+}
+
+/// Sends finished spans in batches until the tracer is gone.
+async fn export(mut spans: Receiver<FinishedSpan>, config: TracingConfig, dropped: Arc<AtomicU64>) {
+    let client = Client::builder(TokioExecutor::new()).build_http::<BoxBody>();
+    let mut interval =
+        tokio::time::interval(Duration::from_millis(config.export_interval_ms.max(1)));
+    let batch_size = config.batch_size.max(1);
+    let mut batch = Vec::new();
+    loop {
+        tokio::select! {
+            span = spans.recv() => match span {
+                Some(span) => {
+                    batch.push(span);
+                    if batch.len() < batch_size {
+                        continue;
+                    }
+                }
+                None => break,
+            },
+            _ = interval.tick() => {
+                if batch.is_empty() {
+                    continue;
+                }
+            }
+        }
+
+        let request = Request::post(&config.endpoint)
+            .header(CONTENT_TYPE, "application/json")
+            .body(full(encode(&batch, &config.service_name).to_string()));
+        let exported = match request {
+            Ok(request) => {
+                let response =
+                    tokio::time::timeout(Duration::from_secs(10), client.request(request)).await;
+                matches!(response, Ok(Ok(response)) if response.status().is_success())
+            }
+            Err(_) => false,
+        };
+        if !exported {
+            dropped.fetch_add(batch.len() as u64, Ordering::Relaxed);
+        }
+        batch.clear();
+    }
+}
+
+/// The OTLP/JSON encoding of an export request.
+fn encode(spans: &[FinishedSpan], service_name: &str) -> Value {
+    let nanos = |time: SystemTime| {
+        time.duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos()
+            .to_string()
+    };
+    let spans: Vec<Value> = spans
+        .iter()
+        .map(|span| {
+            let attributes: Vec<Value> = span
+                .attributes
+                .iter()
+                .map(|(key, value)| json!({ "key": key, "value": value }))
+                .collect();
+            let mut encoded = json!({
+                "traceId": span.context.trace_id(),
+                "spanId": span.context.span_id(),
+                "name": span.name,
+                "kind": match span.kind {
+                    SpanKind::Server => 2,
+                    SpanKind::Client => 3,
+                },
+                "startTimeUnixNano": nanos(span.start),
+                "endTimeUnixNano": nanos(span.end),
+                "attributes": attributes,
+                "status": { "code": if span.error { 2 } else { 0 } },
+            });
+            if let Some(parent_span_id) = &span.parent_span_id {
+                encoded["parentSpanId"] = json!(encode_hex(parent_span_id));
+            }
+            if let Some(state) = span
+                .context
+                .state
+                .as_ref()
+                .and_then(|state| state.to_str().ok())
+            {
+                encoded["traceState"] = json!(state);
+            }
+            encoded
+        })
+        .collect();
+
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [{ "key": "service.name", "value": { "stringValue": service_name } }],
+            },
+            "scopeSpans": [{ "scope": { "name": "hypergate" }, "spans": spans }],
+        }],
+    })
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Decodes lowercase hex, as `traceparent` requires.
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|c| matches!(c, b'0'..=b'9' | b'a'..=b'f'))
+    {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
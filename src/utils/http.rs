@@ -1,11 +1,73 @@
+use bytes::BytesMut;
+use http_body_util::combinators;
 use http_body_util::BodyExt;
 use http_body_util::Full;
-use hyper::body::Bytes;
+use http_body_util::LengthLimitError;
+use http_body_util::Limited;
+use hyper::body::{Body, Bytes};
+use std::error::Error;
+use std::fmt;
 
-pub type BoxBody = http_body_util::combinators::BoxBody<Bytes, hyper::Error>;
+pub type BoxError = Box<dyn Error + Send + Sync>;
+pub type BoxBody = combinators::BoxBody<Bytes, BoxError>;
 
 pub fn full<T: Into<Bytes>>(chunk: T) -> BoxBody {
     Full::new(chunk.into())
         .map_err(|never| match never {})
         .boxed()
 }
+
+pub fn boxed<B>(body: B) -> BoxBody
+where
+    B: Body<Data = Bytes> + Send + Sync + 'static,
+    B::Error: Into<BoxError>,
+{
+    body.map_err(Into::into).boxed()
+}
+
+/// Fails the body stream once more than `max` bytes have been read.
+pub fn limited<B>(body: B, max: usize) -> BoxBody
+where
+    B: Body<Data = Bytes> + Send + Sync + 'static,
+    B::Error: Into<BoxError>,
+{
+    Limited::new(body, max).boxed()
+}
+
+/// Buffers a whole body, giving up when it fails or grows past `max` bytes.
+pub async fn collect_limited(mut body: BoxBody, max: usize) -> Option<Bytes> {
+    let mut buffer = BytesMut::new();
+    while let Some(frame) = body.frame().await {
+        if let Some(data) = frame.ok()?.data_ref() {
+            if buffer.len() + data.len() > max {
+                return None;
+            }
+            buffer.extend_from_slice(data);
+        }
+    }
+    Some(buffer.freeze())
+}
+
+/// A body that grew past its limit once decoded.
+#[derive(Debug)]
+pub struct BodyTooLarge;
+
+impl fmt::Display for BodyTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("body too large")
+    }
+}
+
+impl Error for BodyTooLarge {}
+
+/// Whether an error, or any error that caused it, comes from a body over its limit.
+pub fn is_body_too_large(err: &(dyn Error + 'static)) -> bool {
+    let mut source = Some(err);
+    while let Some(err) = source {
+        if err.is::<LengthLimitError>() || err.is::<BodyTooLarge>() {
+            return true;
+        }
+        source = err.source();
+    }
+    false
+}
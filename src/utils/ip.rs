@@ -0,0 +1,54 @@
+use iptools::{ipv4, ipv6};
+use std::net::IpAddr;
+
+/// An IPv4 or IPv6 network, stored as its first and last address.
+#[derive(Debug, Clone)]
+pub enum Cidr {
+    V4 { start: u32, end: u32 },
+    V6 { start: u128, end: u128 },
+}
+
+impl Cidr {
+    /// Parses CIDR notation, treating a bare address as a single-host network.
+    pub fn parse(value: &str) -> Option<Cidr> {
+        let value = value.trim();
+        if ipv4::validate_ip(value) {
+            return Cidr::parse(&format!("{}/32", value));
+        }
+        if ipv6::validate_ip(value) {
+            return Cidr::parse(&format!("{}/128", value));
+        }
+
+        if let Ok((start, end)) = ipv4::cidr2block(value) {
+            return Some(Cidr::V4 {
+                start: ipv4::ip2long(&start).ok()?,
+                end: ipv4::ip2long(&end).ok()?,
+            });
+        }
+        let (start, end) = ipv6::cidr2block(value).ok()?;
+        Some(Cidr::V6 {
+            start: ipv6::ip2long(&start).ok()?,
+            end: ipv6::ip2long(&end).ok()?,
+        })
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self, ip.to_canonical()) {
+            (Cidr::V4 { start, end }, IpAddr::V4(ip)) => (*start..=*end).contains(&u32::from(ip)),
+            (Cidr::V6 { start, end }, IpAddr::V6(ip)) => (*start..=*end).contains(&u128::from(ip)),
+            _ => false,
+        }
+    }
+}
+
+/// Parses a list of CIDRs from the config, keeping the original text for logging.
+pub fn parse_cidrs(values: &[String]) -> Vec<(String, Cidr)> {
+    values
+        .iter()
+        .map(|value| {
+            let cidr = Cidr::parse(value)
+                .unwrap_or_else(|| panic!("Invalid CIDR in config file: {}", value));
+            (value.clone(), cidr)
+        })
+        .collect()
+}
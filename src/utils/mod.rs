@@ -1 +1,3 @@
 pub mod http;
+pub mod ip;
+pub mod route;
@@ -0,0 +1,111 @@
+use crate::config::parser::{GatewayConfig, ServiceConfig};
+use std::collections::HashMap;
+
+/// A path template such as `/api/v2/patients/{id}`, matched segment by segment against
+/// the start of a request path.
+#[derive(Debug, Clone)]
+pub struct RoutePattern {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Literal(String),
+    Param(String),
+}
+
+/// Params captured from a request path, plus whatever followed the matched segments.
+pub struct RouteMatch {
+    pub params: HashMap<String, String>,
+    pub rest: String,
+}
+
+impl RoutePattern {
+    pub fn parse(pattern: &str) -> RoutePattern {
+        let segments = pattern
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(|segment| {
+                match segment
+                    .strip_prefix('{')
+                    .and_then(|param| param.strip_suffix('}'))
+                {
+                    Some(param) => Segment::Param(param.to_string()),
+                    None => Segment::Literal(segment.to_string()),
+                }
+            })
+            .collect();
+        RoutePattern { segments }
+    }
+
+    pub fn matches(&self, path: &str) -> Option<RouteMatch> {
+        let mut remaining = path;
+        let mut params = HashMap::new();
+
+        for segment in &self.segments {
+            let trimmed = remaining.trim_start_matches('/');
+            let (part, after) = trimmed.split_at(trimmed.find('/').unwrap_or(trimmed.len()));
+            match segment {
+                _ if part.is_empty() => return None,
+                Segment::Literal(literal) if literal == part => (),
+                Segment::Literal(_) => return None,
+                Segment::Param(name) => {
+                    params.insert(name.clone(), part.to_string());
+                }
+            }
+            remaining = after;
+        }
+
+        Some(RouteMatch {
+            params,
+            rest: remaining.to_string(),
+        })
+    }
+}
+
+/// Route patterns of the services that declare one, compiled at startup.
+pub struct Routes {
+    patterns: HashMap<String, RoutePattern>,
+}
+
+impl Routes {
+    pub fn from_config(config: &GatewayConfig) -> Routes {
+        let patterns = config
+            .services
+            .iter()
+            .filter_map(|s| {
+                s.route
+                    .as_ref()
+                    .map(|route| (s.path.clone(), RoutePattern::parse(route)))
+            })
+            .collect();
+
+        Routes { patterns }
+    }
+
+    pub fn matches(&self, service_config: &ServiceConfig, path: &str) -> Option<RouteMatch> {
+        self.patterns.get(&service_config.path)?.matches(path)
+    }
+}
+
+/// Replaces every `{name}` in `template` with the value returned by `lookup`, leaving
+/// unknown placeholders empty.
+pub fn render_template<F>(template: &str, lookup: F) -> String
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}') else {
+            break;
+        };
+        rendered.push_str(&rest[..start]);
+        rendered.push_str(&lookup(&rest[start + 1..start + end]).unwrap_or_default());
+        rest = &rest[start + end + 1..];
+    }
+
+    rendered.push_str(rest);
+    rendered
+}
@@ -0,0 +1,131 @@
+mod common;
+
+use common::{free_port, service, Backend, Gateway};
+use serde_json::Value;
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn gateway_for(backend_port: u16, logger: &str, access_log: &str) -> Gateway {
+    Gateway::start_with_logger(
+        logger,
+        &format!(
+            "access_log:\n{}services:\n{}",
+            access_log,
+            service("/api/v1/plans", backend_port, "")
+        ),
+    )
+}
+
+fn get_with_headers(gateway: &Gateway) -> u16 {
+    gateway
+        .send(
+            "GET /api/v1/plans?page=2 HTTP/1.1\r\nhost: gateway.test\r\nuser-agent: curl/8.5\r\nreferer: https://app.test/\r\nconnection: close\r\n\r\n",
+        )
+        .status
+}
+
+/// Waits for `count` access log entries in `file`.
+fn access_entries(gateway: &Gateway, file: &str, needle: &str, count: usize) -> Vec<String> {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let log = std::fs::read_to_string(gateway.dir.join(file)).unwrap_or_default();
+        let entries: Vec<String> = log
+            .lines()
+            .filter(|line| line.contains(needle))
+            .map(str::to_string)
+            .collect();
+        if entries.len() >= count {
+            return entries;
+        }
+        assert!(Instant::now() < deadline, "access log entries missing");
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn json_entries_describe_the_request() {
+    let backend = Backend::ok();
+    let gateway = gateway_for(backend.port, "", "  format: json\n");
+
+    assert_eq!(get_with_headers(&gateway), 200);
+
+    let entries = access_entries(&gateway, "out.log", "\"message\":\"access\"", 1);
+    let entry: Value = serde_json::from_str(&entries[0]).unwrap();
+    let params = &entry["params"];
+    assert_eq!(params["method"], "GET");
+    assert_eq!(params["path"], "/api/v1/plans");
+    assert_eq!(params["query"], "page=2");
+    assert_eq!(params["status"], "200");
+    assert_eq!(params["bytes"], "2");
+    assert_eq!(
+        params["upstream"],
+        format!("http://127.0.0.1:{}/api/v1/plans?page=2", backend.port)
+    );
+    assert_eq!(params["identity"], "u-42");
+    assert_eq!(params["user_agent"], "curl/8.5");
+    assert_eq!(params["referer"], "https://app.test/");
+    assert_eq!(params["request_id"].as_str().unwrap().len(), 36);
+    assert!(params["upstream_latency_ms"]
+        .as_str()
+        .unwrap()
+        .parse::<f64>()
+        .is_ok());
+    assert!(params["latency_ms"]
+        .as_str()
+        .unwrap()
+        .parse::<f64>()
+        .is_ok());
+}
+
+#[test]
+fn combined_entries_go_to_the_access_file() {
+    let backend = Backend::ok();
+    let gateway = gateway_for(
+        backend.port,
+        "  access_file: \"{dir}/access.log\"\n",
+        "  format: combined\n",
+    );
+
+    get_with_headers(&gateway);
+
+    let line = &access_entries(&gateway, "access.log", "GET", 1)[0];
+    assert!(line.starts_with("127.0.0.1 - u-42 ["));
+    assert!(line.ends_with(
+        "] \"GET /api/v1/plans?page=2 HTTP/1.1\" 200 2 \"https://app.test/\" \"curl/8.5\""
+    ));
+}
+
+#[test]
+fn fields_can_be_selected_and_requests_sampled() {
+    let backend = Backend::ok();
+    let gateway = gateway_for(
+        backend.port,
+        "",
+        "  fields: [method, status]\n  sample_rate: 0.5\n",
+    );
+
+    for _ in 0..4 {
+        gateway.get("/api/v1/plans");
+    }
+    gateway.get("/api/v1/plans");
+    gateway.wait_for_log("out.log", "Connection closed");
+    thread::sleep(Duration::from_millis(300));
+
+    let entries = access_entries(&gateway, "out.log", "\"message\":\"access\"", 2);
+    assert_eq!(entries.len(), 2);
+    let entry: Value = serde_json::from_str(&entries[0]).unwrap();
+    assert_eq!(
+        entry["params"],
+        serde_json::json!({"method": "GET", "status": "200"})
+    );
+}
+
+#[test]
+fn server_errors_are_always_logged() {
+    let gateway = gateway_for(free_port(), "", "  format: common\n  sample_rate: 0\n");
+
+    assert_eq!(gateway.get("/api/v1/plans").status, 503);
+
+    let line = &access_entries(&gateway, "out.log", "\"GET /api/v1/plans", 1)[0];
+    assert!(line.contains("\"GET /api/v1/plans HTTP/1.1\" 503 "));
+}
@@ -0,0 +1,170 @@
+mod common;
+
+use common::{admin_request, free_port, service, Backend, Gateway, RawResponse};
+use std::io::Write;
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+fn backend_with(headers: &str) -> Backend {
+    Backend::start(&format!(
+        "HTTP/1.1 200 OK\r\n{}content-length: 5\r\nconnection: close\r\n\r\nplans",
+        headers
+    ))
+}
+
+fn gateway_for(backend: &Backend, cache: &str) -> Gateway {
+    Gateway::start(&format!(
+        "services:\n{}",
+        service("/api/v1/plans", backend.port, cache)
+    ))
+}
+
+fn get(gateway: &Gateway, headers: &str) -> RawResponse {
+    gateway.send(&format!(
+        "GET /api/v1/plans HTTP/1.1\r\nhost: gateway.test\r\n{}connection: close\r\n\r\n",
+        headers
+    ))
+}
+
+#[test]
+fn fresh_responses_are_served_from_the_cache() {
+    let backend = backend_with("cache-control: max-age=60\r\n");
+    let gateway = gateway_for(&backend, "    cache: {}\n");
+
+    let first = get(&gateway, "");
+    let second = get(&gateway, "");
+
+    assert_eq!(first.header("x-cache"), Some("MISS"));
+    assert_eq!(second.header("x-cache"), Some("HIT"));
+    assert_eq!(second.body, b"plans");
+    assert_eq!(backend.received(), 1);
+}
+
+#[test]
+fn no_store_responses_are_not_cached() {
+    let backend = backend_with("cache-control: no-store\r\n");
+    let gateway = gateway_for(&backend, "    cache:\n      ttl_secs: 60\n");
+
+    get(&gateway, "");
+    let second = get(&gateway, "");
+
+    assert_eq!(second.header("x-cache"), Some("MISS"));
+    assert_eq!(backend.received(), 2);
+}
+
+#[test]
+fn private_responses_are_only_cached_per_caller() {
+    let backend = backend_with("cache-control: private, max-age=60\r\n");
+    let per_caller = gateway_for(&backend, "    cache: {}\n");
+    get(&per_caller, "cookie: session=a\r\n");
+    assert_eq!(
+        get(&per_caller, "cookie: session=a\r\n").header("x-cache"),
+        Some("HIT")
+    );
+    // Nothing tells anonymous callers apart
+    get(&per_caller, "");
+    assert_eq!(get(&per_caller, "").header("x-cache"), Some("MISS"));
+
+    let shared = gateway_for(&backend, "    cache:\n      key_claims: []\n");
+    get(&shared, "cookie: session=a\r\n");
+    assert_eq!(
+        get(&shared, "cookie: session=a\r\n").header("x-cache"),
+        Some("MISS")
+    );
+}
+
+#[test]
+fn callers_with_different_credentials_do_not_share_entries() {
+    // What the authorization API answers, with nothing about the caller
+    const AUTH_BODY: &str = "{\"message\":\"Token is valid\"}";
+    let backend = backend_with("cache-control: private, max-age=60\r\n");
+    let gateway = Gateway::start_with_auth(
+        AUTH_BODY,
+        &format!(
+            "services:\n{}{}",
+            service("/api/v1/plans", backend.port, "    cache: {}\n"),
+            service(
+                "/api/v1/history",
+                backend.port,
+                "    cache:\n      key_claims: [\"userId\"]\n"
+            ),
+        ),
+    );
+
+    let alice = "authorization: Bearer alice\r\n";
+    let bob = "authorization: Bearer bob\r\n";
+    assert_eq!(get(&gateway, alice).header("x-cache"), Some("MISS"));
+    assert_eq!(get(&gateway, bob).header("x-cache"), Some("MISS"));
+    assert_eq!(get(&gateway, alice).header("x-cache"), Some("HIT"));
+    assert_eq!(backend.received(), 2);
+
+    // The claim that would tell them apart is missing, so private responses are not kept
+    let history = |headers: &str| {
+        gateway.send(&format!(
+            "GET /api/v1/history HTTP/1.1\r\nhost: gateway.test\r\n{}connection: close\r\n\r\n",
+            headers
+        ))
+    };
+    history(alice);
+    assert_eq!(history(bob).header("x-cache"), Some("MISS"));
+    assert_eq!(history(alice).header("x-cache"), Some("MISS"));
+}
+
+#[test]
+fn responses_are_cached_per_vary_header() {
+    let backend = backend_with("cache-control: max-age=60\r\nvary: accept-language\r\n");
+    let gateway = gateway_for(&backend, "    cache: {}\n");
+
+    get(&gateway, "accept-language: en\r\n");
+    let french = get(&gateway, "accept-language: fr\r\n");
+    let english = get(&gateway, "accept-language: en\r\n");
+
+    assert_eq!(french.header("x-cache"), Some("MISS"));
+    assert_eq!(english.header("x-cache"), Some("HIT"));
+    assert_eq!(backend.received(), 2);
+}
+
+#[test]
+fn stale_responses_are_revalidated() {
+    let calls = AtomicUsize::new(0);
+    let backend = Backend::start_with(move |mut stream: TcpStream| {
+        let response = match calls.fetch_add(1, Ordering::SeqCst) {
+            0 => "HTTP/1.1 200 OK\r\ncache-control: no-cache\r\netag: \"v1\"\r\ncontent-length: 5\r\nconnection: close\r\n\r\nplans",
+            _ => "HTTP/1.1 304 Not Modified\r\ncache-control: no-cache\r\netag: \"v1\"\r\nconnection: close\r\n\r\n",
+        };
+        let _ = stream.write_all(response.as_bytes());
+    });
+    let gateway = gateway_for(&backend, "    cache: {}\n");
+
+    get(&gateway, "");
+    backend.next_request();
+    let revalidated = get(&gateway, "");
+
+    assert_eq!(
+        backend.next_request().header("if-none-match"),
+        Some("\"v1\"")
+    );
+    assert_eq!(revalidated.status, 200);
+    assert_eq!(revalidated.header("x-cache"), Some("HIT"));
+    assert_eq!(revalidated.body, b"plans");
+}
+
+#[test]
+fn the_admin_endpoint_purges_a_route() {
+    let backend = backend_with("cache-control: max-age=60\r\n");
+    let admin_port = free_port();
+    let gateway = Gateway::start(&format!(
+        "admin_url: \"127.0.0.1:{}\"\nservices:\n{}",
+        admin_port,
+        service("/api/v1/plans", backend.port, "    cache: {}\n")
+    ));
+
+    get(&gateway, "");
+    let purge = admin_request(
+        admin_port,
+        "DELETE /cache?route=/api/v1/plans HTTP/1.1\r\nhost: admin\r\nconnection: close\r\n\r\n",
+    );
+
+    assert!(purge.ends_with("{\"purged\":1}"));
+    assert_eq!(get(&gateway, "").header("x-cache"), Some("MISS"));
+}
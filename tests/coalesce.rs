@@ -0,0 +1,102 @@
+mod common;
+
+use common::{service, Backend, Gateway, RawResponse};
+use std::io::Write;
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Answers after `delay`, or drops the connection without answering when `fail` is set.
+fn slow_backend(delay: Duration, fail: bool) -> Backend {
+    Backend::start_with(move |mut stream: TcpStream| {
+        thread::sleep(delay);
+        if !fail {
+            let _ = stream.write_all(
+                b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\nconnection: close\r\n\r\nplans",
+            );
+        }
+    })
+}
+
+fn gateway_for(backend: &Backend, coalesce: &str) -> Arc<Gateway> {
+    Arc::new(Gateway::start(&format!(
+        "services:\n{}",
+        service("/api/v1/plans", backend.port, coalesce)
+    )))
+}
+
+/// Sends `count` identical requests at once.
+fn burst(gateway: &Arc<Gateway>, count: usize) -> Vec<RawResponse> {
+    let requests: Vec<_> = (0..count)
+        .map(|_| {
+            let gateway = gateway.clone();
+            thread::spawn(move || gateway.get("/api/v1/plans"))
+        })
+        .collect();
+    requests
+        .into_iter()
+        .map(|request| request.join().unwrap())
+        .collect()
+}
+
+#[test]
+fn identical_requests_share_one_backend_call() {
+    let backend = slow_backend(Duration::from_millis(300), false);
+    let gateway = gateway_for(&backend, "    coalesce: {}\n");
+
+    let responses = burst(&gateway, 5);
+
+    assert!(responses
+        .iter()
+        .all(|res| res.status == 200 && res.body == b"plans"));
+    assert_eq!(backend.received(), 1);
+}
+
+#[test]
+fn requests_with_other_key_headers_are_not_shared() {
+    let backend = slow_backend(Duration::from_millis(300), false);
+    let gateway = gateway_for(&backend, "    coalesce: {}\n");
+
+    let requests: Vec<_> = ["a", "b"]
+        .into_iter()
+        .map(|session| {
+            let gateway = gateway.clone();
+            thread::spawn(move || {
+                gateway.send(&format!(
+                    "GET /api/v1/plans HTTP/1.1\r\nhost: gateway.test\r\ncookie: session={}\r\nconnection: close\r\n\r\n",
+                    session
+                ))
+            })
+        })
+        .collect();
+    for request in requests {
+        assert_eq!(request.join().unwrap().status, 200);
+    }
+
+    assert_eq!(backend.received(), 2);
+}
+
+#[test]
+fn errors_are_not_shared_after_the_call_ends() {
+    let backend = slow_backend(Duration::from_millis(300), true);
+    let gateway = gateway_for(&backend, "    coalesce: {}\n");
+
+    let responses = burst(&gateway, 3);
+    assert!(responses.iter().all(|res| res.status == 503));
+    assert_eq!(backend.received(), 1);
+
+    assert_eq!(gateway.get("/api/v1/plans").status, 503);
+    assert_eq!(backend.received(), 1);
+}
+
+#[test]
+fn followers_stop_waiting_after_the_max_wait() {
+    let backend = slow_backend(Duration::from_millis(500), false);
+    let gateway = gateway_for(&backend, "    coalesce:\n      max_wait_ms: 50\n");
+
+    let responses = burst(&gateway, 2);
+
+    assert!(responses.iter().all(|res| res.status == 200));
+    assert_eq!(backend.received(), 2);
+}
@@ -0,0 +1,448 @@
+#![allow(dead_code)]
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process::{Child, Command, Output};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A request as received by a fake backend.
+pub struct CapturedRequest {
+    pub request_line: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl CapturedRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn target(&self) -> &str {
+        self.request_line.split(' ').nth(1).unwrap_or_default()
+    }
+}
+
+/// A backend answering every request with the same raw HTTP response.
+pub struct Backend {
+    pub port: u16,
+    requests: Mutex<Receiver<CapturedRequest>>,
+}
+
+impl Backend {
+    pub fn start(response: &str) -> Backend {
+        let response = response.to_string();
+        Backend::start_with(move |mut stream| {
+            let _ = stream.write_all(response.as_bytes());
+        })
+    }
+
+    /// Starts a backend that hands every connection to `handler` once the request head
+    /// and body have been read.
+    pub fn start_with<F>(handler: F) -> Backend
+    where
+        F: Fn(TcpStream) + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (sender, requests) = channel();
+        let handler = Arc::new(handler);
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { continue };
+                let sender = sender.clone();
+                let handler = handler.clone();
+                thread::spawn(move || {
+                    if let Some(request) = read_request(&mut stream) {
+                        let _ = sender.send(request);
+                        handler(stream);
+                    }
+                });
+            }
+        });
+
+        Backend {
+            port,
+            requests: Mutex::new(requests),
+        }
+    }
+
+    pub fn ok() -> Backend {
+        Backend::start(
+            "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: 2\r\nconnection: close\r\n\r\n{}",
+        )
+    }
+
+    /// How many requests arrived since the last call.
+    pub fn received(&self) -> usize {
+        self.requests.lock().unwrap().try_iter().count()
+    }
+
+    pub fn next_request(&self) -> CapturedRequest {
+        self.requests
+            .lock()
+            .unwrap()
+            .recv_timeout(Duration::from_secs(5))
+            .expect("backend did not receive a request")
+    }
+}
+
+fn read_request(stream: &mut TcpStream) -> Option<CapturedRequest> {
+    stream.set_read_timeout(Some(Duration::from_secs(5))).ok()?;
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 4096];
+
+    let head_end = loop {
+        if let Some(end) = find(&buffer, b"\r\n\r\n") {
+            break end;
+        }
+        let read = stream.read(&mut chunk).ok()?;
+        if read == 0 {
+            return None;
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    };
+
+    let head = String::from_utf8_lossy(&buffer[..head_end]).to_string();
+    let mut lines = head.split("\r\n");
+    let request_line = lines.next()?.to_string();
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+        .collect();
+
+    let mut body = buffer[head_end + 4..].to_vec();
+    let content_length = headers
+        .iter()
+        .find(|(name, _)| name == "content-length")
+        .and_then(|(_, value)| value.parse::<usize>().ok());
+    let chunked = headers
+        .iter()
+        .any(|(name, value)| name == "transfer-encoding" && value.contains("chunked"));
+
+    while content_length.is_some_and(|length| body.len() < length)
+        || (chunked && find(&body, b"0\r\n\r\n").is_none())
+    {
+        let read = stream.read(&mut chunk).ok()?;
+        if read == 0 {
+            break;
+        }
+        body.extend_from_slice(&chunk[..read]);
+    }
+
+    Some(CapturedRequest {
+        request_line,
+        headers,
+        body,
+    })
+}
+
+/// Reads a response head byte by byte, leaving anything after it in the stream.
+pub fn read_head(stream: &mut TcpStream) -> RawResponse {
+    let mut head = Vec::new();
+    let mut byte = [0u8; 1];
+    while find(&head, b"\r\n\r\n").is_none() {
+        match stream.read(&mut byte) {
+            Ok(1) => head.push(byte[0]),
+            _ => break,
+        }
+    }
+    RawResponse::parse(&head)
+}
+
+/// Decodes a `transfer-encoding: chunked` body.
+pub fn dechunk(mut body: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::new();
+    while let Some(line_end) = find(body, b"\r\n") {
+        let size = String::from_utf8_lossy(&body[..line_end]);
+        let size = usize::from_str_radix(size.trim(), 16).unwrap_or(0);
+        if size == 0 {
+            break;
+        }
+        let start = line_end + 2;
+        decoded.extend_from_slice(&body[start..start + size]);
+        body = &body[start + size + 2..];
+    }
+    decoded
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// A gateway process started from the built binary with a temporary config.
+pub struct Gateway {
+    pub port: u16,
+    pub dir: PathBuf,
+    child: Child,
+    pub auth: Backend,
+}
+
+impl Gateway {
+    /// Starts a gateway with `config` appended to a base config that listens on a free
+    /// port, logs to a temporary directory and authorizes every request. `config` may set
+    /// `is_https`, false otherwise, and `cors`, allowing no origin otherwise. Its stdout
+    /// and stderr go to `stdout.log` and `stderr.log` in the same directory.
+    pub fn start(config: &str) -> Gateway {
+        Gateway::start_with_logger("", config)
+    }
+
+    /// Starts a gateway like `start`, with `logger` appended to its `logger_config`. It
+    /// may set `use_kafka`, and `{dir}` in it stands for the log directory.
+    pub fn start_with_logger(logger: &str, config: &str) -> Gateway {
+        Gateway::launch("{\"userId\":\"u-42\"}\n", logger, config)
+    }
+
+    /// Starts a gateway like `start`, whose authorization API answers every request with
+    /// the JSON `auth_body`.
+    pub fn start_with_auth(auth_body: &str, config: &str) -> Gateway {
+        Gateway::launch(auth_body, "", config)
+    }
+
+    /// Starts a gateway with both the authorization response of `start_with_auth` and the
+    /// logger settings of `start_with_logger`.
+    pub fn launch(auth_body: &str, logger: &str, config: &str) -> Gateway {
+        let auth = Backend::start(&format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+            auth_body.len(),
+            auth_body
+        ));
+        let port = free_port();
+        let dir = std::env::temp_dir().join(format!("hypergate-test-{}", port));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut logger = logger.replace("{dir}", &dir.display().to_string());
+        if !logger.contains("use_kafka:") {
+            logger.push_str("  use_kafka: false\n");
+        }
+
+        let is_https = match config.contains("is_https:") {
+            true => "",
+            false => "is_https: false\n",
+        };
+        let cors = match config.lines().any(|line| line.starts_with("cors:")) {
+            true => "",
+            false => "cors:\n  allowed_origins: []\n",
+        };
+
+        let full_config = format!(
+            "api_gateway_url: \"127.0.0.1:{port}\"\n\
+             {is_https}\
+             authorization_api_url: \"http://127.0.0.1:{auth}/validate\"\n\
+             endpoints_without_auth: []\n\
+             logger_config:\n  out_file: \"{dir}/out.log\"\n  err_file: \"{dir}/err.log\"\n  debug_file: \"{dir}/debug.log\"\n\
+             {logger}\
+             {cors}\
+             {config}\n",
+            port = port,
+            is_https = is_https,
+            auth = auth.port,
+            dir = dir.display(),
+            logger = logger,
+            cors = cors,
+            config = config,
+        );
+        let config_path = dir.join("config.yaml");
+        std::fs::write(&config_path, full_config).unwrap();
+
+        let manifest_dir = env!("CARGO_MANIFEST_DIR");
+        let child = Command::new(env!("CARGO_BIN_EXE_hypergate"))
+            .args(["serve", "--conf"])
+            .arg(&config_path)
+            .arg("--specs")
+            .arg(format!("{}/static/openapi.yaml", manifest_dir))
+            .arg("--html")
+            .arg(format!("{}/static/openapi.html", manifest_dir))
+            .stdout(std::fs::File::create(dir.join("stdout.log")).unwrap())
+            .stderr(std::fs::File::create(dir.join("stderr.log")).unwrap())
+            .spawn()
+            .unwrap();
+
+        let gateway = Gateway {
+            port,
+            dir,
+            child,
+            auth,
+        };
+        gateway.wait_until_ready();
+        gateway
+    }
+
+    fn wait_until_ready(&self) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while TcpStream::connect(("127.0.0.1", self.port)).is_err() {
+            assert!(Instant::now() < deadline, "gateway did not start");
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    /// Sends a raw request, which should ask to close the connection, and reads the
+    /// whole response.
+    pub fn send(&self, request: &str) -> RawResponse {
+        let mut stream = self.connect();
+        stream.write_all(request.as_bytes()).unwrap();
+
+        let mut raw = Vec::new();
+        let _ = stream.read_to_end(&mut raw);
+        RawResponse::parse(&raw)
+    }
+
+    pub fn connect(&self) -> TcpStream {
+        let stream = TcpStream::connect(("127.0.0.1", self.port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        stream
+    }
+
+    /// Sends a Unix signal, such as `USR1`, to the gateway process.
+    pub fn signal(&self, name: &str) {
+        let status = Command::new("kill")
+            .arg(format!("-{}", name))
+            .arg(self.child.id().to_string())
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    /// Waits for the gateway to write a line containing `needle` to a log file.
+    pub fn wait_for_log(&self, file: &str, needle: &str) -> String {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let log = std::fs::read_to_string(self.dir.join(file)).unwrap_or_default();
+            if let Some(line) = log.lines().find(|line| line.contains(needle)) {
+                return line.to_string();
+            }
+            assert!(Instant::now() < deadline, "{} was not logged", needle);
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    pub fn get(&self, target: &str) -> RawResponse {
+        self.send(&format!(
+            "GET {} HTTP/1.1\r\nhost: gateway.test\r\nconnection: close\r\n\r\n",
+            target
+        ))
+    }
+}
+
+impl Drop for Gateway {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+pub struct RawResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl RawResponse {
+    fn parse(raw: &[u8]) -> RawResponse {
+        let head_end = find(raw, b"\r\n\r\n").expect("incomplete response");
+        let head = String::from_utf8_lossy(&raw[..head_end]).to_string();
+        let mut lines = head.split("\r\n");
+        let status = lines
+            .next()
+            .and_then(|line| line.split(' ').nth(1))
+            .and_then(|status| status.parse().ok())
+            .expect("invalid status line");
+        let headers = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+            .collect();
+
+        RawResponse {
+            status,
+            headers,
+            body: raw[head_end + 4..].to_vec(),
+        }
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+pub fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+/// Runs a gateway listening on `url` until it exits, for configs it should refuse. `config`
+/// follows the logger settings and gets no `cors` section. Answers the process output and
+/// the contents of its error log.
+pub fn run_until_exit(url: &str, config: &str) -> (Output, String) {
+    let dir = std::env::temp_dir().join(format!("hypergate-test-{}", free_port()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let config_path = dir.join("config.yaml");
+    std::fs::write(
+        &config_path,
+        format!(
+            "api_gateway_url: \"{url}\"\n\
+             is_https: false\n\
+             authorization_api_url: \"http://127.0.0.1:{auth}/validate\"\n\
+             endpoints_without_auth: []\n\
+             logger_config:\n  use_kafka: false\n  out_file: \"{dir}/out.log\"\n  err_file: \"{dir}/err.log\"\n  debug_file: \"{dir}/debug.log\"\n\
+             {config}\n",
+            url = url,
+            auth = free_port(),
+            dir = dir.display(),
+            config = config,
+        ),
+    )
+    .unwrap();
+
+    let manifest_dir = env!("CARGO_MANIFEST_DIR");
+    let output = Command::new(env!("CARGO_BIN_EXE_hypergate"))
+        .args(["serve", "--conf"])
+        .arg(&config_path)
+        .arg("--specs")
+        .arg(format!("{}/static/openapi.yaml", manifest_dir))
+        .arg("--html")
+        .arg(format!("{}/static/openapi.html", manifest_dir))
+        .output()
+        .unwrap();
+    let err_log = std::fs::read_to_string(dir.join("err.log")).unwrap_or_default();
+    let _ = std::fs::remove_dir_all(&dir);
+    (output, err_log)
+}
+
+/// Sends a raw request to the admin listener once it accepts connections.
+pub fn admin_request(port: u16, request: &str) -> String {
+    for _ in 0..50 {
+        if let Ok(mut stream) = TcpStream::connect(("127.0.0.1", port)) {
+            stream.write_all(request.as_bytes()).unwrap();
+            let mut response = String::new();
+            let _ = stream.read_to_string(&mut response);
+            return response;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    panic!("admin listener did not start");
+}
+
+/// A `services` entry pointing at a local backend, with extra indented YAML appended.
+pub fn service(path: &str, port: u16, extra: &str) -> String {
+    format!(
+        "  - path: \"{}\"\n    target_service: \"http://127.0.0.1\"\n    target_port: \"{}\"\n{}",
+        path, port, extra
+    )
+}
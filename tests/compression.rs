@@ -0,0 +1,130 @@
+mod common;
+
+use common::{dechunk, read_head, service, Backend, Gateway, RawResponse};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::{Read, Write};
+
+fn json_backend(body: &str, extra_headers: &str) -> Backend {
+    Backend::start(&format!(
+        "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\n{}content-length: {}\r\nconnection: close\r\n\r\n{}",
+        extra_headers,
+        body.len(),
+        body
+    ))
+}
+
+fn large_json() -> String {
+    format!("[{}]", vec!["{\"plan\":\"basic\"}"; 200].join(","))
+}
+
+fn gateway_for(backend: &Backend, extra: &str) -> Gateway {
+    Gateway::start(&format!(
+        "services:\n{}",
+        service("/api/v1/plans", backend.port, extra)
+    ))
+}
+
+fn get(gateway: &Gateway, accept_encoding: &str) -> RawResponse {
+    gateway.send(&format!(
+        "GET /api/v1/plans HTTP/1.1\r\nhost: gateway.test\r\naccept-encoding: {}\r\nconnection: close\r\n\r\n",
+        accept_encoding
+    ))
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+#[test]
+fn responses_are_compressed_for_the_client() {
+    let body = large_json();
+    let backend = json_backend(&body, "");
+    let gateway = gateway_for(&backend, "");
+
+    let response = get(&gateway, "gzip");
+    let mut decoded = String::new();
+    GzDecoder::new(dechunk(&response.body).as_slice())
+        .read_to_string(&mut decoded)
+        .unwrap();
+
+    assert_eq!(response.header("content-encoding"), Some("gzip"));
+    assert_eq!(response.header("vary"), Some("accept-encoding"));
+    assert_eq!(decoded, body);
+    assert_eq!(get(&gateway, "br").header("content-encoding"), Some("br"));
+    assert_eq!(
+        get(&gateway, "zstd").header("content-encoding"),
+        Some("zstd")
+    );
+}
+
+#[test]
+fn small_or_unlisted_responses_are_not_compressed() {
+    let small = json_backend("{}", "");
+    let gateway = gateway_for(&small, "");
+    assert_eq!(get(&gateway, "gzip").header("content-encoding"), None);
+
+    let image = Backend::start(&format!(
+        "HTTP/1.1 200 OK\r\ncontent-type: image/png\r\ncontent-length: 4096\r\nconnection: close\r\n\r\n{}",
+        "x".repeat(4096)
+    ));
+    let gateway = gateway_for(&image, "");
+    assert_eq!(get(&gateway, "gzip").header("content-encoding"), None);
+}
+
+#[test]
+fn routes_can_opt_out_of_compression() {
+    let backend = json_backend(&large_json(), "");
+    let gateway = gateway_for(&backend, "    compression:\n      enabled: false\n");
+
+    assert_eq!(get(&gateway, "gzip").header("content-encoding"), None);
+}
+
+#[test]
+fn compressed_upstream_responses_pass_through() {
+    let backend = json_backend(&large_json(), "content-encoding: br\r\n");
+    let gateway = gateway_for(&backend, "");
+
+    let response = get(&gateway, "gzip");
+
+    assert_eq!(response.header("content-encoding"), Some("br"));
+    assert_eq!(response.body, large_json().as_bytes());
+}
+
+/// Posts a gzip body, returning the response status.
+fn post_gzip(gateway: &Gateway, body: &[u8]) -> u16 {
+    let mut request = format!(
+        "POST /api/v1/plans HTTP/1.1\r\nhost: gateway.test\r\ncontent-type: application/json\r\ncontent-encoding: gzip\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+        body.len()
+    )
+    .into_bytes();
+    request.extend_from_slice(body);
+    let mut stream = gateway.connect();
+    stream.write_all(&request).unwrap();
+    read_head(&mut stream).status
+}
+
+#[test]
+fn gzip_request_bodies_are_decompressed() {
+    let backend = Backend::ok();
+    let gateway = gateway_for(&backend, "    decompress: {}\n");
+
+    let status = post_gzip(&gateway, &gzip(b"{\"plan\":\"premium\"}"));
+    let request = backend.next_request();
+
+    assert_eq!(status, 200);
+    assert_eq!(request.header("content-encoding"), None);
+    assert_eq!(dechunk(&request.body), b"{\"plan\":\"premium\"}");
+}
+
+#[test]
+fn decompression_stops_at_the_size_limit() {
+    let backend = Backend::ok();
+    let gateway = gateway_for(&backend, "    decompress:\n      max_bytes: 1000\n");
+
+    let bomb = gzip(&vec![0u8; 1024 * 1024]);
+    assert_eq!(post_gzip(&gateway, &bomb), 413);
+}
@@ -0,0 +1,149 @@
+mod common;
+
+use common::{free_port, run_until_exit, service, Backend, Gateway, RawResponse};
+
+const CORS: &str = "cors:\n  allowed_origins:\n    - \"https://app.example.com\"\n    - \"https://*.example.org\"\n    - \"regex:^http://localhost:[0-9]+$\"\n  allowed_methods: [\"GET\", \"POST\"]\n  allowed_headers: [\"content-type\", \"authorization\"]\n  exposed_headers: [\"x-request-id\"]\n  max_age_secs: 600\n  allow_credentials: true\n";
+
+fn gateway_for(backend: &Backend, extra: &str) -> Gateway {
+    Gateway::start(&format!(
+        "{}{}services:\n{}{}",
+        CORS,
+        extra,
+        service("/api/v1/plans", backend.port, ""),
+        service(
+            "/api/v1/public",
+            backend.port,
+            "    cors:\n      allowed_origins: [\"*\"]\n      allowed_methods: [\"GET\"]\n"
+        ),
+    ))
+}
+
+fn get_from(gateway: &Gateway, target: &str, origin: &str) -> RawResponse {
+    gateway.send(&format!(
+        "GET {} HTTP/1.1\r\nhost: gateway.test\r\norigin: {}\r\nconnection: close\r\n\r\n",
+        target, origin
+    ))
+}
+
+fn preflight(gateway: &Gateway, target: &str, origin: &str) -> RawResponse {
+    gateway.send(&format!(
+        "OPTIONS {} HTTP/1.1\r\nhost: gateway.test\r\norigin: {}\r\naccess-control-request-method: POST\r\naccess-control-request-headers: content-type\r\nconnection: close\r\n\r\n",
+        target, origin
+    ))
+}
+
+#[test]
+fn origins_match_exactly_by_subdomain_or_by_regex() {
+    let backend = Backend::ok();
+    let gateway = gateway_for(&backend, "");
+
+    for origin in [
+        "https://app.example.com",
+        "https://records.example.org",
+        "https://eu.records.example.org",
+        "http://localhost:5173",
+    ] {
+        let response = get_from(&gateway, "/api/v1/plans", origin);
+        assert_eq!(response.status, 200);
+        assert_eq!(
+            response.header("access-control-allow-origin"),
+            Some(origin),
+            "{}",
+            origin
+        );
+        assert_eq!(
+            response.header("access-control-allow-credentials"),
+            Some("true")
+        );
+        assert_eq!(
+            response.header("access-control-expose-headers"),
+            Some("x-request-id")
+        );
+    }
+}
+
+#[test]
+fn denied_origins_get_no_allow_origin() {
+    let backend = Backend::ok();
+    let gateway = gateway_for(&backend, "");
+
+    for origin in [
+        "https://evil.example.com",
+        "http://app.example.com",
+        "https://example.org",
+        "https://records.example.org.evil.test",
+        "http://localhost:dev",
+    ] {
+        let response = get_from(&gateway, "/api/v1/plans", origin);
+        assert_eq!(
+            response.header("access-control-allow-origin"),
+            None,
+            "{}",
+            origin
+        );
+        let response = preflight(&gateway, "/api/v1/plans", origin);
+        assert_eq!(
+            response.header("access-control-allow-origin"),
+            None,
+            "{}",
+            origin
+        );
+    }
+}
+
+#[test]
+fn preflights_are_answered_by_the_gateway() {
+    let backend = Backend::ok();
+    let gateway = gateway_for(&backend, "");
+
+    let response = preflight(&gateway, "/api/v1/plans", "https://app.example.com");
+
+    assert_eq!(response.status, 200);
+    assert_eq!(
+        response.header("access-control-allow-origin"),
+        Some("https://app.example.com")
+    );
+    assert_eq!(
+        response.header("access-control-allow-methods"),
+        Some("GET,POST")
+    );
+    assert_eq!(
+        response.header("access-control-allow-headers"),
+        Some("content-type,authorization")
+    );
+    assert_eq!(response.header("access-control-max-age"), Some("600"));
+    assert_eq!(backend.received(), 0);
+    assert_eq!(gateway.auth.received(), 0);
+}
+
+#[test]
+fn routes_can_override_the_policy() {
+    let backend = Backend::ok();
+    let gateway = gateway_for(&backend, "");
+
+    let response = get_from(&gateway, "/api/v1/public", "https://anyone.test");
+    assert_eq!(response.header("access-control-allow-origin"), Some("*"));
+    assert_eq!(response.header("access-control-allow-credentials"), None);
+
+    let response = preflight(&gateway, "/api/v1/public", "https://app.example.com");
+    assert_eq!(response.header("access-control-allow-methods"), Some("GET"));
+}
+
+#[test]
+fn denied_addresses_get_no_preflight() {
+    let backend = Backend::ok();
+    let gateway = gateway_for(&backend, "ip_filter:\n  deny: [\"127.0.0.0/8\"]\n");
+
+    let response = preflight(&gateway, "/api/v1/plans", "https://app.example.com");
+
+    assert_eq!(response.status, 403);
+    assert_eq!(response.header("access-control-allow-origin"), None);
+}
+
+#[test]
+fn configs_without_a_policy_are_refused_at_startup() {
+    let (output, _) = run_until_exit(&format!("127.0.0.1:{}", free_port()), "services: []");
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("No CORS policy configured"));
+}
@@ -0,0 +1,105 @@
+mod common;
+
+use common::{service, Backend, CapturedRequest, Gateway};
+
+/// A gateway echoing the client address it settled on to the backend in `x-client-ip`.
+fn gateway_for(backend: &Backend, trusted_proxies: &str) -> Gateway {
+    Gateway::start(&format!(
+        "trusted_proxies: {}\nheaders:\n  request:\n    - {{ action: set, name: \"x-client-ip\", value: \"{{client_ip}}\" }}\nservices:\n{}",
+        trusted_proxies,
+        service("/api/v1/plans", backend.port, "")
+    ))
+}
+
+fn forward(gateway: &Gateway, backend: &Backend, headers: &str) -> CapturedRequest {
+    let response = gateway.send(&format!(
+        "GET /api/v1/plans HTTP/1.1\r\nhost: gateway.test\r\n{}connection: close\r\n\r\n",
+        headers
+    ));
+    assert_eq!(response.status, 200);
+    backend.next_request()
+}
+
+#[test]
+fn forwarding_headers_from_untrusted_peers_are_replaced() {
+    let backend = Backend::ok();
+    let gateway = gateway_for(&backend, "[]");
+
+    let request = forward(
+        &gateway,
+        &backend,
+        "x-forwarded-for: 6.6.6.6\r\nforwarded: for=6.6.6.6;proto=https\r\nx-forwarded-proto: https\r\nx-forwarded-host: evil.test\r\nx-forwarded-port: 1\r\n",
+    );
+
+    let port = gateway.port.to_string();
+    assert_eq!(request.header("x-client-ip"), Some("127.0.0.1"));
+    assert_eq!(request.header("x-forwarded-for"), Some("127.0.0.1"));
+    assert_eq!(
+        request.header("forwarded"),
+        Some("for=127.0.0.1;proto=http;host=\"gateway.test\"")
+    );
+    assert_eq!(request.header("x-forwarded-proto"), Some("http"));
+    assert_eq!(request.header("x-forwarded-host"), Some("gateway.test"));
+    assert_eq!(request.header("x-forwarded-port"), Some(port.as_str()));
+}
+
+#[test]
+fn chains_through_trusted_proxies_are_walked_and_extended() {
+    let backend = Backend::ok();
+    let gateway = gateway_for(&backend, "[\"127.0.0.1\", \"10.0.0.0/8\"]");
+
+    let request = forward(
+        &gateway,
+        &backend,
+        "x-forwarded-for: 198.51.100.7, 203.0.113.9\r\nx-forwarded-for: 10.0.0.2\r\nforwarded: for=203.0.113.9;proto=https, for=10.0.0.2\r\nx-forwarded-proto: https\r\nx-forwarded-host: public.example\r\nx-forwarded-port: 443\r\n",
+    );
+
+    // The first hop not a trusted proxy, the ones before it may have been made up
+    assert_eq!(request.header("x-client-ip"), Some("203.0.113.9"));
+    assert_eq!(
+        request.header("x-forwarded-for"),
+        Some("198.51.100.7, 203.0.113.9, 10.0.0.2, 127.0.0.1")
+    );
+    assert_eq!(
+        request.header("forwarded"),
+        Some("for=203.0.113.9;proto=https, for=10.0.0.2, for=127.0.0.1;proto=http;host=\"gateway.test\"")
+    );
+    assert_eq!(request.header("x-forwarded-proto"), Some("https"));
+    assert_eq!(request.header("x-forwarded-host"), Some("public.example"));
+    assert_eq!(request.header("x-forwarded-port"), Some("443"));
+}
+
+#[test]
+fn forwarded_is_used_without_x_forwarded_for() {
+    let backend = Backend::ok();
+    let gateway = gateway_for(&backend, "[\"127.0.0.1\"]");
+
+    let request = forward(
+        &gateway,
+        &backend,
+        "forwarded: for=\"[2001:db8::7]:4711\";proto=https\r\n",
+    );
+    assert_eq!(request.header("x-client-ip"), Some("2001:db8::7"));
+    assert_eq!(request.header("x-forwarded-for"), Some("127.0.0.1"));
+
+    // An obfuscated hop stops the walk at the last trusted proxy
+    let request = forward(
+        &gateway,
+        &backend,
+        "forwarded: for=198.51.100.7, for=_hidden\r\n",
+    );
+    assert_eq!(request.header("x-client-ip"), Some("127.0.0.1"));
+}
+
+#[test]
+fn log_entries_carry_the_resolved_client_address() {
+    let backend = Backend::ok();
+    let gateway = gateway_for(&backend, "[\"127.0.0.1\"]");
+
+    gateway.send(
+        "GET /unknown HTTP/1.1\r\nhost: gateway.test\r\nx-forwarded-for: 203.0.113.9\r\nconnection: close\r\n\r\n",
+    );
+
+    let entry = gateway.wait_for_log("out.log", "Path not found");
+    assert!(entry.contains("\"ip\":\"203.0.113.9\""), "{}", entry);
+}
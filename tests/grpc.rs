@@ -0,0 +1,183 @@
+mod common;
+
+use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD};
+use base64::Engine;
+use common::{free_port, service, Gateway};
+use http_body_util::{BodyExt, Collected, Full};
+use hyper::body::{Bytes, Incoming};
+use hyper::header::{HeaderMap, HeaderValue, CONTENT_TYPE, TE};
+use hyper::http::response::Parts;
+use hyper::server::conn::http2;
+use hyper::service::service_fn;
+use hyper::{Request, Response};
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use std::convert::Infallible;
+use tokio::net::TcpListener;
+
+type EchoBody = http_body_util::combinators::BoxBody<Bytes, Infallible>;
+
+/// Echoes the request message back with `grpc-status: 0` trailers, and reports what it
+/// received in `x-seen-*` headers.
+async fn echo(req: Request<Incoming>) -> Result<Response<EchoBody>, Infallible> {
+    let seen = |name| {
+        req.headers()
+            .get(name)
+            .cloned()
+            .unwrap_or(HeaderValue::from_static(""))
+    };
+    let response = Response::builder()
+        .header(CONTENT_TYPE, "application/grpc+proto")
+        .header("x-seen-te", seen(TE))
+        .header("x-seen-content-type", seen(CONTENT_TYPE))
+        .header("x-seen-path", req.uri().path());
+    let message = req.into_body().collect().await.unwrap().to_bytes();
+
+    let mut trailers = HeaderMap::new();
+    trailers.insert("grpc-status", HeaderValue::from_static("0"));
+    trailers.insert("grpc-message", HeaderValue::from_static("echoed"));
+    let body = Full::new(message)
+        .with_trailers(async move { Some(Ok(trailers)) })
+        .boxed();
+    Ok(response.body(body).unwrap())
+}
+
+/// Starts a plaintext HTTP/2 gRPC backend, returning its port.
+async fn grpc_backend() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(
+                http2::Builder::new(TokioExecutor::new())
+                    .serve_connection(TokioIo::new(stream), service_fn(echo)),
+            );
+        }
+    });
+    port
+}
+
+fn gateway_for(port: u16) -> Gateway {
+    Gateway::start(&format!(
+        "services:\n{}",
+        service("/helloworld.Greeter/", port, "    grpc: {}\n")
+    ))
+}
+
+/// A length-prefixed gRPC message.
+fn message(payload: &[u8]) -> Vec<u8> {
+    let mut message = vec![0];
+    message.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    message.extend_from_slice(payload);
+    message
+}
+
+async fn call(
+    gateway: &Gateway,
+    http2: bool,
+    content_type: &str,
+    body: Vec<u8>,
+) -> (Parts, Collected<Bytes>) {
+    let client = Client::builder(TokioExecutor::new())
+        .http2_only(http2)
+        .build_http();
+    let req = Request::post(format!(
+        "http://127.0.0.1:{}/helloworld.Greeter/SayHello",
+        gateway.port
+    ))
+    .header(CONTENT_TYPE, content_type)
+    .body(Full::new(Bytes::from(body)))
+    .unwrap();
+    let (parts, body) = client.request(req).await.unwrap().into_parts();
+    (parts, body.collect().await.unwrap())
+}
+
+#[tokio::test]
+async fn grpc_calls_are_proxied_over_http2_with_trailers() {
+    let gateway = gateway_for(grpc_backend().await);
+
+    let (parts, body) = call(&gateway, true, "application/grpc", message(b"hello")).await;
+
+    assert_eq!(parts.status, 200);
+    assert_eq!(parts.headers["x-seen-te"], "trailers");
+    assert_eq!(parts.headers["x-seen-path"], "/helloworld.Greeter/SayHello");
+    let trailers = body.trailers().cloned().expect("missing trailers");
+    assert_eq!(trailers["grpc-status"], "0");
+    assert_eq!(trailers["grpc-message"], "echoed");
+    assert_eq!(body.to_bytes(), message(b"hello"));
+}
+
+#[tokio::test]
+async fn grpc_web_calls_get_trailers_in_the_body() {
+    let gateway = gateway_for(grpc_backend().await);
+
+    let (parts, body) = call(
+        &gateway,
+        false,
+        "application/grpc-web+proto",
+        message(b"hello"),
+    )
+    .await;
+
+    assert_eq!(parts.headers["content-type"], "application/grpc-web+proto");
+    assert_eq!(
+        parts.headers["x-seen-content-type"],
+        "application/grpc+proto"
+    );
+    let mut expected = message(b"hello");
+    let trailers = b"grpc-status: 0\r\ngrpc-message: echoed\r\n";
+    expected.push(0x80);
+    expected.extend_from_slice(&(trailers.len() as u32).to_be_bytes());
+    expected.extend_from_slice(trailers);
+    assert_eq!(body.to_bytes(), expected);
+}
+
+#[tokio::test]
+async fn grpc_web_text_calls_are_base64_decoded_and_encoded() {
+    let gateway = gateway_for(grpc_backend().await);
+    // Messages encoded one by one, so padding appears mid-stream
+    let request = format!(
+        "{}{}",
+        STANDARD.encode(message(b"hi")),
+        STANDARD.encode(message(b"there"))
+    );
+
+    let (parts, body) = call(
+        &gateway,
+        false,
+        "application/grpc-web-text",
+        request.into_bytes(),
+    )
+    .await;
+
+    assert_eq!(
+        parts.headers["content-type"],
+        "application/grpc-web-text+proto"
+    );
+    assert_eq!(parts.headers["x-seen-content-type"], "application/grpc");
+    let text = String::from_utf8(body.to_bytes().to_vec()).unwrap();
+    let mut decoded = Vec::new();
+    for chunk in text
+        .split_inclusive('=')
+        .filter(|chunk| !chunk.starts_with('='))
+    {
+        let chunk = chunk.trim_end_matches('=');
+        decoded.extend(STANDARD_NO_PAD.decode(chunk).unwrap());
+    }
+    let mut expected = message(b"hi");
+    expected.extend(message(b"there"));
+    assert!(decoded.starts_with(&expected));
+    assert!(decoded[expected.len()..].starts_with(&[0x80]));
+}
+
+#[tokio::test]
+async fn gateway_errors_are_mapped_to_grpc_status() {
+    let gateway = gateway_for(free_port());
+
+    let (parts, body) = call(&gateway, true, "application/grpc", message(b"hello")).await;
+
+    assert_eq!(parts.status, 200);
+    assert_eq!(parts.headers["content-type"], "application/grpc");
+    assert_eq!(parts.headers["grpc-status"], "14");
+    assert!(body.to_bytes().is_empty());
+}
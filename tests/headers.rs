@@ -0,0 +1,89 @@
+mod common;
+
+use common::{service, Backend, Gateway};
+
+const RULES: &str = "headers:
+  request:
+    - { action: set, name: \"x-request-ref\", value: \"{request_id}\" }
+    - { action: set, name: \"x-client\", value: \"{client_ip}\" }
+    - { action: set, name: \"x-user-id\", value: \"{auth.userId}\" }
+    - { action: set, name: \"x-gateway\", value: \"{env.CARGO_PKG_NAME}\" }
+    - { action: append, name: \"x-tags\", value: \"gateway\" }
+    - { action: remove, name: \"x-debug\" }
+    - { action: rename, name: \"x-legacy-token\", to: \"x-token\" }
+  response:
+    - { action: remove, name: \"x-internal-trace\" }
+    - { action: rename, name: \"x-old\", to: \"x-new\" }
+    - { action: append, name: \"x-served-by\", value: \"gateway\" }
+    - { action: set, name: \"x-request-ref\", value: \"{request_id}\" }
+";
+
+fn gateway_for(backend: &Backend) -> Gateway {
+    Gateway::start(&format!(
+        "{}services:\n{}",
+        RULES,
+        service(
+            "/api/v1/plans",
+            backend.port,
+            "    route: \"/api/v1/plans/{plan_id}\"\n    headers:\n      request:\n        - { action: set, name: \"x-plan-id\", value: \"{param.plan_id}\" }\n        - { action: set, name: \"x-user-id\", value: \"patient-{auth.userId}\" }\n      response:\n        - { action: set, name: \"x-plan-id\", value: \"{param.plan_id}\" }\n"
+        ),
+    ))
+}
+
+#[test]
+fn request_rules_are_applied_with_their_templates() {
+    let backend = Backend::ok();
+    let gateway = gateway_for(&backend);
+
+    let response = gateway.send(
+        "GET /api/v1/plans/9 HTTP/1.1\r\nhost: gateway.test\r\nx-tags: client\r\nx-debug: 1\r\nx-legacy-token: abc\r\nx-user-id: spoofed\r\nconnection: close\r\n\r\n",
+    );
+
+    let request = backend.next_request();
+    assert_eq!(
+        request.header("x-request-ref"),
+        response.header("x-request-id")
+    );
+    assert_eq!(request.header("x-client"), Some("127.0.0.1"));
+    assert_eq!(request.header("x-gateway"), Some(env!("CARGO_PKG_NAME")));
+    let tags: Vec<_> = request
+        .headers
+        .iter()
+        .filter(|(name, _)| name == "x-tags")
+        .map(|(_, value)| value.as_str())
+        .collect();
+    assert_eq!(tags, ["client", "gateway"]);
+    assert_eq!(request.header("x-debug"), None);
+    assert_eq!(request.header("x-legacy-token"), None);
+    assert_eq!(request.header("x-token"), Some("abc"));
+    // Route rules run after the global ones
+    assert_eq!(request.header("x-plan-id"), Some("9"));
+    assert_eq!(request.header("x-user-id"), Some("patient-u-42"));
+}
+
+#[test]
+fn response_rules_are_applied_with_their_templates() {
+    let backend = Backend::start(
+        "HTTP/1.1 200 OK\r\nx-internal-trace: t-1\r\nx-old: kept\r\nx-served-by: plans-svc\r\ncontent-length: 2\r\nconnection: close\r\n\r\n{}",
+    );
+    let gateway = gateway_for(&backend);
+
+    let response = gateway.get("/api/v1/plans/9");
+
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("x-internal-trace"), None);
+    assert_eq!(response.header("x-old"), None);
+    assert_eq!(response.header("x-new"), Some("kept"));
+    let served_by: Vec<_> = response
+        .headers
+        .iter()
+        .filter(|(name, _)| name == "x-served-by")
+        .map(|(_, value)| value.as_str())
+        .collect();
+    assert_eq!(served_by, ["plans-svc", "gateway"]);
+    assert_eq!(
+        response.header("x-request-ref"),
+        response.header("x-request-id")
+    );
+    assert_eq!(response.header("x-plan-id"), Some("9"));
+}
@@ -0,0 +1,64 @@
+mod common;
+
+use common::{service, Backend, Gateway};
+
+fn get_from(gateway: &Gateway, target: &str, client_ip: &str) -> u16 {
+    gateway
+        .send(&format!(
+            "GET {} HTTP/1.1\r\nhost: gateway.test\r\nx-forwarded-for: {}\r\nconnection: close\r\n\r\n",
+            target, client_ip
+        ))
+        .status
+}
+
+#[test]
+fn denied_addresses_get_forbidden() {
+    let backend = Backend::ok();
+    let gateway = Gateway::start(&format!(
+        "trusted_proxies: [\"127.0.0.1\"]\nip_filter:\n  deny: [\"203.0.113.0/24\", \"2001:db8:bad::/48\"]\nservices:\n{}",
+        service("/api/v1/plans", backend.port, "")
+    ));
+
+    assert_eq!(get_from(&gateway, "/api/v1/plans", "203.0.113.7"), 403);
+    assert_eq!(get_from(&gateway, "/api/v1/plans", "2001:db8:bad::1"), 403);
+    assert_eq!(backend.received(), 0);
+
+    assert_eq!(get_from(&gateway, "/api/v1/plans", "198.51.100.1"), 200);
+    assert_eq!(get_from(&gateway, "/api/v1/plans", "2001:db8:beef::1"), 200);
+    assert_eq!(backend.received(), 2);
+}
+
+#[test]
+fn route_allow_lists_match_ipv4_and_ipv6_ranges() {
+    let backend = Backend::ok();
+    let gateway = Gateway::start(&format!(
+        "trusted_proxies: [\"127.0.0.1\"]\nservices:\n{}{}",
+        service(
+            "/api/v1/admin",
+            backend.port,
+            "    ip_filter:\n      allow: [\"10.8.0.0/16\", \"2001:db8::/32\"]\n      deny: [\"10.8.9.0/24\"]\n"
+        ),
+        service("/api/v1/plans", backend.port, ""),
+    ));
+
+    assert_eq!(get_from(&gateway, "/api/v1/admin", "10.8.3.4"), 200);
+    assert_eq!(get_from(&gateway, "/api/v1/admin", "2001:db8:1::7"), 200);
+    assert_eq!(get_from(&gateway, "/api/v1/admin", "10.9.0.1"), 403);
+    assert_eq!(get_from(&gateway, "/api/v1/admin", "2001:db9::1"), 403);
+    // Deny wins over allow
+    assert_eq!(get_from(&gateway, "/api/v1/admin", "10.8.9.1"), 403);
+    // Other routes are not restricted
+    assert_eq!(get_from(&gateway, "/api/v1/plans", "10.9.0.1"), 200);
+}
+
+#[test]
+fn untrusted_peers_cannot_spoof_their_address() {
+    let backend = Backend::ok();
+    let gateway = Gateway::start(&format!(
+        "ip_filter:\n  deny: [\"127.0.0.0/8\"]\nservices:\n{}",
+        service("/api/v1/plans", backend.port, "")
+    ));
+
+    assert_eq!(get_from(&gateway, "/api/v1/plans", "10.8.3.4"), 403);
+    assert_eq!(backend.received(), 0);
+}
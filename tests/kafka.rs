@@ -0,0 +1,276 @@
+mod common;
+
+use common::{free_port, service, Backend, Gateway};
+use flate2::read::GzDecoder;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const TOPIC: &str = "gateway-logs";
+
+/// A single-broker Kafka speaking just enough of the v0 protocol for a producer: it
+/// answers metadata requests and acknowledges every produced message.
+struct FakeBroker {
+    messages: Arc<Mutex<Vec<String>>>,
+    connections: Arc<AtomicUsize>,
+}
+
+impl FakeBroker {
+    fn start(port: u16) -> FakeBroker {
+        let listener = TcpListener::bind(("127.0.0.1", port)).unwrap();
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let connections = Arc::new(AtomicUsize::new(0));
+        let broker = FakeBroker {
+            messages: messages.clone(),
+            connections: connections.clone(),
+        };
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else { continue };
+                connections.fetch_add(1, Ordering::SeqCst);
+                let messages = messages.clone();
+                thread::spawn(move || serve(stream, port, &messages));
+            }
+        });
+        broker
+    }
+
+    /// Waits until a message containing `needle` was produced, returning how many did.
+    fn wait_for(&self, needle: &str, count: usize) -> usize {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            let received = self
+                .messages
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|message| message.contains(needle))
+                .count();
+            if received >= count {
+                return received;
+            }
+            assert!(Instant::now() < deadline, "{} was not produced", needle);
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+}
+
+fn serve(mut stream: TcpStream, port: u16, messages: &Mutex<Vec<String>>) {
+    loop {
+        let mut size = [0u8; 4];
+        if stream.read_exact(&mut size).is_err() {
+            return;
+        }
+        let mut request = vec![0u8; i32::from_be_bytes(size) as usize];
+        if stream.read_exact(&mut request).is_err() {
+            return;
+        }
+        let mut reader = Reader(&request);
+        let api_key = reader.i16();
+        reader.i16();
+        let correlation_id = reader.i32();
+        reader.string();
+
+        let mut response = correlation_id.to_be_bytes().to_vec();
+        match api_key {
+            0 => {
+                reader.i16();
+                reader.i32();
+                let mut topics = Vec::new();
+                for _ in 0..reader.i32() {
+                    let topic = reader.string();
+                    let mut partitions = Vec::new();
+                    for _ in 0..reader.i32() {
+                        partitions.push(reader.i32());
+                        let set = reader.bytes(reader.clone().i32() as usize + 4);
+                        messages.lock().unwrap().extend(message_set(&set[4..]));
+                    }
+                    topics.push((topic, partitions));
+                }
+                put_i32(&mut response, topics.len() as i32);
+                for (topic, partitions) in topics {
+                    put_string(&mut response, &topic);
+                    put_i32(&mut response, partitions.len() as i32);
+                    for partition in partitions {
+                        put_i32(&mut response, partition);
+                        response.extend_from_slice(&0i16.to_be_bytes());
+                        response.extend_from_slice(&0i64.to_be_bytes());
+                    }
+                }
+            }
+            3 => {
+                put_i32(&mut response, 1);
+                put_i32(&mut response, 0);
+                put_string(&mut response, "127.0.0.1");
+                put_i32(&mut response, port as i32);
+                put_i32(&mut response, 1);
+                response.extend_from_slice(&0i16.to_be_bytes());
+                put_string(&mut response, TOPIC);
+                put_i32(&mut response, 1);
+                response.extend_from_slice(&0i16.to_be_bytes());
+                for value in [0, 0, 1, 0, 1, 0] {
+                    put_i32(&mut response, value);
+                }
+            }
+            _ => return,
+        }
+
+        let mut frame = (response.len() as i32).to_be_bytes().to_vec();
+        frame.extend_from_slice(&response);
+        if stream.write_all(&frame).is_err() {
+            return;
+        }
+    }
+}
+
+/// The values of a message set, unpacking gzip-compressed wrapper messages.
+fn message_set(mut set: &[u8]) -> Vec<String> {
+    let mut values = Vec::new();
+    while set.len() >= 12 {
+        let mut reader = Reader(set);
+        reader.i64();
+        let size = reader.i32() as usize;
+        let mut message = Reader(reader.bytes(size));
+        set = reader.0;
+
+        message.i32();
+        let magic = message.i8();
+        let attributes = message.i8();
+        if magic == 1 {
+            message.i64();
+        }
+        let key = message.i32();
+        if key > 0 {
+            message.bytes(key as usize);
+        }
+        let length = message.i32() as usize;
+        let value = message.bytes(length);
+        match attributes & 0x07 {
+            1 => {
+                let mut inner = Vec::new();
+                GzDecoder::new(value).read_to_end(&mut inner).unwrap();
+                values.extend(message_set(&inner));
+            }
+            _ => values.push(String::from_utf8_lossy(value).to_string()),
+        }
+    }
+    values
+}
+
+#[derive(Clone)]
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> &'a [u8] {
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        head
+    }
+
+    fn i8(&mut self) -> i8 {
+        self.bytes(1)[0] as i8
+    }
+
+    fn i16(&mut self) -> i16 {
+        i16::from_be_bytes(self.bytes(2).try_into().unwrap())
+    }
+
+    fn i32(&mut self) -> i32 {
+        i32::from_be_bytes(self.bytes(4).try_into().unwrap())
+    }
+
+    fn i64(&mut self) -> i64 {
+        i64::from_be_bytes(self.bytes(8).try_into().unwrap())
+    }
+
+    fn string(&mut self) -> String {
+        let len = self.i16();
+        String::from_utf8_lossy(self.bytes(len.max(0) as usize)).to_string()
+    }
+}
+
+fn put_i32(buffer: &mut Vec<u8>, value: i32) {
+    buffer.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_string(buffer: &mut Vec<u8>, value: &str) {
+    buffer.extend_from_slice(&(value.len() as i16).to_be_bytes());
+    buffer.extend_from_slice(value.as_bytes());
+}
+
+fn gateway_for(backend: &Backend, kafka_port: u16, kafka: &str) -> Gateway {
+    Gateway::start_with_logger(
+        &format!(
+            "  use_kafka: true\n  kafka_host: \"127.0.0.1:{}\"\n  kafka_topic: \"{}\"\n  kafka:\n    linger_ms: 20\n{}",
+            kafka_port, TOPIC, kafka
+        ),
+        &format!(
+            "services:\n{}",
+            service("/api/v1/plans", backend.port, "")
+        ),
+    )
+}
+
+#[test]
+fn entries_are_produced_over_one_connection() {
+    let backend = Backend::ok();
+    let kafka_port = free_port();
+    let broker = FakeBroker::start(kafka_port);
+    let gateway = gateway_for(&backend, kafka_port, "");
+
+    for _ in 0..5 {
+        assert_eq!(gateway.get("/api/v1/plans").status, 200);
+    }
+
+    assert_eq!(broker.wait_for("Connection closed", 5), 5);
+    assert_eq!(broker.connections.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn entries_are_spilled_while_kafka_is_down() {
+    let backend = Backend::ok();
+    let kafka_port = free_port();
+    let gateway = gateway_for(
+        &backend,
+        kafka_port,
+        "    spill_file: \"{dir}/kafka-spill.log\"\n    backoff_initial_ms: 50\n    backoff_max_ms: 100\n",
+    );
+    let spill_file = gateway.dir.join("kafka-spill.log");
+
+    for _ in 0..3 {
+        gateway.get("/api/v1/plans");
+    }
+    gateway.wait_for_log("kafka-spill.log", "Connection closed");
+
+    let broker = FakeBroker::start(kafka_port);
+    gateway.get("/api/v1/plans");
+
+    assert_eq!(broker.wait_for("Connection closed", 4), 4);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while spill_file.exists() {
+        assert!(Instant::now() < deadline, "the spill file was not replayed");
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn entries_are_redacted_before_they_are_produced() {
+    let backend = Backend::ok();
+    let kafka_port = free_port();
+    let broker = FakeBroker::start(kafka_port);
+    let gateway = gateway_for(
+        &backend,
+        kafka_port,
+        "  redaction:\n    rules:\n      - { query: patient_id }\n",
+    );
+
+    gateway.get("/unknown?patient_id=P-77");
+
+    broker.wait_for("\"params\":\"patient_id=***\"", 1);
+    let messages = broker.messages.lock().unwrap();
+    assert!(messages.iter().all(|message| !message.contains("P-77")));
+}
@@ -0,0 +1,166 @@
+mod common;
+
+use common::{admin_request, free_port, service, Backend, Gateway};
+use serde_json::Value;
+use std::io::Write;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const OK: &str = "HTTP/1.1 200 OK\r\ncontent-length: 2\r\nconnection: close\r\n\r\n{}";
+
+/// A backend taking `delay` to answer every request.
+fn slow_backend(delay: Duration) -> Backend {
+    Backend::start_with(move |mut stream| {
+        thread::sleep(delay);
+        let _ = stream.write_all(OK.as_bytes());
+    })
+}
+
+fn gateway_for(limits: &str, services: &str) -> Gateway {
+    Gateway::start(&format!("limits:\n{}services:\n{}", limits, services))
+}
+
+/// Sends a request from another thread, answering its status.
+fn get_in_background(gateway: &Gateway, target: &str) -> thread::JoinHandle<u16> {
+    let mut stream = gateway.connect();
+    let request = format!(
+        "GET {} HTTP/1.1\r\nhost: gateway.test\r\nconnection: close\r\n\r\n",
+        target
+    );
+    thread::spawn(move || {
+        stream.write_all(request.as_bytes()).unwrap();
+        common::read_head(&mut stream).status
+    })
+}
+
+fn usage(admin_port: u16) -> Value {
+    let response = admin_request(
+        admin_port,
+        "GET /limits HTTP/1.1\r\nhost: admin\r\nconnection: close\r\n\r\n",
+    );
+    serde_json::from_str(&response[response.find("\r\n\r\n").unwrap() + 4..]).unwrap()
+}
+
+#[test]
+fn requests_beyond_the_limit_are_shed_with_retry_after() {
+    let backend = slow_backend(Duration::from_millis(800));
+    let gateway = gateway_for(
+        "  queue_size: 0\n  retry_after_secs: 7\n",
+        &service("/api/v1/slow", backend.port, "    max_in_flight: 1\n"),
+    );
+
+    let first = get_in_background(&gateway, "/api/v1/slow");
+    backend.next_request();
+    let shed = gateway.get("/api/v1/slow");
+    assert_eq!(shed.status, 503);
+    assert_eq!(shed.header("retry-after"), Some("7"));
+
+    assert_eq!(first.join().unwrap(), 200);
+    assert_eq!(gateway.get("/api/v1/slow").status, 200);
+}
+
+#[test]
+fn queued_requests_give_up_after_the_queue_timeout() {
+    let backend = slow_backend(Duration::from_millis(1500));
+    let gateway = gateway_for(
+        "  queue_size: 1\n  queue_timeout_ms: 200\n",
+        &service("/api/v1/slow", backend.port, "    max_in_flight: 1\n"),
+    );
+
+    let first = get_in_background(&gateway, "/api/v1/slow");
+    backend.next_request();
+    let started = Instant::now();
+    let queued = gateway.get("/api/v1/slow");
+    assert_eq!(queued.status, 503);
+    assert!(started.elapsed() >= Duration::from_millis(200));
+    assert!(started.elapsed() < Duration::from_millis(1200));
+    assert_eq!(first.join().unwrap(), 200);
+    assert_eq!(backend.received(), 0);
+}
+
+#[test]
+fn clients_leaving_the_queue_give_up_their_place() {
+    let backend = slow_backend(Duration::from_millis(1500));
+    let admin_port = free_port();
+    let gateway = Gateway::start(&format!(
+        "admin_url: \"127.0.0.1:{}\"\nlimits:\n  queue_size: 1\n  queue_timeout_ms: 5000\nservices:\n{}",
+        admin_port,
+        service("/api/v1/slow", backend.port, "    max_in_flight: 1\n"),
+    ));
+    let waiting = || usage(admin_port)["services"]["/api/v1/slow"]["waiting"].clone();
+
+    let first = get_in_background(&gateway, "/api/v1/slow");
+    backend.next_request();
+    let mut queued = gateway.connect();
+    queued
+        .write_all(b"GET /api/v1/slow HTTP/1.1\r\nhost: gateway.test\r\n\r\n")
+        .unwrap();
+    let deadline = Instant::now() + Duration::from_secs(1);
+    while waiting() != 1 {
+        assert!(Instant::now() < deadline, "request was not queued");
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    drop(queued);
+    while waiting() != 0 {
+        assert!(Instant::now() < deadline, "queue place was not given up");
+        thread::sleep(Duration::from_millis(20));
+    }
+    assert_eq!(first.join().unwrap(), 200);
+}
+
+#[test]
+fn a_saturated_service_does_not_hold_global_permits() {
+    let slow = slow_backend(Duration::from_millis(1500));
+    let fast = Backend::ok();
+    let gateway = gateway_for(
+        "  max_in_flight: 2\n  queue_size: 4\n  queue_timeout_ms: 3000\n",
+        &format!(
+            "{}{}",
+            service("/api/v1/slow", slow.port, "    max_in_flight: 1\n"),
+            service("/api/v1/fast", fast.port, ""),
+        ),
+    );
+
+    let slow_requests: Vec<_> = (0..3)
+        .map(|_| get_in_background(&gateway, "/api/v1/slow"))
+        .collect();
+    slow.next_request();
+    thread::sleep(Duration::from_millis(100));
+
+    // The queued slow requests wait for their service, not for one of the two global permits
+    let started = Instant::now();
+    assert_eq!(gateway.get("/api/v1/fast").status, 200);
+    assert!(started.elapsed() < Duration::from_millis(1000));
+    drop(slow_requests);
+}
+
+#[test]
+fn both_waits_share_one_queue_timeout() {
+    let slow = slow_backend(Duration::from_millis(400));
+    let other = slow_backend(Duration::from_millis(900));
+    let gateway = gateway_for(
+        "  max_in_flight: 2\n  queue_size: 4\n  queue_timeout_ms: 600\n",
+        &format!(
+            "{}{}",
+            service("/api/v1/slow", slow.port, "    max_in_flight: 1\n"),
+            service("/api/v1/other", other.port, ""),
+        ),
+    );
+
+    let first = get_in_background(&gateway, "/api/v1/slow");
+    slow.next_request();
+    let second = get_in_background(&gateway, "/api/v1/other");
+    other.next_request();
+    // Takes the global permit the first request gives back
+    let third = get_in_background(&gateway, "/api/v1/other");
+    thread::sleep(Duration::from_millis(50));
+
+    // Waits for its service, then for a global permit, within a single queue timeout
+    let started = Instant::now();
+    assert_eq!(gateway.get("/api/v1/slow").status, 503);
+    assert!(started.elapsed() < Duration::from_millis(850));
+    for request in [first, second, third] {
+        assert_eq!(request.join().unwrap(), 200);
+    }
+}
@@ -0,0 +1,320 @@
+mod common;
+
+use common::{admin_request, free_port, run_until_exit, service, Backend, Gateway};
+use flate2::read::GzDecoder;
+use serde_json::Value;
+use std::fs::File;
+use std::io::Read;
+use std::net::TcpListener;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[test]
+fn entries_are_written_by_the_background_writer() {
+    let backend = Backend::ok();
+    let gateway = Gateway::start(&format!(
+        "services:\n{}",
+        service("/api/v1/plans", backend.port, "")
+    ));
+
+    assert_eq!(gateway.get("/api/v1/plans").status, 200);
+    assert_eq!(gateway.get("/api/v1/unknown").status, 404);
+
+    let closed = gateway.wait_for_log("out.log", "Connection closed");
+    let not_found = gateway.wait_for_log("out.log", "Path not found");
+    assert!(closed.contains("\"level\":\"info\""));
+    assert!(not_found.contains("\"level\":\"warn\""));
+}
+
+#[test]
+fn errors_go_to_the_error_log() {
+    let gateway = Gateway::start(&format!(
+        "services:\n{}",
+        service("/api/v1/plans", free_port(), "")
+    ));
+
+    assert_eq!(gateway.get("/api/v1/plans").status, 503);
+
+    let line = gateway.wait_for_log("err.log", "Failed to connect to downstream service");
+    assert!(line.contains("\"level\":\"err\""));
+}
+
+#[test]
+fn the_admin_endpoint_reports_the_queue() {
+    let admin_port = free_port();
+    let gateway = Gateway::start(&format!(
+        "admin_url: \"127.0.0.1:{}\"\nservices:\n{}",
+        admin_port,
+        service("/api/v1/plans", free_port(), "")
+    ));
+    gateway.get("/api/v1/plans");
+
+    let usage = admin_request(
+        admin_port,
+        "GET /logger HTTP/1.1\r\nhost: admin\r\nconnection: close\r\n\r\n",
+    );
+
+    assert!(usage.contains("\"capacity\":10000"));
+    assert!(usage.contains("\"dropped\":0"));
+}
+
+/// Sends requests from several threads at once, so log entries arrive concurrently.
+fn concurrent_requests(gateway: &Arc<Gateway>, count: usize) {
+    let requests: Vec<_> = (0..count)
+        .map(|_| {
+            let gateway = gateway.clone();
+            thread::spawn(move || gateway.get("/api/v1/plans"))
+        })
+        .collect();
+    for request in requests {
+        assert_eq!(request.join().unwrap().status, 200);
+    }
+}
+
+#[test]
+fn a_full_blocking_queue_holds_requests_up_without_dropping_entries() {
+    let backend = Backend::ok();
+    let admin_port = free_port();
+    let gateway = Arc::new(Gateway::start_with_logger(
+        "  queue:\n    capacity: 1\n    batch_size: 1\n    overflow: block\n",
+        &format!(
+            "admin_url: \"127.0.0.1:{}\"\nservices:\n{}",
+            admin_port,
+            service("/api/v1/plans", backend.port, "")
+        ),
+    ));
+
+    concurrent_requests(&gateway, 32);
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let closed = || {
+        std::fs::read_to_string(gateway.dir.join("out.log"))
+            .unwrap_or_default()
+            .matches("Connection closed")
+            .count()
+    };
+    while closed() < 32 {
+        assert!(Instant::now() < deadline, "entries were lost");
+        thread::sleep(Duration::from_millis(20));
+    }
+    let usage = admin_request(
+        admin_port,
+        "GET /logger HTTP/1.1\r\nhost: admin\r\nconnection: close\r\n\r\n",
+    );
+    assert!(usage.contains("\"dropped\":0"));
+}
+
+fn assert_whole_lines(log: &str) {
+    for line in log.lines() {
+        let entry: Value = serde_json::from_str(line).expect("a whole log line");
+        assert_eq!(entry["service"], "api-gateway");
+    }
+}
+
+fn wait_for_file(path: &Path) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !path.exists() {
+        assert!(
+            Instant::now() < deadline,
+            "{} was not written",
+            path.display()
+        );
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+fn rotating_gateway(backend: &Backend, rotation: &str) -> Arc<Gateway> {
+    Arc::new(Gateway::start_with_logger(
+        &format!("  rotation:\n{}", rotation),
+        &format!(
+            "access_log:\n  enabled: false\nservices:\n{}",
+            service("/api/v1/plans", backend.port, "")
+        ),
+    ))
+}
+
+#[test]
+fn logs_rotate_by_size_keeping_whole_lines() {
+    let backend = Backend::ok();
+    let gateway = rotating_gateway(&backend, "    max_bytes: 1000\n    max_files: 2\n");
+
+    concurrent_requests(&gateway, 20);
+    wait_for_file(&gateway.dir.join("out.log.2"));
+    gateway.get("/api/v1/plans");
+    gateway.wait_for_log("out.log", "Connection closed");
+
+    assert!(!gateway.dir.join("out.log.3").exists());
+    for file in ["out.log", "out.log.1", "out.log.2"] {
+        let log = std::fs::read_to_string(gateway.dir.join(file)).unwrap();
+        assert!(log.len() <= 1000);
+        assert_whole_lines(&log);
+    }
+}
+
+#[test]
+fn rotated_logs_can_be_compressed() {
+    let backend = Backend::ok();
+    let gateway = rotating_gateway(&backend, "    max_bytes: 1000\n    compress: true\n");
+
+    concurrent_requests(&gateway, 10);
+    let rotated = gateway.dir.join("out.log.1.gz");
+    wait_for_file(&rotated);
+    // The uncompressed file is removed once compression is done
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while gateway.dir.join("out.log.1").exists() {
+        assert!(Instant::now() < deadline, "out.log.1 was not compressed");
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    let mut log = String::new();
+    GzDecoder::new(File::open(rotated).unwrap())
+        .read_to_string(&mut log)
+        .unwrap();
+    assert!(log.contains("Connection closed"));
+    assert_whole_lines(&log);
+}
+
+#[test]
+fn logs_are_reopened_on_sigusr1() {
+    let backend = Backend::ok();
+    let gateway = Gateway::start(&format!(
+        "services:\n{}",
+        service("/api/v1/plans", backend.port, "")
+    ));
+    gateway.get("/api/v1/plans");
+    gateway.wait_for_log("out.log", "Connection closed");
+
+    std::fs::rename(
+        gateway.dir.join("out.log"),
+        gateway.dir.join("out.log.moved"),
+    )
+    .unwrap();
+    gateway.signal("USR1");
+    thread::sleep(Duration::from_millis(100));
+    gateway.get("/api/v1/plans");
+
+    gateway.wait_for_log("out.log", "Connection closed");
+}
+
+fn log_contents(gateway: &Gateway, file: &str) -> String {
+    std::fs::read_to_string(gateway.dir.join(file)).unwrap_or_default()
+}
+
+#[test]
+fn debug_entries_go_to_the_debug_file() {
+    let backend = Backend::ok();
+    let gateway = Gateway::start_with_logger(
+        "  level: debug\n",
+        &format!("services:\n{}", service("/api/v1/plans", backend.port, "")),
+    );
+
+    gateway.get("/api/v1/plans");
+
+    let line = gateway.wait_for_log("debug.log", "Forwarding request");
+    assert!(line.contains("\"level\":\"debug\""));
+    gateway.wait_for_log("out.log", "Connection closed");
+    assert!(!log_contents(&gateway, "debug.log").contains("Request received"));
+    assert!(!log_contents(&gateway, "out.log").contains("Forwarding request"));
+}
+
+#[test]
+fn modules_and_sinks_have_their_own_levels() {
+    let backend = Backend::ok();
+    let gateway = Gateway::start_with_logger(
+        "  level: err\n  modules:\n    hypergate: trace\n  sink_levels:\n    file: warn\n",
+        &format!("services:\n{}", service("/api/v1/plans", backend.port, "")),
+    );
+
+    gateway.get("/api/v1/plans");
+    gateway.get("/api/v1/unknown");
+
+    gateway.wait_for_log("out.log", "Path not found");
+    assert!(!log_contents(&gateway, "out.log").contains("Connection closed"));
+    assert!(!log_contents(&gateway, "debug.log").contains("Request received"));
+}
+
+#[test]
+fn the_level_can_be_changed_at_runtime() {
+    let backend = Backend::ok();
+    let admin_port = free_port();
+    let gateway = Gateway::start(&format!(
+        "admin_url: \"127.0.0.1:{}\"\nservices:\n{}",
+        admin_port,
+        service("/api/v1/plans", backend.port, "")
+    ));
+    let admin = |request: &str| {
+        admin_request(
+            admin_port,
+            &format!(
+                "{} HTTP/1.1\r\nhost: admin\r\nconnection: close\r\n\r\n",
+                request
+            ),
+        )
+    };
+
+    assert!(admin("PUT /logger?level=loud").starts_with("HTTP/1.1 400"));
+    let levels = admin("PUT /logger?level=trace");
+    assert!(levels.ends_with("{\"level\":\"trace\",\"modules\":{}}"));
+    gateway.get("/api/v1/plans");
+    gateway.wait_for_log("debug.log", "Request received");
+
+    admin("PUT /logger?module=hypergate&level=info");
+    assert!(admin("DELETE /logger?module=hypergate").ends_with("\"modules\":{}}"));
+}
+
+#[test]
+fn the_stdout_sink_writes_errors_to_stderr() {
+    let gateway = Gateway::start_with_logger(
+        "  sinks: [stdout]\n",
+        &format!("services:\n{}", service("/api/v1/plans", free_port(), "")),
+    );
+
+    assert_eq!(gateway.get("/api/v1/plans").status, 503);
+
+    let started: Value =
+        serde_json::from_str(&gateway.wait_for_log("stdout.log", "listening")).unwrap();
+    assert_eq!(started["level"], "info");
+    gateway.wait_for_log("stderr.log", "Failed to connect to downstream service");
+    assert!(!log_contents(&gateway, "stdout.log").contains("Failed to connect"));
+    assert!(!gateway.dir.join("out.log").exists());
+}
+
+#[test]
+fn the_memory_sink_keeps_the_latest_entries() {
+    let backend = Backend::ok();
+    let admin_port = free_port();
+    let gateway = Gateway::start_with_logger(
+        "  sinks: [file, memory]\n  memory_capacity: 2\n",
+        &format!(
+            "admin_url: \"127.0.0.1:{}\"\naccess_log:\n  enabled: false\nservices:\n{}",
+            admin_port,
+            service("/api/v1/plans", backend.port, "")
+        ),
+    );
+
+    gateway.get("/api/v1/plans");
+    gateway.wait_for_log("out.log", "Connection closed");
+
+    let response = admin_request(
+        admin_port,
+        "GET /logger/entries HTTP/1.1\r\nhost: admin\r\nconnection: close\r\n\r\n",
+    );
+    let body = &response[response.find("\r\n\r\n").unwrap() + 4..];
+    let entries: Vec<Value> = serde_json::from_str(body).unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[1]["message"], "Connection closed");
+}
+
+#[test]
+fn a_gateway_that_cannot_listen_logs_why_and_fails() {
+    let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+    let (output, err_log) = run_until_exit(
+        &taken.local_addr().unwrap().to_string(),
+        "cors:\n  allowed_origins: []\nservices: []",
+    );
+
+    assert!(!output.status.success());
+    assert!(err_log.contains("Gateway stopped"), "{}", err_log);
+}
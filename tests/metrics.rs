@@ -0,0 +1,50 @@
+mod common;
+
+use common::{admin_request, free_port, service, Backend, Gateway};
+
+fn metrics(admin_port: u16) -> String {
+    let response = admin_request(
+        admin_port,
+        "GET /metrics HTTP/1.1\r\nhost: admin\r\nconnection: close\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 200"));
+    assert!(response.contains("content-type: text/plain; version=0.0.4"));
+    response[response.find("\r\n\r\n").unwrap() + 4..].to_string()
+}
+
+#[test]
+fn requests_are_counted_by_route_template() {
+    let backend = Backend::ok();
+    let admin_port = free_port();
+    let gateway = Gateway::start(&format!(
+        "admin_url: \"127.0.0.1:{}\"\nservices:\n{}{}",
+        admin_port,
+        service(
+            "/api/v2/patients",
+            backend.port,
+            "    route: \"/api/v2/patients/{patient_id}\"\n"
+        ),
+        service("/api/v1/down", free_port(), ""),
+    ));
+
+    assert_eq!(gateway.get("/api/v2/patients/17").status, 200);
+    assert_eq!(gateway.get("/api/v2/patients/42").status, 200);
+    assert_eq!(gateway.get("/api/v1/down").status, 503);
+    assert_eq!(gateway.get("/nowhere").status, 404);
+
+    let metrics = metrics(admin_port);
+    for line in [
+        "hypergate_requests_total{service=\"/api/v2/patients\",route=\"/api/v2/patients/{patient_id}\",method=\"GET\",status=\"2xx\"} 2",
+        "hypergate_requests_total{service=\"/api/v1/down\",route=\"/api/v1/down\",method=\"GET\",status=\"5xx\"} 1",
+        "hypergate_requests_total{service=\"unmatched\",route=\"unmatched\",method=\"GET\",status=\"4xx\"} 1",
+        "hypergate_request_duration_seconds_count{service=\"/api/v2/patients\",route=\"/api/v2/patients/{patient_id}\",method=\"GET\",status=\"2xx\"} 2",
+        "hypergate_upstream_errors_total{service=\"/api/v1/down\",kind=\"connect\"} 1",
+        "hypergate_auth_duration_seconds_bucket{outcome=\"allowed\",le=\"+Inf\"} 3",
+        "hypergate_in_flight_requests 0",
+        "hypergate_log_entries_dropped_total{stage=\"writer\"} 0",
+        "# TYPE hypergate_open_connections gauge",
+    ] {
+        assert!(metrics.lines().any(|metric| metric == line), "{} missing from\n{}", line, metrics);
+    }
+    assert!(!metrics.contains("/api/v2/patients/17"));
+}
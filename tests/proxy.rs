@@ -0,0 +1,345 @@
+mod common;
+
+use common::{read_head, service, Backend, Gateway};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+
+fn websocket_backend() -> Backend {
+    Backend::start_with(|mut stream: TcpStream| {
+        stream
+            .write_all(
+                b"HTTP/1.1 101 Switching Protocols\r\nupgrade: websocket\r\nconnection: upgrade\r\n\r\n",
+            )
+            .unwrap();
+        let mut buffer = [0u8; 1024];
+        while let Ok(read) = stream.read(&mut buffer) {
+            if read == 0 || stream.write_all(&buffer[..read]).is_err() {
+                break;
+            }
+        }
+    })
+}
+
+const UPGRADE_REQUEST: &str = "GET /api/v1/alert/live HTTP/1.1\r\n\
+     host: gateway.test\r\n\
+     connection: Upgrade\r\n\
+     upgrade: websocket\r\n\
+     sec-websocket-version: 13\r\n\
+     sec-websocket-key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+
+fn gateway_for(backend: &Backend, extra: &str) -> Gateway {
+    Gateway::start(&format!(
+        "services:\n{}",
+        service("/api/v1/plans", backend.port, extra)
+    ))
+}
+
+#[test]
+fn forwards_path_without_trailing_question_mark() {
+    let backend = Backend::ok();
+    let gateway = gateway_for(&backend, "");
+
+    let response = gateway.get("/api/v1/plans/7");
+
+    assert_eq!(response.status, 200);
+    assert_eq!(backend.next_request().target(), "/api/v1/plans/7");
+}
+
+#[test]
+fn forwards_query_string_unchanged() {
+    let backend = Backend::ok();
+    let gateway = gateway_for(&backend, "");
+
+    gateway.get("/api/v1/plans?name=basic%20plan&page=2");
+
+    assert_eq!(
+        backend.next_request().target(),
+        "/api/v1/plans?name=basic%20plan&page=2"
+    );
+}
+
+#[test]
+fn strips_and_adds_path_prefixes() {
+    let backend = Backend::ok();
+    let gateway = gateway_for(
+        &backend,
+        "    rewrite:\n      strip_prefix: \"/api/v1/\"\n      add_prefix: \"/internal/v2\"\n",
+    );
+
+    gateway.get("/api/v1/plans/7?page=2");
+    gateway.get("/api/v1/plans");
+
+    assert_eq!(
+        backend.next_request().target(),
+        "/internal/v2/plans/7?page=2"
+    );
+    assert_eq!(backend.next_request().target(), "/internal/v2/plans");
+}
+
+#[test]
+fn rewrites_paths_with_regex_capture_groups() {
+    let backend = Backend::ok();
+    let gateway = gateway_for(
+        &backend,
+        "    rewrite:\n      regex: \"^/api/v1/plans/([0-9]+)/items$\"\n      replacement: \"/plans/$1/line-items\"\n",
+    );
+
+    gateway.get("/api/v1/plans/42/items?page=2");
+    gateway.get("/api/v1/plans/basic/items");
+
+    assert_eq!(
+        backend.next_request().target(),
+        "/plans/42/line-items?page=2"
+    );
+    // Paths the regex does not match are left alone
+    assert_eq!(backend.next_request().target(), "/api/v1/plans/basic/items");
+}
+
+#[test]
+fn renders_route_params_into_the_path() {
+    let backend = Backend::ok();
+    let gateway = gateway_for(
+        &backend,
+        "    route: \"/api/v1/plans/{plan_id}\"\n    rewrite:\n      template: \"/internal/plans/{plan_id}/detail\"\n",
+    );
+
+    gateway.get("/api/v1/plans/9");
+    gateway.get("/api/v1/plans/9/prices?currency=eur");
+
+    assert_eq!(backend.next_request().target(), "/internal/plans/9/detail");
+    assert_eq!(
+        backend.next_request().target(),
+        "/internal/plans/9/detail/prices?currency=eur"
+    );
+}
+
+#[test]
+fn adds_removes_and_renames_query_params() {
+    let backend = Backend::ok();
+    let gateway = gateway_for(
+        &backend,
+        "    rewrite:\n      query:\n        add: { source: \"gateway\" }\n        remove: [\"debug\"]\n        rename: { q: \"search\" }\n",
+    );
+
+    gateway.get("/api/v1/plans?q=flu%20shot&debug=1&source=client");
+    gateway.get("/api/v1/plans");
+
+    assert_eq!(
+        backend.next_request().target(),
+        "/api/v1/plans?search=flu+shot&source=gateway"
+    );
+    assert_eq!(
+        backend.next_request().target(),
+        "/api/v1/plans?source=gateway"
+    );
+}
+
+#[test]
+fn strips_hop_by_hop_request_headers() {
+    let backend = Backend::ok();
+    let gateway = gateway_for(&backend, "");
+
+    gateway.send(
+        "GET /api/v1/plans HTTP/1.1\r\n\
+         host: gateway.test\r\n\
+         connection: close, x-session-hint\r\n\
+         x-session-hint: secret\r\n\
+         keep-alive: timeout=5\r\n\
+         te: gzip\r\n\
+         upgrade: h2c\r\n\
+         proxy-authorization: Basic Zm9vOmJhcg==\r\n\
+         x-kept: yes\r\n\r\n",
+    );
+
+    let request = backend.next_request();
+    for name in [
+        "x-session-hint",
+        "keep-alive",
+        "te",
+        "upgrade",
+        "proxy-authorization",
+    ] {
+        assert_eq!(request.header(name), None, "{} was forwarded", name);
+    }
+    assert_ne!(request.header("connection"), Some("close, x-session-hint"));
+    assert_eq!(request.header("x-kept"), Some("yes"));
+}
+
+#[test]
+fn strips_hop_by_hop_response_headers() {
+    let backend = Backend::start(
+        "HTTP/1.1 200 OK\r\n\
+         content-length: 2\r\n\
+         connection: close, x-backend-hint\r\n\
+         x-backend-hint: internal\r\n\
+         keep-alive: timeout=5\r\n\
+         proxy-authenticate: Basic\r\n\
+         x-kept: yes\r\n\r\n{}",
+    );
+    let gateway = gateway_for(&backend, "");
+
+    let response = gateway.get("/api/v1/plans");
+
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("x-backend-hint"), None);
+    assert_eq!(response.header("keep-alive"), None);
+    assert_eq!(response.header("proxy-authenticate"), None);
+    assert_eq!(response.header("x-kept"), Some("yes"));
+    assert_eq!(response.body, b"{}");
+}
+
+#[test]
+fn rewrites_host_to_the_target_by_default() {
+    let backend = Backend::ok();
+    let gateway = gateway_for(&backend, "");
+
+    gateway.get("/api/v1/plans");
+
+    let expected = format!("127.0.0.1:{}", backend.port);
+    assert_eq!(
+        backend.next_request().header("host"),
+        Some(expected.as_str())
+    );
+}
+
+#[test]
+fn forwarding_headers_carry_the_host_the_client_asked_for() {
+    let backend = Backend::ok();
+    let gateway = gateway_for(&backend, "");
+
+    gateway.get("/api/v1/plans");
+
+    let request = backend.next_request();
+    let port = gateway.port.to_string();
+    assert_eq!(request.header("x-forwarded-host"), Some("gateway.test"));
+    assert_eq!(request.header("x-forwarded-port"), Some(port.as_str()));
+    assert_eq!(
+        request.header("forwarded"),
+        Some("for=127.0.0.1;proto=http;host=\"gateway.test\"")
+    );
+}
+
+#[test]
+fn preserves_host_when_configured() {
+    let backend = Backend::ok();
+    let gateway = gateway_for(&backend, "    host_header: \"preserve\"\n");
+
+    gateway.get("/api/v1/plans");
+
+    assert_eq!(backend.next_request().header("host"), Some("gateway.test"));
+}
+
+#[test]
+fn sets_a_fixed_host_when_configured() {
+    let backend = Backend::ok();
+    let gateway = gateway_for(&backend, "    host_header: \"payments.internal\"\n");
+
+    gateway.get("/api/v1/plans");
+
+    assert_eq!(
+        backend.next_request().header("host"),
+        Some("payments.internal")
+    );
+}
+
+#[test]
+fn appends_via_to_request_and_response() {
+    let backend = Backend::start(
+        "HTTP/1.1 200 OK\r\ncontent-length: 0\r\nvia: 1.1 backend\r\nconnection: close\r\n\r\n",
+    );
+    let gateway = gateway_for(&backend, "");
+
+    let response = gateway.send(
+        "GET /api/v1/plans HTTP/1.1\r\nhost: gateway.test\r\nvia: 1.1 edge\r\nconnection: close\r\n\r\n",
+    );
+
+    assert_eq!(
+        backend.next_request().header("via"),
+        Some("1.1 edge, 1.1 hypergate")
+    );
+    assert_eq!(response.header("via"), Some("1.1 backend, 1.1 hypergate"));
+}
+
+#[test]
+fn invalid_rewritten_path_returns_bad_request() {
+    let backend = Backend::ok();
+    let gateway = gateway_for(
+        &backend,
+        "    rewrite:\n      regex: \"^/api/v1/plans\"\n      replacement: \"/plans with spaces\"\n",
+    );
+
+    let response = gateway.get("/api/v1/plans");
+
+    assert_eq!(response.status, 400);
+}
+
+#[test]
+fn misconfigured_target_returns_bad_gateway() {
+    let gateway = Gateway::start(
+        "services:\n  - path: \"/api/v1/plans\"\n    target_service: \"http://bad host\"\n    target_port: \"80\"\n",
+    );
+
+    let response = gateway.get("/api/v1/plans");
+
+    assert_eq!(response.status, 502);
+}
+
+#[test]
+fn unreachable_backend_returns_service_unavailable() {
+    let port = common::free_port();
+    let gateway = Gateway::start(&format!(
+        "services:\n{}",
+        service("/api/v1/plans", port, "")
+    ));
+
+    let response = gateway.get("/api/v1/plans");
+
+    assert_eq!(response.status, 503);
+}
+
+#[test]
+fn tunnels_upgraded_connections_when_enabled() {
+    let backend = websocket_backend();
+    let gateway = Gateway::start(&format!(
+        "services:\n{}",
+        service(
+            "/api/v1/alert",
+            backend.port,
+            "    upgrade:\n      idle_timeout_secs: 5\n"
+        )
+    ));
+
+    let mut stream = gateway.connect();
+    stream.write_all(UPGRADE_REQUEST.as_bytes()).unwrap();
+    let response = read_head(&mut stream);
+
+    assert_eq!(response.status, 101);
+    assert_eq!(response.header("upgrade"), Some("websocket"));
+    let request = backend.next_request();
+    assert_eq!(request.header("upgrade"), Some("websocket"));
+    assert_eq!(
+        request.header("sec-websocket-key"),
+        Some("dGhlIHNhbXBsZSBub25jZQ==")
+    );
+
+    stream.write_all(b"ping").unwrap();
+    let mut echoed = [0u8; 4];
+    stream.read_exact(&mut echoed).unwrap();
+    assert_eq!(&echoed, b"ping");
+}
+
+#[test]
+fn does_not_upgrade_routes_without_upgrade_enabled() {
+    let backend = Backend::ok();
+    let gateway = Gateway::start(&format!(
+        "services:\n{}",
+        service("/api/v1/alert", backend.port, "")
+    ));
+
+    let mut stream = gateway.connect();
+    stream.write_all(UPGRADE_REQUEST.as_bytes()).unwrap();
+    let response = read_head(&mut stream);
+
+    assert_eq!(response.status, 200);
+    assert_eq!(backend.next_request().header("upgrade"), None);
+}
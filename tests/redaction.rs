@@ -0,0 +1,133 @@
+mod common;
+
+use common::{admin_request, free_port, run_until_exit, service, Backend, Gateway};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+const RULES: &str = "  redaction:
+    salt: \"pepper\"
+    rules:
+      - { query: patient_id, action: hash }
+      - { query: token, action: drop }
+      - { header: user-agent }
+      - { header: referer, action: drop }
+      - { pattern: \"[\\\\w.]+@[\\\\w.]+\" }
+";
+
+const REQUEST: &str = "GET /unknown/jane.doe@example.com?patient_id=P-77&token=abc&page=2 HTTP/1.1\r\nhost: gateway.test\r\nuser-agent: curl/8.5\r\nreferer: https://app.test/\r\nconnection: close\r\n\r\n";
+
+fn hashed(value: &str) -> String {
+    let digest = Sha256::new()
+        .chain_update("pepper")
+        .chain_update(value)
+        .finalize();
+    let hex: String = digest[..8].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("sha256:{}", hex)
+}
+
+/// Checks the entries about the request were redacted, and are there at all.
+fn assert_redacted(log: &str) {
+    for secret in [
+        "P-77",
+        "token",
+        "jane.doe@example.com",
+        "curl/8.5",
+        "app.test",
+    ] {
+        assert!(!log.contains(secret), "{} was logged: {}", secret, log);
+    }
+    let query = format!("patient_id={}&page=2", hashed("P-77"));
+    assert!(log.contains(&format!("\"params\":\"{}\"", query)));
+    assert!(log.contains("\"message\":\"Path not found: /unknown/***\""));
+    assert!(log.contains(&format!("\"query\":\"{}\"", query)));
+    assert!(log.contains("\"user_agent\":\"***\""));
+}
+
+#[test]
+fn entries_are_redacted_in_every_sink() {
+    let backend = Backend::ok();
+    let admin_port = free_port();
+    let gateway = Gateway::start_with_logger(
+        &format!("  sinks: [file, stdout, memory]\n{}", RULES),
+        &format!(
+            "admin_url: \"127.0.0.1:{}\"\nservices:\n{}",
+            admin_port,
+            service("/api/v1/plans", backend.port, "")
+        ),
+    );
+
+    assert_eq!(gateway.send(REQUEST).status, 404);
+
+    gateway.wait_for_log("out.log", "\"message\":\"access\"");
+    gateway.wait_for_log("stdout.log", "\"message\":\"access\"");
+    assert_redacted(&std::fs::read_to_string(gateway.dir.join("out.log")).unwrap());
+    assert_redacted(&std::fs::read_to_string(gateway.dir.join("stdout.log")).unwrap());
+
+    let response = admin_request(
+        admin_port,
+        "GET /logger/entries HTTP/1.1\r\nhost: admin\r\nconnection: close\r\n\r\n",
+    );
+    assert_redacted(&response[response.find("\r\n\r\n").unwrap() + 4..]);
+}
+
+#[test]
+fn access_lines_are_redacted_before_they_are_rendered() {
+    let backend = Backend::ok();
+    let gateway = Gateway::start_with_logger(
+        &format!("  access_file: \"{{dir}}/access.log\"\n{}", RULES),
+        &format!(
+            "access_log:\n  format: combined\nservices:\n{}",
+            service("/api/v1/plans", backend.port, "")
+        ),
+    );
+
+    gateway.send(REQUEST);
+
+    let line = gateway.wait_for_log("access.log", "GET");
+    assert!(line.contains(&format!(
+        "\"GET /unknown/***?patient_id={}&page=2 HTTP/1.1\" 404 ",
+        hashed("P-77")
+    )));
+    assert!(line.ends_with(" \"-\" \"***\""));
+}
+
+#[test]
+fn json_fields_are_redacted_at_any_depth() {
+    let backend = Backend::ok();
+    let gateway = Gateway::launch(
+        "{\"userId\":\"u-42\",\"patient\":{\"dni\":\"12345678Z\",\"name\":\"Jane\"},\"cards\":[{\"number\":\"4111\"},{\"number\":\"5500\"}]}",
+        "  level: debug
+  redaction:
+    rules:
+      - { json_path: patient.dni, action: drop }
+      - { json_path: cards.number }
+",
+        &format!("services:\n{}", service("/api/v1/plans", backend.port, "")),
+    );
+
+    assert_eq!(gateway.get("/api/v1/plans").status, 200);
+
+    let line = gateway.wait_for_log("debug.log", "Caller authorized");
+    let entry: Value = serde_json::from_str(&line).unwrap();
+    let identity: Value =
+        serde_json::from_str(entry["params"]["identity"].as_str().unwrap()).unwrap();
+    assert_eq!(
+        identity,
+        json!({
+            "userId": "u-42",
+            "patient": {"name": "Jane"},
+            "cards": [{"number": "***"}, {"number": "***"}],
+        })
+    );
+}
+
+#[test]
+fn misspelt_rule_targets_are_refused_at_startup() {
+    let (output, _) = run_until_exit(
+        &format!("127.0.0.1:{}", free_port()),
+        "  redaction:\n    rules:\n      - { json-path: patient.dni }\ncors:\n  allowed_origins: []\nservices: []",
+    );
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown field `json-path`"));
+}
@@ -0,0 +1,88 @@
+mod common;
+
+use common::{read_head, service, Backend, Gateway};
+use std::io::{Read, Write};
+
+fn gateway_for(backend: &Backend, request_id: &str) -> Gateway {
+    Gateway::start(&format!(
+        "request_id:\n{}services:\n{}",
+        request_id,
+        service("/api/v1/plans", backend.port, "")
+    ))
+}
+
+fn get_with_id(gateway: &Gateway, request_id: &str) -> String {
+    let response = gateway.send(&format!(
+        "GET /api/v1/plans HTTP/1.1\r\nhost: gateway.test\r\nx-request-id: {}\r\nconnection: close\r\n\r\n",
+        request_id
+    ));
+    response.header("x-request-id").unwrap().to_string()
+}
+
+#[test]
+fn every_request_on_a_connection_gets_its_own_id() {
+    let backend = Backend::ok();
+    let gateway = gateway_for(&backend, "  format: uuidv4\n");
+    let mut stream = gateway.connect();
+
+    let mut ids = Vec::new();
+    for _ in 0..2 {
+        stream
+            .write_all(b"GET /api/v1/plans HTTP/1.1\r\nhost: gateway.test\r\n\r\n")
+            .unwrap();
+        let response = read_head(&mut stream);
+        let mut body = [0u8; 2];
+        stream.read_exact(&mut body).unwrap();
+
+        let id = response.header("x-request-id").unwrap().to_string();
+        assert_eq!(
+            backend.next_request().header("x-request-id"),
+            Some(id.as_str())
+        );
+        assert_eq!((id.len(), &id[14..15]), (36, "4"));
+        ids.push(id);
+    }
+    assert_ne!(ids[0], ids[1]);
+}
+
+#[test]
+fn inbound_ids_are_kept_from_trusted_sources_only() {
+    let backend = Backend::ok();
+    let trusting = gateway_for(
+        &backend,
+        "  accept_inbound: true\n  trusted_sources: [\"127.0.0.1\"]\n",
+    );
+
+    assert_eq!(get_with_id(&trusting, "lb-7f3a.2"), "lb-7f3a.2");
+    assert_eq!(
+        backend.next_request().header("x-request-id"),
+        Some("lb-7f3a.2")
+    );
+    // Not a valid id
+    assert_ne!(get_with_id(&trusting, "lb/7f3a"), "lb/7f3a");
+    backend.next_request();
+
+    let untrusting = gateway_for(
+        &backend,
+        "  accept_inbound: true\n  trusted_sources: [\"10.0.0.0/8\"]\n",
+    );
+    assert_ne!(get_with_id(&untrusting, "lb-7f3a.2"), "lb-7f3a.2");
+    let ignoring = gateway_for(&backend, "  trusted_sources: [\"127.0.0.1\"]\n");
+    assert_ne!(get_with_id(&ignoring, "lb-7f3a.2"), "lb-7f3a.2");
+}
+
+#[test]
+fn ids_can_be_uuidv7_or_ulid() {
+    let backend = Backend::ok();
+
+    let gateway = gateway_for(&backend, "  format: uuidv7\n");
+    let id = get_with_id(&gateway, "");
+    assert_eq!((id.len(), &id[14..15]), (36, "7"));
+
+    let gateway = gateway_for(&backend, "  format: ulid\n");
+    let id = get_with_id(&gateway, "");
+    assert_eq!(id.len(), 26);
+    assert!(id
+        .chars()
+        .all(|c| c.is_ascii_digit() || c.is_ascii_uppercase()));
+}
@@ -0,0 +1,90 @@
+mod common;
+
+use common::{service, Backend, Gateway};
+
+fn backend() -> Backend {
+    Backend::start(
+        "HTTP/1.1 200 OK\r\nserver: express/4.18\r\nx-powered-by: Express\r\ncontent-length: 2\r\nconnection: close\r\n\r\n{}",
+    )
+}
+
+fn gateway_for(backend: &Backend, extra: &str) -> Gateway {
+    Gateway::start(&format!(
+        "{}services:\n{}{}",
+        extra,
+        service("/api/v1/plans", backend.port, ""),
+        service(
+            "/api/v1/reports",
+            backend.port,
+            "    security_headers:\n      frame_options: \"SAMEORIGIN\"\n      content_security_policy: null\n      strip_headers: []\n"
+        ),
+    ))
+}
+
+#[test]
+fn responses_get_the_default_policy_and_lose_server_headers() {
+    let backend = backend();
+    let gateway = gateway_for(&backend, "");
+
+    let response = gateway.get("/api/v1/plans");
+
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("x-content-type-options"), Some("nosniff"));
+    assert_eq!(response.header("x-frame-options"), Some("DENY"));
+    assert_eq!(response.header("referrer-policy"), Some("no-referrer"));
+    assert_eq!(
+        response.header("permissions-policy"),
+        Some("camera=(), microphone=(), geolocation=()")
+    );
+    assert_eq!(
+        response.header("content-security-policy"),
+        Some("default-src 'none'; frame-ancestors 'none'")
+    );
+    assert_eq!(response.header("server"), None);
+    assert_eq!(response.header("x-powered-by"), None);
+    // Only sent when the gateway is served over HTTPS
+    assert_eq!(response.header("strict-transport-security"), None);
+}
+
+#[test]
+fn hsts_is_sent_over_https_only() {
+    let backend = backend();
+    let gateway = gateway_for(&backend, "is_https: true\n");
+
+    let response = gateway.get("/api/v1/plans");
+
+    assert_eq!(
+        response.header("strict-transport-security"),
+        Some("max-age=31536000; includeSubDomains")
+    );
+}
+
+#[test]
+fn docs_get_the_relaxed_profile() {
+    let backend = backend();
+    let gateway = gateway_for(&backend, "");
+
+    let response = gateway.get("/docs");
+
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("x-frame-options"), Some("SAMEORIGIN"));
+    assert!(response
+        .header("content-security-policy")
+        .unwrap()
+        .contains("script-src 'self' 'unsafe-inline' https://cdnjs.cloudflare.com"));
+    assert_eq!(response.header("x-content-type-options"), Some("nosniff"));
+}
+
+#[test]
+fn routes_can_override_the_policy() {
+    let backend = backend();
+    let gateway = gateway_for(&backend, "");
+
+    let response = gateway.get("/api/v1/reports");
+
+    assert_eq!(response.header("x-frame-options"), Some("SAMEORIGIN"));
+    assert_eq!(response.header("content-security-policy"), None);
+    assert_eq!(response.header("x-content-type-options"), Some("nosniff"));
+    assert_eq!(response.header("server"), Some("express/4.18"));
+    assert_eq!(response.header("x-powered-by"), Some("Express"));
+}
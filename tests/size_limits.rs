@@ -0,0 +1,131 @@
+mod common;
+
+use common::{service, Backend, Gateway};
+use http_body_util::Empty;
+use hyper::body::Bytes;
+use hyper::Request;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+
+fn gateway_for(backend: &Backend, limits: &str) -> Gateway {
+    Gateway::start(&format!(
+        "limits:\n{}services:\n{}{}",
+        limits,
+        service("/api/v1/plans", backend.port, ""),
+        service("/api/v1/uploads", backend.port, "    max_body_bytes: 64\n"),
+    ))
+}
+
+fn post(gateway: &Gateway, target: &str, body: &str) -> u16 {
+    gateway
+        .send(&format!(
+            "POST {} HTTP/1.1\r\nhost: gateway.test\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+            target,
+            body.len(),
+            body
+        ))
+        .status
+}
+
+#[test]
+fn declared_bodies_over_the_limit_are_rejected_before_auth() {
+    let backend = Backend::ok();
+    let gateway = gateway_for(&backend, "  max_body_bytes: 16\n");
+
+    assert_eq!(post(&gateway, "/api/v1/plans", &"a".repeat(17)), 413);
+    assert_eq!(gateway.auth.received(), 0);
+    assert_eq!(backend.received(), 0);
+
+    assert_eq!(post(&gateway, "/api/v1/plans", &"a".repeat(16)), 200);
+    // Routes can raise the limit
+    assert_eq!(post(&gateway, "/api/v1/uploads", &"a".repeat(40)), 200);
+    assert_eq!(post(&gateway, "/api/v1/uploads", &"a".repeat(65)), 413);
+    assert_eq!(gateway.auth.received(), 2);
+}
+
+#[test]
+fn streamed_bodies_are_cut_off_at_the_limit() {
+    let backend = Backend::ok();
+    let gateway = gateway_for(&backend, "  max_body_bytes: 16\n");
+
+    let response = gateway.send(
+        "POST /api/v1/plans HTTP/1.1\r\nhost: gateway.test\r\ntransfer-encoding: chunked\r\nconnection: close\r\n\r\n\
+         a\r\n0123456789\r\na\r\n0123456789\r\n0\r\n\r\n",
+    );
+
+    assert_eq!(response.status, 413);
+}
+
+#[test]
+fn long_uris_and_large_headers_are_rejected_before_auth() {
+    let backend = Backend::ok();
+    let gateway = gateway_for(
+        &backend,
+        "  max_uri_length: 64\n  max_header_bytes: 256\n  max_headers: 8\n",
+    );
+
+    let long_uri = format!("/api/v1/plans?q={}", "a".repeat(64));
+    assert_eq!(gateway.get(&long_uri).status, 414);
+
+    let large_header = gateway.send(&format!(
+        "GET /api/v1/plans HTTP/1.1\r\nhost: gateway.test\r\nx-large: {}\r\nconnection: close\r\n\r\n",
+        "a".repeat(256)
+    ));
+    assert_eq!(large_header.status, 431);
+
+    let many_headers: String = (0..8).map(|i| format!("x-extra-{}: 1\r\n", i)).collect();
+    let too_many = gateway.send(&format!(
+        "GET /api/v1/plans HTTP/1.1\r\nhost: gateway.test\r\n{}connection: close\r\n\r\n",
+        many_headers
+    ));
+    assert_eq!(too_many.status, 431);
+
+    assert_eq!(gateway.auth.received(), 0);
+    assert_eq!(backend.received(), 0);
+    assert_eq!(gateway.get("/api/v1/plans").status, 200);
+}
+
+/// Checks hyper refused the last head itself, so the request never reached the gateway's
+/// own size check. A later request's entry shows the earlier ones were written.
+fn assert_refused_by_hyper(gateway: &Gateway) {
+    assert_eq!(gateway.get("/api/v1/plans").status, 200);
+    gateway.wait_for_log("out.log", "Connection closed");
+    let log = std::fs::read_to_string(gateway.dir.join("out.log")).unwrap();
+    assert!(!log.contains("Request rejected by size limits"), "{}", log);
+}
+
+#[test]
+fn oversized_heads_are_refused_while_they_are_read() {
+    let backend = Backend::ok();
+    let gateway = gateway_for(&backend, "  max_header_bytes: 256\n");
+
+    let response = gateway.send(&format!(
+        "GET /api/v1/plans HTTP/1.1\r\nhost: gateway.test\r\nx-large: {}\r\nconnection: close\r\n\r\n",
+        "a".repeat(256 * 1024)
+    ));
+
+    assert_eq!(response.status, 431);
+    assert_eq!(gateway.auth.received(), 0);
+    assert_eq!(backend.received(), 0);
+    assert_refused_by_hyper(&gateway);
+}
+
+#[tokio::test]
+async fn http2_header_lists_over_the_limit_are_refused() {
+    let backend = Backend::ok();
+    let gateway = gateway_for(&backend, "  max_header_bytes: 256\n");
+    let client = Client::builder(TokioExecutor::new())
+        .http2_only(true)
+        .build_http::<Empty<Bytes>>();
+
+    let request = Request::get(format!("http://127.0.0.1:{}/api/v1/plans", gateway.port))
+        .header("x-large", "a".repeat(12 * 1024))
+        .body(Empty::new())
+        .unwrap();
+    let response = client.request(request).await.unwrap();
+
+    assert_eq!(response.status(), 431);
+    assert_eq!(gateway.auth.received(), 0);
+    assert_eq!(backend.received(), 0);
+    assert_refused_by_hyper(&gateway);
+}
@@ -0,0 +1,100 @@
+mod common;
+
+use common::{read_head, service, Backend, Gateway};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Sends one event right away, then stalls for `stall` before sending a second one.
+fn event_stream_backend(stall: Duration) -> Backend {
+    Backend::start_with(move |mut stream: TcpStream| {
+        let _ = stream.write_all(
+            b"HTTP/1.1 200 OK\r\n\
+              content-type: text/event-stream\r\n\
+              transfer-encoding: chunked\r\n\r\n\
+              d\r\ndata: first\n\n\r\n",
+        );
+        thread::sleep(stall);
+        let _ = stream.write_all(b"e\r\ndata: second\n\n\r\n0\r\n\r\n");
+    })
+}
+
+fn read_until(stream: &mut TcpStream, needle: &str) -> String {
+    let mut received = Vec::new();
+    let mut buffer = [0u8; 256];
+    while !String::from_utf8_lossy(&received).contains(needle) {
+        match stream.read(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(read) => received.extend_from_slice(&buffer[..read]),
+        }
+    }
+    String::from_utf8_lossy(&received).to_string()
+}
+
+const SSE_REQUEST: &str = "GET /api/v1/alert/events HTTP/1.1\r\n\
+     host: gateway.test\r\n\
+     accept: text/event-stream\r\n\
+     connection: close\r\n\r\n";
+
+#[test]
+fn passes_events_through_without_buffering() {
+    let backend = event_stream_backend(Duration::from_secs(3));
+    let gateway = Gateway::start(&format!(
+        "services:\n{}",
+        service("/api/v1/alert", backend.port, "")
+    ));
+
+    let started = Instant::now();
+    let mut stream = gateway.connect();
+    stream.write_all(SSE_REQUEST.as_bytes()).unwrap();
+    let response = read_head(&mut stream);
+
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("x-accel-buffering"), Some("no"));
+    assert!(read_until(&mut stream, "data: first").contains("data: first"));
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+#[test]
+fn ends_streams_that_stay_idle_too_long() {
+    let backend = event_stream_backend(Duration::from_secs(5));
+    let gateway = Gateway::start(&format!(
+        "services:\n{}",
+        service(
+            "/api/v1/alert",
+            backend.port,
+            "    streaming:\n      idle_timeout_secs: 1\n"
+        )
+    ));
+
+    let started = Instant::now();
+    let mut stream = gateway.connect();
+    stream.write_all(SSE_REQUEST.as_bytes()).unwrap();
+    read_head(&mut stream);
+    let received = read_until(&mut stream, "data: second");
+
+    assert!(received.contains("data: first"));
+    assert!(!received.contains("data: second"));
+    assert!(started.elapsed() < Duration::from_secs(4));
+}
+
+#[test]
+fn does_not_time_out_slow_downloads_outside_streams() {
+    let backend = Backend::start_with(|mut stream: TcpStream| {
+        let _ = stream.write_all(
+            b"HTTP/1.1 200 OK\r\ncontent-type: application/pdf\r\ncontent-length: 10\r\n\r\nfirst",
+        );
+        thread::sleep(Duration::from_secs(2));
+        let _ = stream.write_all(b"-last");
+    });
+    let gateway = Gateway::start(&format!(
+        "streaming:\n  idle_timeout_secs: 1\nservices:\n{}",
+        service("/api/v1/reports", backend.port, "")
+    ));
+
+    let response = gateway.get("/api/v1/reports/7.pdf");
+
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, b"first-last");
+}
@@ -0,0 +1,164 @@
+mod common;
+
+use common::{service, Backend, Gateway};
+use serde_json::Value;
+use std::time::{Duration, Instant};
+
+const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+const PARENT_ID: &str = "00f067aa0ba902b7";
+
+fn collector() -> Backend {
+    Backend::start("HTTP/1.1 200 OK\r\ncontent-length: 2\r\nconnection: close\r\n\r\n{}")
+}
+
+fn gateway_for(backend: &Backend, collector: &Backend, tracing: &str) -> Gateway {
+    Gateway::start(&format!(
+        "tracing:\n  enabled: true\n  endpoint: \"http://127.0.0.1:{}/v1/traces\"\n  export_interval_ms: 50\n{}services:\n{}",
+        collector.port,
+        tracing,
+        service("/api/v1/plans", backend.port, "")
+    ))
+}
+
+fn get_with_traceparent(gateway: &Gateway, traceparent: &str) {
+    let response = gateway.send(&format!(
+        "GET /api/v1/plans HTTP/1.1\r\nhost: gateway.test\r\ntraceparent: {}\r\ntracestate: vendor=abc\r\nconnection: close\r\n\r\n",
+        traceparent
+    ));
+    assert_eq!(response.status, 200);
+}
+
+/// The spans exported until `count` of them have arrived, by name.
+fn exported_spans(collector: &Backend, count: usize) -> Vec<Value> {
+    let mut spans = Vec::new();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while spans.len() < count {
+        assert!(Instant::now() < deadline, "spans were not exported");
+        let request = collector.next_request();
+        assert_eq!(request.target(), "/v1/traces");
+        let body: Value = serde_json::from_slice(&request.body).unwrap();
+        for resource in body["resourceSpans"].as_array().unwrap() {
+            assert_eq!(
+                resource["resource"]["attributes"][0]["value"]["stringValue"],
+                "hypergate"
+            );
+            for scope in resource["scopeSpans"].as_array().unwrap() {
+                spans.extend(scope["spans"].as_array().unwrap().iter().cloned());
+            }
+        }
+    }
+    spans.sort_by_key(|span| span["name"].as_str().unwrap().to_string());
+    spans
+}
+
+#[test]
+fn requests_continue_the_inbound_trace() {
+    let backend = Backend::ok();
+    let collector = collector();
+    let gateway = gateway_for(&backend, &collector, "");
+
+    get_with_traceparent(&gateway, &format!("00-{}-{}-01", TRACE_ID, PARENT_ID));
+
+    let upstream = backend.next_request();
+    let upstream_parent = upstream.header("traceparent").unwrap().to_string();
+    assert!(upstream_parent.starts_with(&format!("00-{}-", TRACE_ID)));
+    assert!(upstream_parent.ends_with("-01"));
+    assert_eq!(upstream.header("tracestate"), Some("vendor=abc"));
+    let auth_parent = gateway.auth.next_request();
+    let auth_parent = auth_parent.header("traceparent").unwrap();
+    assert!(auth_parent.starts_with(&format!("00-{}-", TRACE_ID)));
+    assert_ne!(auth_parent, upstream_parent);
+
+    let spans = exported_spans(&collector, 3);
+    let names: Vec<_> = spans
+        .iter()
+        .map(|span| span["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, ["GET", "GET /api/v1/plans", "auth"]);
+    let (upstream_span, server_span, auth_span) = (&spans[0], &spans[1], &spans[2]);
+    for span in &spans {
+        assert_eq!(span["traceId"], TRACE_ID);
+    }
+    assert_eq!(server_span["parentSpanId"], PARENT_ID);
+    assert_eq!(server_span["kind"], 2);
+    assert_eq!(upstream_span["parentSpanId"], server_span["spanId"]);
+    assert_eq!(auth_span["parentSpanId"], server_span["spanId"]);
+    assert_eq!(upstream_span["kind"], 3);
+    // The upstream span is the parent the backend sees
+    assert_eq!(
+        upstream_parent,
+        format!(
+            "00-{}-{}-01",
+            TRACE_ID,
+            upstream_span["spanId"].as_str().unwrap()
+        )
+    );
+    assert!(server_span["attributes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|attribute| attribute["key"] == "http.response.status_code"
+            && attribute["value"]["intValue"] == "200"));
+
+    gateway.wait_for_log("out.log", &format!("\"trace_id\":\"{}\"", TRACE_ID));
+}
+
+#[test]
+fn unsampled_traces_are_propagated_but_not_exported() {
+    let backend = Backend::ok();
+    let collector = collector();
+    let gateway = gateway_for(&backend, &collector, "  sample_rate: 0.0\n");
+
+    // Without a sampled parent, the sample rate decides
+    let response = gateway.get("/api/v1/plans");
+    assert_eq!(response.status, 200);
+    let traceparent = backend
+        .next_request()
+        .header("traceparent")
+        .unwrap()
+        .to_string();
+    assert!(traceparent.ends_with("-00"));
+
+    get_with_traceparent(&gateway, &format!("00-{}-{}-00", TRACE_ID, PARENT_ID));
+    let traceparent = backend
+        .next_request()
+        .header("traceparent")
+        .unwrap()
+        .to_string();
+    assert!(traceparent.starts_with(&format!("00-{}-", TRACE_ID)));
+    assert!(traceparent.ends_with("-00"));
+
+    // A sampled parent is followed
+    get_with_traceparent(&gateway, &format!("00-{}-{}-01", TRACE_ID, PARENT_ID));
+    backend.next_request();
+    let spans = exported_spans(&collector, 3);
+    assert_eq!(spans.len(), 3);
+    for span in &spans {
+        assert_eq!(span["traceId"], TRACE_ID);
+    }
+}
+
+#[test]
+fn malformed_traceparents_start_a_new_trace() {
+    let backend = Backend::ok();
+    let collector = collector();
+    let gateway = gateway_for(&backend, &collector, "");
+
+    get_with_traceparent(&gateway, &format!("00-{}-{}-01", "0".repeat(32), PARENT_ID));
+    let traceparent = backend
+        .next_request()
+        .header("traceparent")
+        .unwrap()
+        .to_string();
+    let fields: Vec<_> = traceparent.split('-').collect();
+    assert_eq!(fields.len(), 4);
+    assert_ne!(fields[1], "0".repeat(32));
+    assert_eq!(fields[1].len(), 32);
+
+    let spans = exported_spans(&collector, 3);
+    let server_span = spans.iter().find(|span| span["kind"] == 2).unwrap();
+    assert_eq!(server_span["traceId"], fields[1]);
+    assert!(server_span
+        .get("parentSpanId")
+        .is_none_or(|parent| parent == ""));
+}